@@ -0,0 +1,387 @@
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use std::{
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+        process::CommandExt,
+    },
+    process, thread,
+};
+
+use clap::ArgMatches;
+use custom_error::custom_error;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    encryption::{keyring, EncryptionError},
+    password::{check_password, PasswordError},
+    print_err, print_info, print_solution, print_success,
+    utils::{bytes::Bytes, config::Config, get_config_path, prompt, GenericError},
+};
+
+custom_error! {pub AgentError
+    Io{source: io::Error} = "{source}",
+    Json{source: serde_json::Error} = "{source}",
+    Generic{source: GenericError} = "{source}",
+    Encryption{source: EncryptionError} = "{source}",
+    Password{source: PasswordError} = "{source}",
+    WrongPassword = "Wrong password!",
+    NotStarted = "The mucli agent did not start",
+    ForeignAgent = "The mucli agent socket is held by another user",
+    Unsupported = "Unlocked sessions are only supported on Unix systems"
+}
+
+pub const DEFAULT_SESSION_DURATION: &str = "15m";
+
+const STATUS_REQUEST: &str = "status";
+const LOCK_REQUEST: &str = "lock";
+// printed by the agent once it listens
+const READY: &str = "ready";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Unlocked session held by the agent started with `mucli unlock`
+pub struct Session {
+    // opens the protected encryption keys, None when they are not protected
+    pub password_key: Option<[u8; 32]>,
+    // checked against the password hash by the gates, None when no password is set
+    pub password: Option<String>,
+    pub expires_in: u64,
+}
+
+// sent to the agent when it starts and by the agent in reply to requests
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SessionMessage {
+    password_key: Option<Bytes>,
+    password: Option<String>,
+    seconds: u64,
+}
+
+pub fn unlock_command(sub_matches: &ArgMatches) {
+    let seconds = match sub_matches.get_one::<u64>("for") {
+        Some(seconds) => *seconds,
+        None => return,
+    };
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            print_err!("{}", e);
+            return;
+        }
+    };
+    if config.password.hash.is_none() && config.encryption.wrapped_keys.is_none() {
+        print_info!("Nothing to unlock, no password is set");
+        print_solution!("Use \"password --init\" to set one");
+        return;
+    }
+    if let Some(session) = session() {
+        print_info!(
+            "mucli is already unlocked for {}, it will be unlocked again",
+            format_duration(session.expires_in)
+        );
+    }
+
+    let password = match prompt::password("Enter your password to unlock mucli") {
+        Ok(password) => password,
+        Err(e) => {
+            print_err!("{}", e);
+            return;
+        }
+    };
+
+    match start_session(&config, &password, seconds) {
+        Ok(_) => {
+            print_success!("mucli unlocked for {}", format_duration(seconds));
+            print_solution!("Use \"mucli lock\" to lock it earlier");
+        }
+        Err(AgentError::WrongPassword) => print_err!("Wrong password!"),
+        Err(e) => print_err!("(unlock failed): {}", e),
+    }
+}
+
+pub fn lock_command() {
+    if end_session() {
+        print_success!("mucli locked");
+        return;
+    }
+
+    // left behind when the agent was killed
+    if let Ok(socket_path) = socket_path() {
+        let _ = fs::remove_file(socket_path);
+    }
+    print_info!("mucli was not unlocked");
+}
+
+/// Runs the agent itself, started in the background by `mucli unlock`
+pub fn agent_command(sub_matches: &ArgMatches) {
+    if let Some(socket_path) = sub_matches.get_one::<PathBuf>("SOCKET") {
+        // nobody reads the agent output once it is ready
+        let _ = run_agent(socket_path);
+    }
+}
+
+/// The running unlocked session, None when there is none or the agent does not answer
+pub fn session() -> Option<Session> {
+    let reply = request(STATUS_REQUEST).ok()?;
+    Some(Session {
+        password_key: reply
+            .password_key
+            .and_then(|password_key| password_key.0.try_into().ok()),
+        password: reply.password,
+        expires_in: reply.seconds,
+    })
+}
+
+/// Stops the running unlocked session, false when there was none
+pub fn end_session() -> bool {
+    request(LOCK_REQUEST).is_ok()
+}
+
+/// Parses durations such as "90s", "15m", "1h30m" or "1d" into seconds
+pub fn parse_duration(value: &str) -> Result<u64, String> {
+    let mut seconds: u64 = 0;
+    let mut number = String::new();
+
+    for c in value.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(format!("unknown unit '{}', use s, m, h or d", c)),
+        };
+        let amount: u64 = number
+            .parse()
+            .map_err(|_| format!("missing number before '{}'", c))?;
+        seconds = amount
+            .checked_mul(unit)
+            .and_then(|amount| seconds.checked_add(amount))
+            .ok_or("duration too long")?;
+        number.clear();
+    }
+
+    if !number.is_empty() {
+        return Err("missing unit after the last number, use s, m, h or d".to_string());
+    }
+    if seconds == 0 {
+        return Err("the duration must be longer than 0s".to_string());
+    }
+    Ok(seconds)
+}
+
+fn format_duration(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+    [(hours, "h"), (minutes, "m"), (seconds, "s")]
+        .iter()
+        .filter(|(amount, _)| *amount > 0)
+        .map(|(amount, unit)| format!("{}{}", amount, unit))
+        .collect()
+}
+
+// one agent per config, so that profiles are unlocked separately
+fn socket_path() -> Result<PathBuf, AgentError> {
+    Ok(get_config_path()?.with_extension("sock"))
+}
+
+fn start_session(config: &Config, password: &str, seconds: u64) -> Result<(), AgentError> {
+    if config.password.hash.is_some() && !check_password(password)? {
+        return Err(AgentError::WrongPassword);
+    }
+    let password_key = match keyring::password_key(password) {
+        Ok(password_key) => password_key,
+        Err(EncryptionError::WrongKeyringPassword) => return Err(AgentError::WrongPassword),
+        Err(e) => return Err(e.into()),
+    };
+
+    // a running session is replaced
+    let _ = request(LOCK_REQUEST);
+    let socket_path = socket_path()?;
+    if socket_path.exists() {
+        fs::remove_file(&socket_path)?;
+    }
+
+    spawn_agent(
+        &socket_path,
+        &SessionMessage {
+            password_key: password_key.map(|password_key| Bytes(password_key.to_vec())),
+            password: config.password.hash.as_ref().map(|_| password.to_string()),
+            seconds,
+        },
+    )
+}
+
+#[cfg(unix)]
+fn spawn_agent(socket_path: &Path, message: &SessionMessage) -> Result<(), AgentError> {
+    let mut agent = Command::new(env::current_exe()?)
+        .arg("agent")
+        .arg(socket_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        // out of the terminal process group, so that Ctrl-C does not end the session
+        .process_group(0)
+        .spawn()?;
+
+    // secrets go through a pipe, never through the command line
+    let mut stdin = agent.stdin.take().ok_or(AgentError::NotStarted)?;
+    writeln!(stdin, "{}", serde_json::to_string(message)?)?;
+    drop(stdin);
+
+    let mut ready = String::new();
+    BufReader::new(agent.stdout.take().ok_or(AgentError::NotStarted)?).read_line(&mut ready)?;
+    if ready.trim() != READY {
+        return Err(AgentError::NotStarted);
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn spawn_agent(_socket_path: &Path, _message: &SessionMessage) -> Result<(), AgentError> {
+    Err(AgentError::Unsupported)
+}
+
+#[cfg(unix)]
+fn run_agent(socket_path: &Path) -> Result<(), AgentError> {
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    let message: SessionMessage = serde_json::from_str(&line)?;
+    let expires_at = Instant::now() + Duration::from_secs(message.seconds);
+
+    // only the user running mucli may talk to the agent, the socket is created owner-only
+    // so that it is never reachable by others, even before its permissions are set
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(socket_path);
+    unsafe { libc::umask(umask) };
+    let listener = listener?;
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(0o600))?;
+
+    let expired_socket = socket_path.to_path_buf();
+    thread::spawn(move || {
+        thread::sleep(expires_at.saturating_duration_since(Instant::now()));
+        let _ = fs::remove_file(expired_socket);
+        process::exit(0);
+    });
+
+    println!("{}", READY);
+    io::stdout().flush()?;
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        if !is_own_peer(&stream) {
+            continue;
+        }
+        let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+
+        let mut request = String::new();
+        if BufReader::new(&stream).read_line(&mut request).is_err() {
+            continue;
+        }
+
+        match request.trim() {
+            STATUS_REQUEST => {
+                let reply = SessionMessage {
+                    password_key: message.password_key.clone(),
+                    password: message.password.clone(),
+                    seconds: expires_at
+                        .saturating_duration_since(Instant::now())
+                        .as_secs(),
+                };
+                let _ = writeln!(stream, "{}", serde_json::to_string(&reply)?);
+            }
+            LOCK_REQUEST => {
+                let _ = writeln!(
+                    stream,
+                    "{}",
+                    serde_json::to_string(&SessionMessage::default())?
+                );
+                fs::remove_file(socket_path)?;
+                return Ok(());
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn run_agent(_socket_path: &Path) -> Result<(), AgentError> {
+    Err(AgentError::Unsupported)
+}
+
+#[cfg(unix)]
+fn request(request: &str) -> Result<SessionMessage, AgentError> {
+    let mut stream = UnixStream::connect(socket_path()?)?;
+    // the config dir may be shared, a socket left there by someone else is not trusted
+    if !is_own_peer(&stream) {
+        return Err(AgentError::ForeignAgent);
+    }
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    writeln!(stream, "{}", request)?;
+
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)?;
+    Ok(serde_json::from_str(&reply)?)
+}
+
+// whether the process at the other end of the socket runs as the current user
+#[cfg(unix)]
+fn is_own_peer(stream: &UnixStream) -> bool {
+    peer_uid(stream).is_ok_and(|uid| uid == unsafe { libc::getuid() })
+}
+
+#[cfg(target_os = "linux")]
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(credentials.uid)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    use std::os::unix::io::AsRawFd;
+
+    let (mut uid, mut gid) = (0, 0);
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(uid)
+}
+
+#[cfg(not(unix))]
+fn request(_request: &str) -> Result<SessionMessage, AgentError> {
+    Err(AgentError::Unsupported)
+}
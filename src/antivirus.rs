@@ -3,6 +3,7 @@ use std::path::PathBuf;
 
 use clap::ArgMatches;
 
+use crate::utils::{config::Config, GenericError};
 use crate::{print_err, print_info};
 
 use std::io::Error;
@@ -22,6 +23,7 @@ custom_error! {pub AntivirusError
     Io{source: Error} = "{source}",
     ReqWest{source: reqwest::Error } = "{source}",
     Serde{source: SerdeError } = "{source}",
+    Generic{source: GenericError} = "{source}",
     InvalidApiResponse = "API response is invalid",
     ErrorApiResponse{message: String} = "{message}",
     ApiReponseAnalyseFailed = "Failed to analyse API Response",
@@ -81,8 +83,16 @@ async fn is_dangerous(file_path: &PathBuf) -> Result<bool, AntivirusError> {
     Ok(malicious_number + suspicious_number > 0)
 }
 
+// the key set in the config takes precedence over the one mucli was built with
+fn api_key() -> Result<String, AntivirusError> {
+    Ok(Config::load()?
+        .antivirus
+        .api_key
+        .unwrap_or_else(|| dotenv!("VIRUSTOTAL_API_KEY").to_string()))
+}
+
 async fn get_analysis_id(file_path: &PathBuf) -> Result<String, AntivirusError> {
-    const API_KEY: &str = dotenv!("VIRUSTOTAL_API_KEY");
+    let api_key = api_key()?;
     const URL: &str = "https://www.virustotal.com/api/v3/files";
     let client = reqwest::Client::new();
     let body = file_to_base64(file_path)?;
@@ -97,7 +107,7 @@ async fn get_analysis_id(file_path: &PathBuf) -> Result<String, AntivirusError>
     let response = client
         .post(URL)
         .header("accept", "application/json")
-        .header("x-apikey", API_KEY)
+        .header("x-apikey", &api_key)
         .multipart(form)
         .send()
         .await?;
@@ -122,14 +132,14 @@ async fn get_analysis_id(file_path: &PathBuf) -> Result<String, AntivirusError>
 }
 
 async fn get_analysis_report(id: &str) -> Result<AnalysisReportData, AntivirusError> {
-    const API_KEY: &str = dotenv!("VIRUSTOTAL_API_KEY");
+    let api_key = api_key()?;
     let url = String::from("https://www.virustotal.com/api/v3/analyses/") + id;
     let client = reqwest::Client::new();
 
     let response = client
         .get(url)
         .header("accept", "application/json")
-        .header("x-apikey", API_KEY)
+        .header("x-apikey", &api_key)
         .send()
         .await?;
 
@@ -1,22 +1,73 @@
-use crate::file_as_bytes;
+mod extract;
+mod format;
+mod journal;
+mod policy;
+mod read;
+mod write;
+
+use crate::encryption::{encrypt_with, init_encryption_key, EncryptionError};
+use crate::password::unlock_gate;
 use crate::print_solution;
-use crate::{print_err, print_success};
+use crate::utils::{
+    config::Config,
+    prompt::{self, PromptError},
+    walk::PathFilter,
+    GenericError,
+};
+use crate::vault::{read_secret, VaultError};
+use crate::{print_err, print_info, print_success, print_warn};
 use clap::ArgMatches;
 use custom_error::custom_error;
 use std::{
     env::current_dir,
-    fs::{self, File},
-    io::{self, Error, Write},
+    fs,
+    io::{Error, Seek},
     path::{Path, PathBuf},
 };
 use zip::result::ZipError;
-use zip::{write::FileOptions, ZipWriter};
+
+use self::extract::{extract, list, test, EntrySelection, ExtractOptions};
+pub use self::format::Format;
+pub use self::policy::{
+    parse_size, ExtractLimits, Overwrite, DEFAULT_MAX_ENTRIES, DEFAULT_MAX_RATIO, DEFAULT_MAX_SIZE,
+    DEFAULT_OVERWRITE,
+};
+use self::write::{source_entries, stream_archive, write_archive};
+pub use self::write::{Compression, Method};
 
 custom_error! {pub CompressionError
     Io{source: Error} = "{source}",
     Zip{source: ZipError} = "{source}",
+    SevenZ{source: sevenz_rust::Error} = "{source}",
+    Encryption{source: EncryptionError} = "{source}",
+    Prompt{source: PromptError} = "{source}",
+    Vault{source: VaultError} = "{source}",
     Default = "Failed to compress file",
-    Custom{src: String} = "{src}"
+    Custom{src: String} = "{src}",
+    InvalidPattern{pattern: String, source: glob::PatternError} = "Invalid pattern \"{pattern}\": {source}",
+    UnknownFormat = "Not an archive mucli can read, zip, tar, tar.gz, tar.zst, tar.xz and 7z are supported",
+    ReadOnlyFormat{format: Format} = "{format} archives can be extracted but not created",
+    NoMethod{format: Format} = "Only zips take a compression method, a {format} archive is compressed by its format",
+    NoPassword{format: Format} = "Only zips can be protected with a password, not {format} archives",
+    WrongPassword = "Wrong password for the archive",
+    EncryptedSevenZ = "Password protected 7z archives are not supported",
+    NoLevel{compressor: String} = "The {compressor} does not compress, it takes no level",
+    UnsupportedLevel{compressor: String, level: i32, min: i32, max: i32} = "The {compressor} takes a level between {min} and {max}, not {level}",
+    TooManyEntries{max: u64} = "The archive has more than {max} entries, raise archive.max_entries or use --max-entries to extract it",
+    TooLarge{max: String} = "The archive extracts to more than {max}, raise archive.max_size or use --max-size to extract it",
+    RatioExceeded{max: u64} = "The archive expands to more than {max} times its size and may be an archive bomb, raise archive.max_ratio or use --max-ratio to extract it",
+    UnsafePath{name: String} = "\"{name}\" points outside of the output directory",
+    SymlinkEscape{name: String, target: String} = "\"{name}\" links to \"{target}\", outside of the output directory",
+    OutsideLink{name: String} = "\"{name}\" would be written outside of the output directory through an existing link",
+    LongLinkTarget{name: String, max: u64} = "\"{name}\" links to a target longer than {max} bytes",
+    ReplaceDirectory{path: String} = "Cannot replace the directory \"{path}\" with a file"
+}
+
+/// Bytes read from the source and bytes of the archive written
+#[derive(Debug, Clone, Copy)]
+pub struct ArchiveSize {
+    pub original: u64,
+    pub archive: u64,
 }
 
 pub fn compress_command(sub_matches: &ArgMatches) {
@@ -41,25 +92,82 @@ pub fn compress_command(sub_matches: &ArgMatches) {
                 return;
             }
         };
-        let output_file_name = format!("{}.zip", source_name);
-
-        let compression_level = sub_matches
+        let format = sub_matches
+            .get_one::<String>("format")
+            .and_then(|format| Format::from_name(format))
+            .unwrap_or(Format::Zip);
+        let method = sub_matches
+            .get_one::<String>("method")
+            .and_then(|method| Method::from_name(method));
+        let level = sub_matches
             .get_one::<i64>("level")
             .copied()
             .map(|val| val as i32);
+        let compression = match Compression::new(format, method, level) {
+            Ok(compression) => compression,
+            Err(e) => {
+                print_err!("{}", e);
+                return;
+            }
+        };
+
+        // checked before asking for a password that could not be used
+        let protect = sub_matches.get_flag("password") || sub_matches.contains_id("vault");
+        if protect && format != Format::Zip {
+            print_err!("{}", CompressionError::NoPassword { format });
+            return;
+        }
+        if sub_matches.contains_id("vault") && !unlock_gate("vault") {
+            return;
+        }
+        let compression = match archive_password(sub_matches, protect) {
+            Ok(Some(password)) => compression.with_password(password),
+            Ok(None) => compression,
+            Err(e) => {
+                print_err!("{}", e);
+                return;
+            }
+        };
+
+        let patterns = |id: &str| -> Vec<String> {
+            sub_matches
+                .get_many::<String>(id)
+                .map(|values| values.cloned().collect())
+                .unwrap_or_default()
+        };
+        let filter = match PathFilter::new(&patterns("include"), &patterns("exclude")) {
+            Ok(filter) => filter,
+            Err(e) => {
+                print_err!("{}", e);
+                return;
+            }
+        };
+
+        let encrypt = sub_matches.get_flag("encrypt");
+        let output_file_name = if encrypt {
+            if let Err(e) = init_encryption_key() {
+                print_err!("Error initializing encryption key: {}", e);
+                return;
+            }
+            format!("{}.{}.enc", source_name, format)
+        } else {
+            format!("{}.{}", source_name, format)
+        };
+        let create_archive = if encrypt {
+            create_encrypted_archive
+        } else {
+            create_archive
+        };
 
         if let true = sub_matches.get_flag("cdir") {
             match current_dir() {
                 Ok(current_dir) => {
                     let output_path = current_dir.join(&output_file_name);
-                    match create_zip(&source_path, &output_path, compression_level) {
-                        Ok(_) => print_success!(
-                            "{} successfully compressed as {}",
-                            source_path.display(),
-                            output_path.display()
-                        ),
-                        Err(e) => print_err!("(compress error): {}", e),
-                    }
+                    print_compressed(
+                        &source_path,
+                        &output_path,
+                        create_archive(&source_path, &output_path, compression, &filter),
+                    )
                 }
                 Err(error) => {
                     print_err!("Failed to get current directory: {}", error)
@@ -68,28 +176,22 @@ pub fn compress_command(sub_matches: &ArgMatches) {
         } else if let Some(output_dir) = sub_matches.get_one::<PathBuf>("OUTPUTDIR") {
             let output_path = output_dir.join(output_file_name);
             match output_dir.is_dir() {
-                true => match create_zip(&source_path, &output_path, compression_level) {
-                    Ok(_) => print_success!(
-                        "{} successfully compressed as {}",
-                        source_path.display(),
-                        output_path.display()
-                    ),
-                    Err(e) => print_err!("(compress error): {}", e),
-                },
+                true => print_compressed(
+                    &source_path,
+                    &output_path,
+                    create_archive(&source_path, &output_path, compression, &filter),
+                ),
                 false => print_err!("Failed to get {} directory", output_dir.display()),
             }
         } else {
             match source_path.parent() {
                 Some(parent_dir) => {
                     let output_path = &Path::new(parent_dir).join(output_file_name);
-                    match create_zip(&source_path, &output_path, compression_level) {
-                        Ok(_) => print_success!(
-                            "{} successfully compressed as {}",
-                            source_path.display(),
-                            output_path.display()
-                        ),
-                        Err(e) => print_err!("(compress error): {}", e),
-                    }
+                    print_compressed(
+                        &source_path,
+                        output_path,
+                        create_archive(&source_path, output_path, compression, &filter),
+                    )
                 }
                 None => print_err!("Failed to get source directory parent directory"),
             }
@@ -113,158 +215,246 @@ pub fn extract_command(sub_matches: &ArgMatches) {
             }
         };
 
-        if let true = sub_matches.get_flag("cdir") {
-            match current_dir() {
-                Ok(current_dir) => match extract_zip(&source_path, &current_dir) {
-                    Ok(_) => print_success!(
-                        "{} successfully extracted in {}",
-                        source_path.display(),
-                        current_dir.display()
-                    ),
-                    Err(e) => print_err!("(extraction error): {}", e),
-                },
-                Err(error) => {
-                    print_err!("Failed to get current directory: {}", error)
-                }
+        let patterns: Vec<String> = sub_matches
+            .get_many::<String>("PATTERN")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default();
+        let strip_components = sub_matches
+            .get_one::<usize>("strip-components")
+            .copied()
+            .unwrap_or_default();
+        let mut selection = match EntrySelection::new(&patterns, strip_components) {
+            Ok(selection) => selection,
+            Err(e) => {
+                print_err!("{}", e);
+                return;
             }
-        } else if let Some(output_dir) = sub_matches.get_one::<PathBuf>("OUTPUTDIR") {
-            match output_dir.is_dir() {
-                true => match extract_zip(&source_path, &output_dir) {
-                    Ok(_) => print_success!(
-                        "{} successfully extracted in {}",
-                        source_path.display(),
-                        output_dir.display()
-                    ),
-                    Err(e) => print_err!("(extraction error): {}", e),
-                },
-                false => print_err!("Failed to get {} directory", output_dir.display()),
+        };
+
+        // otherwise asked for when an encrypted entry is met
+        if sub_matches.contains_id("vault") && !unlock_gate("vault") {
+            return;
+        }
+        let password = match archive_password(sub_matches, false) {
+            Ok(password) => password,
+            Err(e) => {
+                print_err!("{}", e);
+                return;
+            }
+        };
+
+        if sub_matches.get_flag("list") {
+            if let Err(e) = list(&source_path, &mut selection) {
+                print_err!("(listing error): {}", e);
+            }
+        } else if sub_matches.get_flag("test") {
+            match test(&source_path, &mut selection, password) {
+                Ok((tested, 0)) => print_success!(
+                    "No errors in the {} entries of {}",
+                    tested,
+                    source_path.display()
+                ),
+                Ok((tested, failed)) => print_err!(
+                    "{} of the {} entries of {} are corrupted",
+                    failed,
+                    tested,
+                    source_path.display()
+                ),
+                Err(e) => print_err!("(test error): {}", e),
             }
         } else {
-            match source_path.parent() {
-                Some(parent_dir) => match extract_zip(&source_path, &parent_dir.to_path_buf()) {
-                    Ok(_) => print_success!(
+            let output_dir = if sub_matches.get_flag("cdir") {
+                match current_dir() {
+                    Ok(current_dir) => current_dir,
+                    Err(error) => {
+                        print_err!("Failed to get current directory: {}", error);
+                        return;
+                    }
+                }
+            } else if let Some(output_dir) = sub_matches.get_one::<PathBuf>("OUTPUTDIR") {
+                if !output_dir.is_dir() {
+                    print_err!("Failed to get {} directory", output_dir.display());
+                    return;
+                }
+                output_dir.to_path_buf()
+            } else {
+                match source_path.parent() {
+                    Some(parent_dir) => parent_dir.to_path_buf(),
+                    None => {
+                        print_err!("Failed to get source directory parent directory");
+                        return;
+                    }
+                }
+            };
+
+            let options = match extract_options(sub_matches) {
+                Ok(options) => options,
+                Err(e) => {
+                    print_err!("{}", e);
+                    return;
+                }
+            };
+            match extract(&source_path, &output_dir, &mut selection, options, password) {
+                Ok(summary) => {
+                    print_success!(
                         "{} successfully extracted in {}",
                         source_path.display(),
-                        parent_dir.display()
-                    ),
-                    Err(e) => print_err!("(extraction error): {}", e),
-                },
-                None => print_err!("Failed to get source directory parent directory"),
+                        output_dir.display()
+                    );
+                    match summary.skipped {
+                        0 => print_info!("{} entries extracted", summary.extracted),
+                        skipped => print_info!(
+                            "{} entries extracted, {} skipped as they already exist",
+                            summary.extracted,
+                            skipped
+                        ),
+                    }
+                }
+                Err(e) => {
+                    print_err!("(extraction error): {}", e);
+                    if let CompressionError::Prompt { .. } = e {
+                        print_solution!("Use --overwrite or --keep-both to decide without asking");
+                    }
+                    print_info!("Nothing was left of the extraction");
+                }
             }
         }
+
+        for pattern in selection.unmatched() {
+            print_warn!("No entry matches \"{}\"", pattern);
+        }
     }
 }
 
-fn create_zip(
-    source_path: &PathBuf,
-    output_path: &PathBuf,
-    compression_level: Option<i32>,
-) -> Result<(), CompressionError> {
-    let file = File::create(output_path)?;
-    let mut zip = ZipWriter::new(file);
-
-    let options = FileOptions::default()
-        .compression_method(zip::CompressionMethod::Stored)
-        .compression_level(compression_level)
-        .unix_permissions(0o755);
-
-    if source_path.is_dir() {
-        let mut path_queue = vec![];
-        path_queue.push(source_path.to_owned());
-
-        while let Some(dir) = path_queue.pop() {
-            let entries = fs::read_dir(&dir)?;
-
-            for (i, entry) in entries.enumerate() {
-                let entry = entry?;
-                let entry_path = entry.path();
-                let entry_name = entry_path.to_string_lossy().to_string();
-                println!("{}", entry_path.display());
-                println!("{}", source_path.display());
-
-                if entry_path.is_file() {
-                    let (_, content) = file_as_bytes!(&entry_path);
+// the password of the vault entry given with --vault, or a new one typed in when `ask` is set
+fn archive_password(
+    sub_matches: &ArgMatches,
+    ask: bool,
+) -> Result<Option<String>, CompressionError> {
+    if let Some(name) = sub_matches.get_one::<String>("vault") {
+        return Ok(Some(read_secret(name)?));
+    }
+    if !ask {
+        return Ok(None);
+    }
+    Ok(Some(prompt::new_password(
+        "Enter the password of the archive",
+        "Confirm the password",
+    )?))
+}
 
-                    {
-                        let path = entry_path;
-                        println!("File {i} comment: {:?}", path);
-                    }
+// limits of the config, overridden by the command line
+fn extract_options(sub_matches: &ArgMatches) -> Result<ExtractOptions, GenericError> {
+    let limits = Config::load()?.archive.limits();
+    Ok(ExtractOptions {
+        overwrite: sub_matches
+            .get_one::<String>("overwrite")
+            .and_then(|overwrite| Overwrite::from_name(overwrite))
+            .unwrap_or(Overwrite::Ask),
+        keep_both: sub_matches.get_flag("keep-both"),
+        limits: ExtractLimits {
+            max_size: sub_matches
+                .get_one::<u64>("max-size")
+                .copied()
+                .unwrap_or(limits.max_size),
+            max_entries: sub_matches
+                .get_one::<u64>("max-entries")
+                .copied()
+                .unwrap_or(limits.max_entries),
+            max_ratio: sub_matches
+                .get_one::<u64>("max-ratio")
+                .copied()
+                .unwrap_or(limits.max_ratio),
+        },
+    })
+}
 
-                    zip.start_file(entry_name, options)?;
-                    zip.write(&content)?;
-                } else if entry_path.is_dir() {
-                    zip.add_directory(entry_name, options)?;
-                    path_queue.push(entry_path)
-                }
+fn print_compressed(
+    source_path: &Path,
+    output_path: &Path,
+    result: Result<ArchiveSize, CompressionError>,
+) {
+    match result {
+        Ok(size) => {
+            print_success!(
+                "{} successfully compressed as {}",
+                source_path.display(),
+                output_path.display()
+            );
+            if size.original > 0 {
+                print_info!(
+                    "{} compressed to {}, {:.1}% of the original size",
+                    format_size(size.original),
+                    format_size(size.archive),
+                    size.archive as f64 * 100.0 / size.original as f64
+                );
             }
         }
-    } else if source_path.is_file() {
-        let (_, content) = file_as_bytes!(&source_path);
-
-        if let Some(name) = source_path.file_name() {
-            zip.start_file(name.to_string_lossy(), options)?;
-            zip.write(&content)?;
-        } else {
-            return Err(CompressionError::Custom {
-                src: "Invalid file path".to_string(),
-            });
-        }
+        Err(e) => print_err!("(compress error): {}", e),
     }
-    zip.finish()?;
+}
 
-    Ok(())
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
 }
 
-fn extract_zip(source_path: &PathBuf, output_dir: &PathBuf) -> Result<(), CompressionError> {
-    let source_file = File::open(source_path)?;
+fn create_archive(
+    source_path: &Path,
+    output_path: &Path,
+    compression: Compression,
+    filter: &PathFilter,
+) -> Result<ArchiveSize, CompressionError> {
+    // listed first, an archive written inside the source is not part of it
+    let entries = source_entries(source_path, filter)?;
 
-    let mut archive = zip::ZipArchive::new(source_file)?;
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i)?;
-        let outpath = match file.enclosed_name() {
-            Some(path) => path.to_owned(),
-            None => continue,
-        };
-        println!("{}", outpath.display());
-        println!("{}", source_path.display());
-        let outpath = output_dir.join(outpath);
+    // written next to the output and renamed over it once complete, so that a failure
+    // leaves an existing archive untouched. Created with the permissions of a new file
+    let output_dir = match output_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut builder = tempfile::Builder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
 
-        {
-            let comment = file.comment();
-            if !comment.is_empty() {
-                println!("File {i} comment: {comment}");
-            }
-        }
+        builder.permissions(fs::Permissions::from_mode(0o666));
+    }
+    let mut temp_file = builder.tempfile_in(output_dir)?;
 
-        if (*file.name()).ends_with('/') {
-            println!("File {} extracted to \"{}\"", i, outpath.display());
-            fs::create_dir_all(&outpath)?;
-        } else {
-            println!(
-                "File {} extracted to \"{}\" ({} bytes)",
-                i,
-                outpath.display(),
-                file.size()
-            );
-            if let Some(p) = outpath.parent() {
-                if !p.exists() {
-                    fs::create_dir_all(p)?;
-                }
-            }
-            let mut outfile = fs::File::create(&outpath)?;
-            io::copy(&mut file, &mut outfile)?;
-        }
+    let (file, original) = write_archive(&entries, temp_file.as_file_mut(), &compression)?;
+    let archive = file.stream_position()?;
+    file.sync_all()?;
 
-        // Get and Set permissions
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
+    temp_file.persist(output_path).map_err(|e| e.error)?;
+    Ok(ArchiveSize { original, archive })
+}
 
-            if let Some(mode) = file.unix_mode() {
-                fs::set_permissions(&outpath, fs::Permissions::from_mode(mode))?;
-            }
-        }
-    }
+// the archive is sealed as it is written, no plain copy of it is left next to the output
+fn create_encrypted_archive(
+    source_path: &Path,
+    output_path: &Path,
+    compression: Compression,
+    filter: &PathFilter,
+) -> Result<ArchiveSize, CompressionError> {
+    let entries = source_entries(source_path, filter)?;
+    let original = encrypt_with(output_path, |writer| {
+        let (_, original) = stream_archive(&entries, writer, &compression)?;
+        Ok::<_, CompressionError>(original)
+    })?;
 
-    Ok(())
+    Ok(ArchiveSize {
+        original,
+        archive: fs::metadata(output_path)?.len(),
+    })
 }
@@ -0,0 +1,550 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use glob::Pattern;
+use time::OffsetDateTime;
+
+use super::{
+    format::Format,
+    journal::Journal,
+    policy::{Budget, ExtractLimits, Overwrite},
+    read::{for_each_entry, ArchiveEntry, EntryType, ZipPassword},
+    CompressionError,
+};
+use crate::{
+    encryption::decrypt_to_spool,
+    print_err,
+    utils::{file::AEAD_HEADER_MARKER, prompt},
+};
+
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Entries picked by name, and how many leading directories are removed from their path
+#[derive(Debug, Default)]
+pub struct EntrySelection {
+    patterns: Vec<Pattern>,
+    // whether each pattern picked an entry
+    matched: Vec<bool>,
+    strip_components: usize,
+}
+
+impl EntrySelection {
+    pub fn new(
+        patterns: &[String],
+        strip_components: usize,
+    ) -> Result<EntrySelection, CompressionError> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                Pattern::new(pattern).map_err(|source| CompressionError::InvalidPattern {
+                    pattern: pattern.to_string(),
+                    source,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(EntrySelection {
+            matched: vec![false; patterns.len()],
+            patterns,
+            strip_components,
+        })
+    }
+
+    // every entry without patterns, otherwise the entries matching one with everything
+    // under them, as tar does for directories
+    fn matches(&mut self, name: &str) -> bool {
+        if self.patterns.is_empty() {
+            return true;
+        }
+
+        let mut matches = false;
+        for ancestor in Path::new(name.trim_end_matches('/')).ancestors() {
+            for (pattern, matched) in self.patterns.iter().zip(self.matched.iter_mut()) {
+                if !ancestor.as_os_str().is_empty() && pattern.matches_path(ancestor) {
+                    *matched = true;
+                    matches = true;
+                }
+            }
+        }
+        matches
+    }
+
+    /// Patterns no entry matched
+    pub fn unmatched(&self) -> Vec<&str> {
+        self.patterns
+            .iter()
+            .zip(&self.matched)
+            .filter(|(_, matched)| !**matched)
+            .map(|(pattern, _)| pattern.as_str())
+            .collect()
+    }
+
+    // None when nothing is left of the path once stripped
+    fn strip(&self, path: &Path) -> Option<PathBuf> {
+        let path: PathBuf = path.components().skip(self.strip_components).collect();
+        match path.as_os_str().is_empty() {
+            true => None,
+            false => Some(path),
+        }
+    }
+}
+
+/// How an extraction treats paths that already exist, and when it gives up
+#[derive(Debug, Clone, Copy)]
+pub struct ExtractOptions {
+    pub overwrite: Overwrite,
+    // existing files are kept and the entry is written next to them under a free name
+    pub keep_both: bool,
+    pub limits: ExtractLimits,
+}
+
+/// Entries written, and entries left out because their path already existed
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ExtractSummary {
+    pub extracted: usize,
+    pub skipped: usize,
+}
+
+/// Extracts the selected entries in `output_dir`. Nothing is left of the extraction when it
+/// fails, the files it wrote are removed and the ones it replaced are put back. The password
+/// of encrypted zip entries is asked for when it is not given
+pub fn extract(
+    source_path: &Path,
+    output_dir: &Path,
+    selection: &mut EntrySelection,
+    options: ExtractOptions,
+    password: Option<String>,
+) -> Result<ExtractSummary, CompressionError> {
+    let (reader, format, archive_size) = open_archive(source_path)?;
+    let mut extraction = Extraction {
+        output_dir: fs::canonicalize(output_dir)?,
+        options,
+        budget: Budget::new(options.limits, archive_size),
+        journal: Journal::default(),
+        directories: vec![],
+        summary: ExtractSummary::default(),
+    };
+
+    let mut password = ZipPassword::Ask(password);
+    let result = for_each_entry(reader, format, &mut password, |entry| {
+        if !selection.matches(&entry.name) {
+            return Ok(());
+        }
+        let path = enclosed_path(&entry.name).ok_or_else(|| CompressionError::UnsafePath {
+            name: entry.name.clone(),
+        })?;
+        match selection.strip(&path) {
+            Some(path) => extraction.add(entry, &path),
+            None => Ok(()),
+        }
+    })
+    .and_then(|_| extraction.finish_directories());
+
+    match result {
+        Ok(()) => {
+            extraction.journal.commit();
+            Ok(extraction.summary)
+        }
+        Err(e) => {
+            extraction.journal.rollback();
+            Err(e)
+        }
+    }
+}
+
+/// Prints a table of the selected entries with their sizes, time, mode and CRC,
+/// encrypted entries are listed without their password
+pub fn list(source_path: &Path, selection: &mut EntrySelection) -> Result<(), CompressionError> {
+    let (reader, format, _) = open_archive(source_path)?;
+    let mut count = 0;
+    let mut total_size = 0;
+    // unknown as soon as one entry has no compressed size
+    let mut total_compressed = Some(0);
+
+    println!(
+        "{:>12}  {:>12}  {:>6}  {:<16}  {:<10}  {:<8}  Name",
+        "Size", "Compressed", "Ratio", "Modified", "Mode", "CRC32"
+    );
+    for_each_entry(reader, format, &mut ZipPassword::Skip, |entry| {
+        if !selection.matches(&entry.name) {
+            return Ok(());
+        }
+        let name = match &entry.entry_type {
+            EntryType::Symlink { target } if !entry.encrypted => {
+                format!("{} -> {}", entry.name, target)
+            }
+            _ => entry.name.clone(),
+        };
+        let name = match entry.encrypted {
+            true => format!("{} (encrypted)", name),
+            false => name,
+        };
+
+        println!(
+            "{:>12}  {:>12}  {:>6}  {:<16}  {:<10}  {:<8}  {}",
+            entry.size,
+            optional(entry.compressed_size),
+            ratio(entry.size, entry.compressed_size),
+            entry.modified.map_or("-".to_string(), format_time),
+            format_mode(&entry.entry_type, entry.mode),
+            optional(entry.crc32.map(|crc| format!("{:08x}", crc))),
+            name
+        );
+        count += 1;
+        total_size += entry.size;
+        total_compressed = total_compressed
+            .zip(entry.compressed_size)
+            .map(|(a, b)| a + b);
+        Ok(())
+    })?;
+    println!(
+        "{:>12}  {:>12}  {:>6}  {:<16}  {:<10}  {:<8}  {} entries ({})",
+        total_size,
+        optional(total_compressed),
+        ratio(total_size, total_compressed),
+        "",
+        "",
+        "",
+        count,
+        format
+    );
+
+    Ok(())
+}
+
+/// Reads every selected entry to its end without writing anything, which checks the CRC of
+/// each entry and the checksum of compressed tars. Returns how many were read and how many failed
+pub fn test(
+    source_path: &Path,
+    selection: &mut EntrySelection,
+    password: Option<String>,
+) -> Result<(usize, usize), CompressionError> {
+    let (reader, format, _) = open_archive(source_path)?;
+    let mut tested = 0;
+    let mut failed = 0;
+
+    for_each_entry(reader, format, &mut ZipPassword::Ask(password), |entry| {
+        if !selection.matches(&entry.name) {
+            return Ok(());
+        }
+        tested += 1;
+        if let Err(e) = io::copy(entry.reader, &mut io::sink()) {
+            print_err!("{}: {}", entry.name, e);
+            failed += 1;
+        }
+        Ok(())
+    })?;
+
+    Ok((tested, failed))
+}
+
+// the archive with its format and size, decrypted to a spool when mucli encrypted it.
+// The format is told from the content, whatever the extension of the archive
+fn open_archive(source_path: &Path) -> Result<(Box<dyn ReadSeek>, Format, u64), CompressionError> {
+    let mut source_file = File::open(source_path)?;
+
+    let mut marker = [0u8; 4];
+    let is_encrypted = source_file.read_exact(&mut marker).is_ok() && marker == AEAD_HEADER_MARKER;
+    source_file.rewind()?;
+
+    let mut reader: Box<dyn ReadSeek> = if is_encrypted {
+        Box::new(decrypt_to_spool(source_path)?)
+    } else {
+        Box::new(source_file)
+    };
+    let size = reader.seek(SeekFrom::End(0))?;
+    reader.rewind()?;
+    let format = Format::detect(&mut reader)?.ok_or(CompressionError::UnknownFormat)?;
+    Ok((reader, format, size))
+}
+
+// the state of an extraction in progress
+struct Extraction {
+    // canonical, nothing is written out of it
+    output_dir: PathBuf,
+    options: ExtractOptions,
+    budget: Budget,
+    journal: Journal,
+    // created directories, finished last as extracting their content changes their time
+    directories: Vec<(PathBuf, Option<SystemTime>, Option<u32>)>,
+    summary: ExtractSummary,
+}
+
+impl Extraction {
+    // writes the entry at `path`, relative to the output directory
+    fn add(&mut self, entry: ArchiveEntry, path: &Path) -> Result<(), CompressionError> {
+        self.budget.add_entry()?;
+        let outpath = self.output_dir.join(path);
+
+        if entry.entry_type == EntryType::Directory {
+            self.check_enclosed(&outpath, &entry.name)?;
+            self.journal.create_dir_all(&outpath)?;
+            if self.journal.created_dir(&outpath) {
+                self.directories.push((outpath, entry.modified, entry.mode));
+            }
+            self.summary.extracted += 1;
+            return Ok(());
+        }
+
+        if let EntryType::Symlink { target } = &entry.entry_type {
+            if link_escapes(path, target) {
+                return Err(CompressionError::SymlinkEscape {
+                    name: entry.name.clone(),
+                    target: target.clone(),
+                });
+            }
+        }
+        if let Some(parent) = outpath.parent() {
+            self.check_enclosed(parent, &entry.name)?;
+            self.journal.create_dir_all(parent)?;
+        }
+
+        let outpath = match fs::symlink_metadata(&outpath) {
+            Ok(metadata) => match self.resolve_existing(&entry, path, &outpath, &metadata)? {
+                Some(outpath) => outpath,
+                None => {
+                    self.summary.skipped += 1;
+                    return Ok(());
+                }
+            },
+            Err(_) => outpath,
+        };
+
+        if let EntryType::Symlink { target } = &entry.entry_type {
+            create_symlink(target, &outpath)?;
+            self.journal.add_file(&outpath);
+        } else {
+            // never opened through a link that would be in its place
+            let mut outfile = File::options()
+                .write(true)
+                .create_new(true)
+                .open(&outpath)?;
+            self.journal.add_file(&outpath);
+            self.copy(entry.reader, &mut outfile)?;
+            set_modified(&outfile, entry.modified);
+            set_permissions(&outpath, entry.mode)?;
+        }
+        self.summary.extracted += 1;
+        Ok(())
+    }
+
+    // where to write an entry whose path exists, None to skip it. A replaced path is
+    // moved aside until the extraction succeeds
+    fn resolve_existing(
+        &mut self,
+        entry: &ArchiveEntry,
+        path: &Path,
+        outpath: &Path,
+        metadata: &fs::Metadata,
+    ) -> Result<Option<PathBuf>, CompressionError> {
+        if self.options.keep_both {
+            return Ok(Some(free_path(outpath)));
+        }
+
+        let replace = match self.options.overwrite {
+            Overwrite::Never => false,
+            Overwrite::Always => true,
+            Overwrite::Ask => prompt::confirm(&format!("Replace \"{}\"?", path.display()))?,
+            Overwrite::Newer => entry
+                .modified
+                .zip(metadata.modified().ok())
+                .is_some_and(|(modified, existing)| modified > existing),
+        };
+        if !replace {
+            return Ok(None);
+        }
+        if metadata.is_dir() {
+            return Err(CompressionError::ReplaceDirectory {
+                path: path.display().to_string(),
+            });
+        }
+
+        self.journal.move_aside(outpath)?;
+        Ok(Some(outpath.to_path_buf()))
+    }
+
+    // `path` or, when it does not exist yet, its closest existing ancestor must resolve
+    // inside the output directory, links already on disk could lead anywhere
+    fn check_enclosed(&self, path: &Path, name: &str) -> Result<(), CompressionError> {
+        let existing = path
+            .ancestors()
+            .find(|ancestor| fs::symlink_metadata(ancestor).is_ok())
+            .unwrap_or(path);
+        match fs::canonicalize(existing)?.starts_with(&self.output_dir) {
+            true => Ok(()),
+            false => Err(CompressionError::OutsideLink {
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    // counts what is actually written, the sizes an archive declares could lie
+    fn copy(&mut self, reader: &mut dyn Read, writer: &mut File) -> Result<(), CompressionError> {
+        let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            self.budget.add_bytes(read as u64)?;
+            writer.write_all(&buffer[..read])?;
+        }
+    }
+
+    fn finish_directories(&mut self) -> Result<(), CompressionError> {
+        for (path, modified, mode) in self.directories.iter().rev() {
+            set_permissions(path, *mode)?;
+            if let Ok(dir) = File::open(path) {
+                set_modified(&dir, *modified);
+            }
+        }
+        Ok(())
+    }
+}
+
+// the relative path of an entry name, None when it goes above the output directory
+fn enclosed_path(name: &str) -> Option<PathBuf> {
+    if name.contains('\0') {
+        return None;
+    }
+
+    let mut path = PathBuf::new();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return None,
+            Component::ParentDir => {
+                if !path.pop() {
+                    return None;
+                }
+            }
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => (),
+        }
+    }
+    Some(path)
+}
+
+// whether a link at `path`, relative to the output directory, points above it
+fn link_escapes(path: &Path, target: &str) -> bool {
+    let mut resolved = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    for component in Path::new(target).components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return true,
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return true;
+                }
+            }
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => (),
+        }
+    }
+    false
+}
+
+// "name (n).ext" with the first n not taken, as file managers name copies
+fn free_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut copy = 1;
+    loop {
+        let candidate = path.with_file_name(format!("{} ({}){}", stem, copy, extension));
+        if fs::symlink_metadata(&candidate).is_err() {
+            return candidate;
+        }
+        copy += 1;
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or("-".to_string(), |value| value.to_string())
+}
+
+fn ratio(size: u64, compressed_size: Option<u64>) -> String {
+    match compressed_size {
+        Some(compressed_size) if size > 0 => {
+            format!("{:.1}%", compressed_size as f64 * 100.0 / size as f64)
+        }
+        _ => "-".to_string(),
+    }
+}
+
+// in UTC, as mucli stores the time of zip entries
+fn format_time(time: SystemTime) -> String {
+    let time = OffsetDateTime::from(time);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        time.year(),
+        time.month() as u8,
+        time.day(),
+        time.hour(),
+        time.minute()
+    )
+}
+
+// as ls prints it, without the permissions when the archive does not keep them
+fn format_mode(entry_type: &EntryType, mode: Option<u32>) -> String {
+    let kind = match entry_type {
+        EntryType::File => '-',
+        EntryType::Directory => 'd',
+        EntryType::Symlink { .. } => 'l',
+    };
+    let permissions: String = match mode {
+        Some(mode) => (0..9)
+            .map(|bit| match mode & (0o400 >> bit) != 0 {
+                true => ['r', 'w', 'x'][bit % 3],
+                false => '-',
+            })
+            .collect(),
+        None => "?".repeat(9),
+    };
+    format!("{}{}", kind, permissions)
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, path: &Path) -> Result<(), CompressionError> {
+    std::os::unix::fs::symlink(target, path)?;
+    Ok(())
+}
+
+// links need privileges on Windows, the target is written instead as unzip does
+#[cfg(not(unix))]
+fn create_symlink(target: &str, path: &Path) -> Result<(), CompressionError> {
+    fs::write(path, target)?;
+    Ok(())
+}
+
+// best effort, the content is kept even when its time cannot be
+fn set_modified(file: &File, modified: Option<SystemTime>) {
+    if let Some(modified) = modified {
+        let _ = file.set_modified(modified);
+    }
+}
+
+#[cfg(unix)]
+fn set_permissions(path: &Path, mode: Option<u32>) -> Result<(), CompressionError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_permissions(_path: &Path, _mode: Option<u32>) -> Result<(), CompressionError> {
+    Ok(())
+}
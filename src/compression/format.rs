@@ -0,0 +1,104 @@
+use std::{
+    fmt,
+    io::{self, Read, Seek},
+    ops::RangeInclusive,
+};
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+// an archive without entries starts with its end of central directory
+const EMPTY_ZIP_MAGIC: &[u8] = b"PK\x05\x06";
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const SEVENZ_MAGIC: &[u8] = &[b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c];
+// ustar and GNU tar headers hold it after the name, mode, owner, size and link fields
+const TAR_MAGIC: &[u8] = b"ustar";
+const TAR_MAGIC_OFFSET: usize = 257;
+
+/// Archive formats, told apart by their first bytes when read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+    TarXz,
+    SevenZ,
+}
+
+impl Format {
+    /// Formats mucli writes, 7z archives are only read
+    pub const NAMES: [&'static str; 5] = ["zip", "tar", "tar.gz", "tar.zst", "tar.xz"];
+
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "zip" => Some(Format::Zip),
+            "tar" => Some(Format::Tar),
+            "tar.gz" => Some(Format::TarGz),
+            "tar.zst" => Some(Format::TarZst),
+            "tar.xz" => Some(Format::TarXz),
+            "7z" => Some(Format::SevenZ),
+            _ => None,
+        }
+    }
+
+    /// Name of the format, also the extension of its archives
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Zip => "zip",
+            Format::Tar => "tar",
+            Format::TarGz => "tar.gz",
+            Format::TarZst => "tar.zst",
+            Format::TarXz => "tar.xz",
+            Format::SevenZ => "7z",
+        }
+    }
+
+    // levels of the compressor wrapping a tar, a zip takes the levels of its method
+    pub fn tar_levels(&self) -> Option<RangeInclusive<i32>> {
+        match self {
+            Format::TarGz => Some(0..=9),
+            Format::TarZst => Some(1..=22),
+            Format::TarXz => Some(0..=9),
+            Format::Zip | Format::Tar | Format::SevenZ => None,
+        }
+    }
+
+    /// Tells the format of an archive from its first bytes, whatever its extension.
+    /// The reader is rewound afterwards
+    pub fn detect<R: Read + Seek>(reader: &mut R) -> io::Result<Option<Format>> {
+        let mut header = [0; TAR_MAGIC_OFFSET + TAR_MAGIC.len()];
+        let mut read = 0;
+        while read < header.len() {
+            match reader.read(&mut header[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        reader.rewind()?;
+
+        let header = &header[..read];
+        let format = if header.starts_with(ZIP_MAGIC) || header.starts_with(EMPTY_ZIP_MAGIC) {
+            Some(Format::Zip)
+        } else if header.starts_with(GZIP_MAGIC) {
+            Some(Format::TarGz)
+        } else if header.starts_with(ZSTD_MAGIC) {
+            Some(Format::TarZst)
+        } else if header.starts_with(XZ_MAGIC) {
+            Some(Format::TarXz)
+        } else if header.starts_with(SEVENZ_MAGIC) {
+            Some(Format::SevenZ)
+        } else if header.len() > TAR_MAGIC_OFFSET && header[TAR_MAGIC_OFFSET..] == *TAR_MAGIC {
+            Some(Format::Tar)
+        } else {
+            None
+        };
+        Ok(format)
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
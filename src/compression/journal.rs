@@ -0,0 +1,79 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// What an extraction changed on disk, so that a failed one leaves the output as it was
+#[derive(Debug, Default)]
+pub struct Journal {
+    // files and links created, in order
+    files: Vec<PathBuf>,
+    // directories created, parents first
+    directories: Vec<PathBuf>,
+    // existing paths moved aside before being replaced, with where they were moved
+    replaced: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Journal {
+    pub fn add_file(&mut self, path: &Path) {
+        self.files.push(path.to_path_buf());
+    }
+
+    /// Creates the missing directories of `path`, remembering each of them
+    pub fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        let mut missing: Vec<&Path> = path
+            .ancestors()
+            .take_while(|ancestor| fs::symlink_metadata(ancestor).is_err())
+            .collect();
+        missing.reverse();
+
+        for dir in missing {
+            fs::create_dir(dir)?;
+            self.directories.push(dir.to_path_buf());
+        }
+        Ok(())
+    }
+
+    pub fn created_dir(&self, path: &Path) -> bool {
+        self.directories.iter().any(|dir| dir == path)
+    }
+
+    /// Moves an existing file or link away from `path`, it is put back by a rollback
+    /// and deleted by a commit
+    pub fn move_aside(&mut self, path: &Path) -> io::Result<()> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let backup = path.with_file_name(format!(
+            ".{}.{:08x}.mucli-backup",
+            name,
+            rand::random::<u32>()
+        ));
+
+        fs::rename(path, &backup)?;
+        self.replaced.push((path.to_path_buf(), backup));
+        Ok(())
+    }
+
+    /// Keeps the extraction, the replaced files are deleted
+    pub fn commit(self) {
+        for (_, backup) in self.replaced {
+            let _ = fs::remove_file(backup);
+        }
+    }
+
+    /// Undoes the extraction as far as possible, newest changes first
+    pub fn rollback(self) {
+        for file in self.files.iter().rev() {
+            let _ = fs::remove_file(file);
+        }
+        for (path, backup) in self.replaced.iter().rev() {
+            let _ = fs::rename(backup, path);
+        }
+        // only emptied directories go, files the extraction did not write are kept
+        for dir in self.directories.iter().rev() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use super::{format_size, CompressionError};
+
+/// Extraction limits when `archive.max_size`, `archive.max_entries` or `archive.max_ratio`
+/// are not set, high enough for real archives and low enough to stop archive bombs
+pub const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024 * 1024;
+pub const DEFAULT_MAX_ENTRIES: u64 = 100_000;
+pub const DEFAULT_MAX_RATIO: u64 = 1000;
+
+// small archives of very repetitive data legitimately go past any ratio,
+// the ratio is only checked once this many bytes were written
+const RATIO_FLOOR: u64 = 16 * 1024 * 1024;
+
+pub const DEFAULT_OVERWRITE: &str = "ask";
+
+/// What to do with an entry whose path already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overwrite {
+    Never,
+    Always,
+    Ask,
+    // when the entry was modified after the existing file
+    Newer,
+}
+
+impl Overwrite {
+    pub const NAMES: [&'static str; 4] = ["never", "always", "ask", "newer"];
+
+    pub fn from_name(name: &str) -> Option<Overwrite> {
+        match name {
+            "never" => Some(Overwrite::Never),
+            "always" => Some(Overwrite::Always),
+            "ask" => Some(Overwrite::Ask),
+            "newer" => Some(Overwrite::Newer),
+            _ => None,
+        }
+    }
+}
+
+/// Bounds of an extraction, 0 lifts a limit
+#[derive(Debug, Clone, Copy)]
+pub struct ExtractLimits {
+    // bytes written in total
+    pub max_size: u64,
+    pub max_entries: u64,
+    // bytes written per byte of archive
+    pub max_ratio: u64,
+}
+
+impl Default for ExtractLimits {
+    fn default() -> Self {
+        ExtractLimits {
+            max_size: DEFAULT_MAX_SIZE,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            max_ratio: DEFAULT_MAX_RATIO,
+        }
+    }
+}
+
+/// What an extraction used of its limits. Bytes are counted as they are written,
+/// the sizes archives declare are not trusted
+#[derive(Debug)]
+pub struct Budget {
+    limits: ExtractLimits,
+    archive_size: u64,
+    entries: u64,
+    written: u64,
+}
+
+impl Budget {
+    pub fn new(limits: ExtractLimits, archive_size: u64) -> Budget {
+        Budget {
+            limits,
+            archive_size,
+            entries: 0,
+            written: 0,
+        }
+    }
+
+    pub fn add_entry(&mut self) -> Result<(), CompressionError> {
+        self.entries += 1;
+        if self.limits.max_entries > 0 && self.entries > self.limits.max_entries {
+            return Err(CompressionError::TooManyEntries {
+                max: self.limits.max_entries,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn add_bytes(&mut self, bytes: u64) -> Result<(), CompressionError> {
+        self.written += bytes;
+        if self.limits.max_size > 0 && self.written > self.limits.max_size {
+            return Err(CompressionError::TooLarge {
+                max: format_size(self.limits.max_size),
+            });
+        }
+        if self.limits.max_ratio > 0
+            && self.written > RATIO_FLOOR
+            && self.written / self.archive_size.max(1) >= self.limits.max_ratio
+        {
+            return Err(CompressionError::RatioExceeded {
+                max: self.limits.max_ratio,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Parses sizes such as "4096", "500K", "20M" or "10G" into bytes, 1K being 1024 bytes
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(split) => value.split_at(split),
+        None => (value, ""),
+    };
+
+    let unit_name = unit.to_ascii_uppercase();
+    let prefix = unit_name
+        .strip_suffix("IB")
+        .or_else(|| unit_name.strip_suffix('B'))
+        .unwrap_or(&unit_name);
+    let multiplier: u64 = match prefix {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("unknown unit '{}', use K, M, G or T", unit)),
+    };
+    let amount: u64 = number
+        .parse()
+        .map_err(|_| format!("missing number before '{}'", unit))?;
+    amount
+        .checked_mul(multiplier)
+        .ok_or_else(|| "size too large".to_string())
+}
@@ -0,0 +1,286 @@
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use flate2::read::GzDecoder;
+use sevenz_rust::{Password, SevenZMethod, SevenZReader};
+use time::OffsetDateTime;
+use xz2::read::XzDecoder;
+use zip::result::ZipError;
+
+use super::{format::Format, CompressionError};
+use crate::utils::prompt;
+
+// set in the attributes of 7z entries holding a unix mode in their upper 16 bits
+const SEVENZ_UNIX_EXTENSION: u32 = 0x8000;
+// longest link target read from zip and 7z entries, the PATH_MAX of linux
+const MAX_LINK_TARGET: u64 = 4096;
+
+/// What an archive entry holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryType {
+    File,
+    Directory,
+    Symlink { target: String },
+}
+
+/// An entry of an archive being read, the content of a file is read from `reader`
+pub struct ArchiveEntry<'a> {
+    // as stored in the archive, not checked yet
+    pub name: String,
+    pub entry_type: EntryType,
+    pub mode: Option<u32>,
+    pub modified: Option<SystemTime>,
+    pub size: u64,
+    // unknown for entries compressed together, as in tars and solid 7z archives
+    pub compressed_size: Option<u64>,
+    pub crc32: Option<u32>,
+    // only zip entries are encrypted, `reader` gives their raw content when no password is asked
+    pub encrypted: bool,
+    pub reader: &'a mut dyn Read,
+}
+
+/// Password of the encrypted entries of a zip, AES or legacy ZipCrypto
+#[derive(Debug)]
+pub enum ZipPassword {
+    // asked for when the first encrypted entry is read, unless already known
+    Ask(Option<String>),
+    // encrypted entries are not decrypted, for listing them
+    Skip,
+}
+
+impl ZipPassword {
+    fn get(&mut self) -> Result<Option<&[u8]>, CompressionError> {
+        match self {
+            ZipPassword::Skip => Ok(None),
+            ZipPassword::Ask(password) => {
+                if password.is_none() {
+                    *password = Some(prompt::password("Enter the password of the archive")?);
+                }
+                Ok(password.as_deref().map(str::as_bytes))
+            }
+        }
+    }
+}
+
+pub fn is_symlink_mode(mode: u32) -> bool {
+    mode & 0o170000 == 0o120000
+}
+
+// the target held by a link entry, whatever size the archive declares for it
+fn read_link_target(reader: &mut dyn Read, name: &str) -> Result<String, CompressionError> {
+    let mut target = Vec::new();
+    reader.take(MAX_LINK_TARGET + 1).read_to_end(&mut target)?;
+
+    if target.len() as u64 > MAX_LINK_TARGET {
+        return Err(CompressionError::LongLinkTarget {
+            name: name.to_string(),
+            max: MAX_LINK_TARGET,
+        });
+    }
+    Ok(String::from_utf8(target).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?)
+}
+
+/// Calls `each` on every entry of the archive, in the order they are stored
+pub fn for_each_entry<R, F>(
+    reader: R,
+    format: Format,
+    password: &mut ZipPassword,
+    each: F,
+) -> Result<(), CompressionError>
+where
+    R: Read + Seek,
+    F: FnMut(ArchiveEntry) -> Result<(), CompressionError>,
+{
+    match format {
+        Format::Zip => read_zip(reader, password, each),
+        Format::Tar => read_tar(reader, each),
+        Format::TarGz => read_tar(GzDecoder::new(reader), each),
+        Format::TarZst => read_tar(zstd::Decoder::new(reader)?, each),
+        Format::TarXz => read_tar(XzDecoder::new(reader), each),
+        Format::SevenZ => read_sevenz(reader, each),
+    }
+}
+
+fn read_zip<R, F>(
+    reader: R,
+    password: &mut ZipPassword,
+    mut each: F,
+) -> Result<(), CompressionError>
+where
+    R: Read + Seek,
+    F: FnMut(ArchiveEntry) -> Result<(), CompressionError>,
+{
+    let mut archive = zip::ZipArchive::new(reader)?;
+    for i in 0..archive.len() {
+        let encrypted = archive.by_index_raw(i)?.encrypted();
+        let (mut file, readable) = match encrypted {
+            false => (archive.by_index(i)?, true),
+            true => match password.get()? {
+                Some(password) => match archive.by_index_decrypt(i, password) {
+                    Ok(file) => (file, true),
+                    Err(ZipError::InvalidPassword) => return Err(CompressionError::WrongPassword),
+                    Err(e) => return Err(e.into()),
+                },
+                None => (archive.by_index_raw(i)?, false),
+            },
+        };
+        let mode = file.unix_mode();
+        let name = file.name().to_string();
+
+        // links are stored as files holding their target, unknown while encrypted
+        let entry_type = if name.ends_with('/') {
+            EntryType::Directory
+        } else if mode.is_some_and(is_symlink_mode) && !readable {
+            EntryType::Symlink {
+                target: String::new(),
+            }
+        } else if mode.is_some_and(is_symlink_mode) {
+            EntryType::Symlink {
+                target: read_link_target(&mut file, &name)?,
+            }
+        } else {
+            EntryType::File
+        };
+
+        each(ArchiveEntry {
+            name,
+            entry_type,
+            mode,
+            modified: file
+                .last_modified()
+                .and_then(|modified| OffsetDateTime::try_from(modified).ok())
+                .map(SystemTime::from),
+            size: file.size(),
+            compressed_size: Some(file.compressed_size()),
+            crc32: Some(file.crc32()),
+            encrypted,
+            reader: &mut file,
+        })?;
+    }
+    Ok(())
+}
+
+// hard links and special files are left out
+fn read_tar<R, F>(reader: R, mut each: F) -> Result<(), CompressionError>
+where
+    R: Read,
+    F: FnMut(ArchiveEntry) -> Result<(), CompressionError>,
+{
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let header = entry.header();
+
+        let entry_type = match header.entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous => EntryType::File,
+            tar::EntryType::Directory => EntryType::Directory,
+            tar::EntryType::Symlink => match entry.link_name()? {
+                Some(target) => EntryType::Symlink {
+                    target: target.to_string_lossy().to_string(),
+                },
+                None => continue,
+            },
+            _ => continue,
+        };
+        let mode = header.mode().ok();
+        let modified = header
+            .mtime()
+            .ok()
+            .map(|mtime| UNIX_EPOCH + Duration::from_secs(mtime));
+        let size = header.size().unwrap_or_default();
+        let name = entry.path()?.to_string_lossy().to_string();
+
+        each(ArchiveEntry {
+            name,
+            entry_type,
+            mode,
+            modified,
+            size,
+            compressed_size: None,
+            crc32: None,
+            encrypted: false,
+            reader: &mut entry,
+        })?;
+    }
+
+    // the end of a compressed tar is read too, its checksum is only checked there
+    io::copy(&mut archive.into_inner(), &mut io::sink())?;
+    Ok(())
+}
+
+fn read_sevenz<R, F>(mut reader: R, mut each: F) -> Result<(), CompressionError>
+where
+    R: Read + Seek,
+    F: FnMut(ArchiveEntry) -> Result<(), CompressionError>,
+{
+    let length = reader.seek(SeekFrom::End(0))?;
+    reader.rewind()?;
+    let mut archive = SevenZReader::new(reader, length, Password::empty()).map_err(sevenz_error)?;
+
+    // the first error of `each` stops the archive, which only reports its own
+    let mut result = Ok(());
+    archive
+        .for_each_entries(|entry, reader| {
+            if entry.is_anti_item {
+                return Ok(true);
+            }
+
+            let mode = match entry.has_windows_attributes
+                && entry.windows_attributes() & SEVENZ_UNIX_EXTENSION != 0
+            {
+                true => Some(entry.windows_attributes() >> 16),
+                false => None,
+            };
+            let entry_type = if entry.is_directory() {
+                EntryType::Directory
+            } else if mode.is_some_and(is_symlink_mode) {
+                match read_link_target(reader, entry.name()) {
+                    Ok(target) => EntryType::Symlink { target },
+                    Err(e) => {
+                        result = Err(e);
+                        return Ok(false);
+                    }
+                }
+            } else {
+                EntryType::File
+            };
+
+            result = each(ArchiveEntry {
+                name: entry.name().to_string(),
+                entry_type,
+                mode,
+                modified: match entry.has_last_modified_date {
+                    true => Some(entry.last_modified_date().into()),
+                    false => None,
+                },
+                size: entry.size(),
+                compressed_size: None,
+                crc32: match entry.has_crc {
+                    true => Some(entry.crc as u32),
+                    false => None,
+                },
+                encrypted: false,
+                reader,
+            });
+            Ok(result.is_ok())
+        })
+        .map_err(sevenz_error)?;
+    result
+}
+
+// the AES coder of 7z is not built in, password protected archives cannot be read
+fn sevenz_error(error: sevenz_rust::Error) -> CompressionError {
+    match error {
+        sevenz_rust::Error::PasswordRequired | sevenz_rust::Error::MaybeBadPassword(_) => {
+            CompressionError::EncryptedSevenZ
+        }
+        sevenz_rust::Error::UnsupportedCompressionMethod(method)
+            if method == SevenZMethod::AES256SHA256.name() =>
+        {
+            CompressionError::EncryptedSevenZ
+        }
+        error => error.into(),
+    }
+}
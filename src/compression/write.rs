@@ -0,0 +1,335 @@
+use std::{
+    fs::{self, File},
+    io::{self, Seek, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use flate2::write::GzEncoder;
+use time::OffsetDateTime;
+use xz2::write::XzEncoder;
+use zip::{write::FileOptions, AesMode, CompressionMethod, DateTime, ZipWriter};
+
+use super::{format::Format, CompressionError};
+use crate::encryption::spool::Spool;
+use crate::utils::walk::PathFilter;
+
+// levels used when none is given, as the zip crate does for its methods
+const DEFAULT_GZIP_LEVEL: u32 = 6;
+const DEFAULT_XZ_LEVEL: u32 = 6;
+// zero selects the default of the zstd library
+const DEFAULT_ZSTD_LEVEL: i32 = 0;
+
+/// Methods a zip compresses its files with
+#[derive(Debug, Clone, Copy)]
+pub enum Method {
+    Stored,
+    Deflate,
+    Bzip2,
+    Zstd,
+}
+
+impl Method {
+    pub const NAMES: [&'static str; 4] = ["stored", "deflate", "bzip2", "zstd"];
+
+    pub fn from_name(name: &str) -> Option<Method> {
+        match name {
+            "stored" => Some(Method::Stored),
+            "deflate" => Some(Method::Deflate),
+            "bzip2" => Some(Method::Bzip2),
+            "zstd" => Some(Method::Zstd),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Method::Stored => "stored",
+            Method::Deflate => "deflate",
+            Method::Bzip2 => "bzip2",
+            Method::Zstd => "zstd",
+        }
+    }
+
+    // levels the method accepts, none when it does not compress
+    fn levels(&self) -> Option<RangeInclusive<i32>> {
+        match self {
+            Method::Stored => None,
+            Method::Deflate => Some(0..=9),
+            Method::Bzip2 => Some(1..=9),
+            Method::Zstd => Some(1..=22),
+        }
+    }
+
+    fn zip_method(&self) -> CompressionMethod {
+        match self {
+            Method::Stored => CompressionMethod::Stored,
+            Method::Deflate => CompressionMethod::Deflated,
+            Method::Bzip2 => CompressionMethod::Bzip2,
+            Method::Zstd => CompressionMethod::Zstd,
+        }
+    }
+}
+
+/// How an archive is written, the level is checked against the method of a zip
+/// or the compressor of a tar
+#[derive(Debug, Clone)]
+pub struct Compression {
+    format: Format,
+    // only used by zips, tars are compressed as a whole by their format
+    method: Method,
+    // None for the default level of the method
+    level: Option<i32>,
+    // encrypts the content of the files of a zip with AES-256, as WinZip does
+    password: Option<String>,
+}
+
+impl Compression {
+    pub fn new(
+        format: Format,
+        method: Option<Method>,
+        level: Option<i32>,
+    ) -> Result<Compression, CompressionError> {
+        let (method, levels, compressor) = match (format, method) {
+            (Format::SevenZ, _) => return Err(CompressionError::ReadOnlyFormat { format }),
+            (Format::Zip, method) => {
+                let method = method.unwrap_or(Method::Deflate);
+                (method, method.levels(), format!("{} method", method.name()))
+            }
+            (_, Some(_)) => return Err(CompressionError::NoMethod { format }),
+            (_, None) => (
+                Method::Stored,
+                format.tar_levels(),
+                format!("{} format", format.name()),
+            ),
+        };
+
+        if let Some(level) = level {
+            match levels {
+                None => return Err(CompressionError::NoLevel { compressor }),
+                Some(levels) if !levels.contains(&level) => {
+                    return Err(CompressionError::UnsupportedLevel {
+                        compressor,
+                        level,
+                        min: *levels.start(),
+                        max: *levels.end(),
+                    })
+                }
+                Some(_) => (),
+            }
+        }
+        Ok(Compression {
+            format,
+            method,
+            level,
+            password: None,
+        })
+    }
+
+    /// Protects the files of a zip with `password`, other formats ignore it
+    pub fn with_password(self, password: String) -> Compression {
+        Compression {
+            password: Some(password),
+            ..self
+        }
+    }
+
+    fn zip_options(&self) -> FileOptions<'_, ()> {
+        FileOptions::default()
+            .compression_method(self.method.zip_method())
+            .compression_level(self.level.map(i64::from))
+    }
+}
+
+/// A path to archive, with the name it is stored under
+#[derive(Debug)]
+pub struct SourceEntry {
+    pub path: PathBuf,
+    pub name: PathBuf,
+    pub metadata: fs::Metadata,
+}
+
+impl SourceEntry {
+    // zip paths are separated by forward slashes on every platform
+    fn zip_name(&self) -> String {
+        self.name
+            .iter()
+            .map(|component| component.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+/// Lists the source and, for a directory, every path under it accepted by `filter`, in name
+/// order. Entries are named from the parent of the source, so that the archive extracts to a
+/// single file or directory whatever path the source was given with. The source itself is
+/// followed if it is a symlink, the links inside are listed as links
+pub fn source_entries(
+    source_path: &Path,
+    filter: &PathFilter,
+) -> Result<Vec<SourceEntry>, CompressionError> {
+    let source_path = fs::canonicalize(source_path)?;
+    let root_name = match source_path.file_name() {
+        Some(name) => PathBuf::from(name),
+        None => {
+            return Err(CompressionError::Custom {
+                src: "Invalid file path".to_string(),
+            })
+        }
+    };
+
+    let mut entries = vec![];
+    let mut path_queue = vec![(source_path, root_name.clone())];
+    while let Some((path, name)) = path_queue.pop() {
+        let metadata = fs::symlink_metadata(&path)?;
+
+        if metadata.is_dir() {
+            let mut children = fs::read_dir(&path)?.collect::<Result<Vec<_>, _>>()?;
+            children.sort_by_key(|child| child.file_name());
+            // pushed in reverse to be listed in name order
+            for child in children.into_iter().rev() {
+                let child_name = name.join(child.file_name());
+                let relative_path = child_name.strip_prefix(&root_name).unwrap_or(&child_name);
+
+                let is_dir = child.file_type()?.is_dir();
+                if (is_dir && !filter.is_excluded(relative_path))
+                    || (!is_dir && filter.is_included(relative_path))
+                {
+                    path_queue.push((child.path(), child_name));
+                }
+            }
+        }
+        entries.push(SourceEntry {
+            path,
+            name,
+            metadata,
+        });
+    }
+    Ok(entries)
+}
+
+/// Writes the entries as an archive of the format of `compression`, returns the writer
+/// with the number of bytes archived
+pub fn write_archive<W: Write + Seek>(
+    entries: &[SourceEntry],
+    writer: W,
+    compression: &Compression,
+) -> Result<(W, u64), CompressionError> {
+    match compression.format {
+        Format::Zip => write_zip(entries, writer, compression),
+        _ => stream_archive(entries, writer, compression),
+    }
+}
+
+/// Writes the entries as an archive to a writer that cannot seek. Tars are streamed, zips are
+/// first written to a spool as the header of each of their files is only completed once its
+/// content is written
+pub fn stream_archive<W: Write>(
+    entries: &[SourceEntry],
+    mut writer: W,
+    compression: &Compression,
+) -> Result<(W, u64), CompressionError> {
+    let level = compression.level;
+    match compression.format {
+        Format::Zip => {
+            let (mut spool, original) = write_zip(entries, Spool::new()?, compression)?;
+            spool.rewind()?;
+            io::copy(&mut spool, &mut writer)?;
+            Ok((writer, original))
+        }
+        Format::Tar => write_tar(entries, writer),
+        Format::TarGz => {
+            let level = level.map_or(DEFAULT_GZIP_LEVEL, |level| level as u32);
+            let encoder = GzEncoder::new(writer, flate2::Compression::new(level));
+            let (encoder, original) = write_tar(entries, encoder)?;
+            Ok((encoder.finish()?, original))
+        }
+        Format::TarZst => {
+            let encoder = zstd::Encoder::new(writer, level.unwrap_or(DEFAULT_ZSTD_LEVEL))?;
+            let (encoder, original) = write_tar(entries, encoder)?;
+            Ok((encoder.finish()?, original))
+        }
+        Format::TarXz => {
+            let level = level.map_or(DEFAULT_XZ_LEVEL, |level| level as u32);
+            let encoder = XzEncoder::new(writer, level);
+            let (encoder, original) = write_tar(entries, encoder)?;
+            Ok((encoder.finish()?, original))
+        }
+        format @ Format::SevenZ => Err(CompressionError::ReadOnlyFormat { format }),
+    }
+}
+
+fn write_zip<W: Write + Seek>(
+    entries: &[SourceEntry],
+    writer: W,
+    compression: &Compression,
+) -> Result<(W, u64), CompressionError> {
+    let mut zip = ZipWriter::new(writer);
+    let mut original = 0;
+
+    for entry in entries {
+        let options = zip_entry_options(&entry.metadata, compression);
+
+        if entry.metadata.is_symlink() {
+            let target = fs::read_link(&entry.path)?;
+            zip.add_symlink(entry.zip_name(), target.to_string_lossy(), options)?;
+        } else if entry.metadata.is_dir() {
+            zip.add_directory(entry.zip_name(), options)?;
+        } else {
+            zip.start_file(entry.zip_name(), options)?;
+            let mut file = File::open(&entry.path)?;
+            original += io::copy(&mut file, &mut zip)?;
+        }
+    }
+    Ok((zip.finish()?, original))
+}
+
+// keeps the modification time and the permissions of the entry. Times are stored
+// in UTC, as the zip crate does for entries without one. Only files are encrypted, most
+// tools cannot read encrypted links
+fn zip_entry_options<'k>(
+    metadata: &fs::Metadata,
+    compression: &'k Compression,
+) -> FileOptions<'k, ()> {
+    let mut options = compression
+        .zip_options()
+        .large_file(metadata.len() > u32::MAX as u64);
+    if let Some(password) = compression
+        .password
+        .as_deref()
+        .filter(|_| metadata.is_file())
+    {
+        options = options.with_aes_encryption(AesMode::Aes256, password);
+    }
+
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| DateTime::try_from(OffsetDateTime::from(modified)).ok());
+    if let Some(modified) = modified {
+        options = options.last_modified_time(modified);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        options = options.unix_permissions(metadata.permissions().mode());
+    }
+    options
+}
+
+// tar headers keep the type, permissions, owner and modification time of each path
+fn write_tar<W: Write>(entries: &[SourceEntry], writer: W) -> Result<(W, u64), CompressionError> {
+    let mut tar = tar::Builder::new(writer);
+    tar.follow_symlinks(false);
+    let mut original = 0;
+
+    for entry in entries {
+        tar.append_path_with_name(&entry.path, &entry.name)?;
+        if entry.metadata.is_file() {
+            original += entry.metadata.len();
+        }
+    }
+    Ok((tar.into_inner()?, original))
+}
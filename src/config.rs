@@ -0,0 +1,595 @@
+use std::{fs, io::Error, path::Path};
+
+use argon2::password_hash::PasswordHash;
+use clap::ArgMatches;
+use custom_error::custom_error;
+use tempfile::Builder;
+use toml::{Table, Value};
+
+use crate::{
+    password::{require_password, unlock_gate, GATEABLE_COMMANDS},
+    print_err, print_info, print_solution, print_success, print_warn,
+    utils::{
+        config::{legacy_config_path, legacy_lines, parse_table, Config, LEGACY_KEYS},
+        editor::{edit_file, EditorError},
+        get_config_path,
+        line::Line,
+        GenericError,
+    },
+};
+
+custom_error! {pub ConfigError
+    Io{source: Error} = "{source}",
+    Generic{source: GenericError} = "{source}",
+    UnknownKey{key: String} = "Unknown setting \"{key}\"",
+    SecretKey{key: String} = "\"{key}\" holds secrets and can only be changed by the command managing it",
+    ReadOnlyKey{key: String} = "\"{key}\" is managed by mucli",
+    InvalidValue{key: String, message: String} = "Invalid value for \"{key}\": {message}",
+    Editor{source: EditorError} = "{source}",
+    InvalidConfig = "The edited config is invalid, nothing was saved"
+}
+
+// settings holding keys or passwords, redacted unless --show-secrets is given
+const SECRET_KEYS: [&str; 6] = [
+    "encryption.keys",
+    "encryption.wrapped_keys",
+    "encryption.identities",
+    "encryption.wrapped_identities",
+    "password.hash",
+    "password.questions",
+];
+const READ_ONLY_KEYS: [&str; 3] = [
+    "version",
+    "password.failed_answers",
+    "password.locked_until",
+];
+const REDACTED: &str = "<redacted>";
+
+/// Problem found in a config file by `config validate`
+struct Issue {
+    error: bool,
+    message: String,
+}
+
+impl Issue {
+    fn error(message: String) -> Self {
+        Issue {
+            error: true,
+            message,
+        }
+    }
+
+    fn warning(message: String) -> Self {
+        Issue {
+            error: false,
+            message,
+        }
+    }
+}
+
+pub fn config_command(sub_matches: &ArgMatches) {
+    match sub_matches.subcommand() {
+        Some(("list", sub_matches)) => list_command(sub_matches),
+        Some(("get", sub_matches)) => get_command(sub_matches),
+        Some(("set", sub_matches)) => set_command(sub_matches),
+        Some(("unset", sub_matches)) => unset_command(sub_matches),
+        Some(("path", _)) => path_command(),
+        Some(("edit", _)) => edit_command(),
+        Some(("validate", _)) => validate_command(),
+        _ => unreachable!("Exhausted list of subcommands and subcommand_required prevents `None`"),
+    }
+}
+
+fn list_command(sub_matches: &ArgMatches) {
+    let show_secrets = sub_matches.get_flag("show-secrets");
+    if show_secrets && !unlock_gate("config secrets") {
+        return;
+    }
+
+    let entries = match entries() {
+        Ok(entries) => entries,
+        Err(e) => {
+            print_err!("{}", e);
+            return;
+        }
+    };
+
+    for (key, value) in entries {
+        println!("{} = {}", key, display_value(&key, &value, show_secrets));
+    }
+}
+
+fn get_command(sub_matches: &ArgMatches) {
+    let key = match sub_matches.get_one::<String>("KEY") {
+        Some(key) => key,
+        None => return,
+    };
+    let show_secrets = sub_matches.get_flag("show-secrets");
+    if show_secrets && !unlock_gate("config secrets") {
+        return;
+    }
+
+    let entries = match entries() {
+        Ok(entries) => entries,
+        Err(e) => {
+            print_err!("{}", e);
+            return;
+        }
+    };
+
+    // a section prints every setting below it
+    let section = format!("{}.", key);
+    let matching: Vec<(String, Value)> = entries
+        .into_iter()
+        .filter(|(entry, _)| entry == key || entry.starts_with(&section))
+        .collect();
+
+    match matching.as_slice() {
+        [] => print_info!("\"{}\" is not set", key),
+        [(entry, value)] if entry == key => match value {
+            Value::String(value) if show_secrets || !is_secret(key) => println!("{}", value),
+            value => println!("{}", display_value(key, value, show_secrets)),
+        },
+        _ => {
+            for (entry, value) in &matching {
+                println!("{} = {}", entry, display_value(entry, value, show_secrets));
+            }
+        }
+    }
+}
+
+fn set_command(sub_matches: &ArgMatches) {
+    let (key, value) = match (
+        sub_matches.get_one::<String>("KEY"),
+        sub_matches.get_one::<String>("VALUE"),
+    ) {
+        (Some(key), Some(value)) => (key, value),
+        _ => return,
+    };
+    if is_password_key(key) && !require_password() {
+        return;
+    }
+
+    match set_value(key, value) {
+        Ok(_) => print_success!("\"{}\" set", key),
+        Err(e) => print_config_error(e),
+    }
+}
+
+fn unset_command(sub_matches: &ArgMatches) {
+    let key = match sub_matches.get_one::<String>("KEY") {
+        Some(key) => key,
+        None => return,
+    };
+    if is_password_key(key) && !require_password() {
+        return;
+    }
+
+    match unset_value(key) {
+        Ok(true) => print_success!("\"{}\" unset", key),
+        Ok(false) => print_info!("\"{}\" is not set", key),
+        Err(e) => print_config_error(e),
+    }
+}
+
+fn path_command() {
+    match get_config_path() {
+        Ok(path) => println!("{}", path.display()),
+        Err(e) => print_err!("{}", e),
+    }
+}
+
+fn edit_command() {
+    // the edited file shows every secret and can change the password settings,
+    // so the password is asked whether "config secrets" is gated or not
+    if !require_password() {
+        return;
+    }
+
+    match edit_config() {
+        Ok(_) => print_success!("Config saved"),
+        Err(e) => print_err!("{}", e),
+    }
+}
+
+fn validate_command() {
+    let issues = match validate() {
+        Ok(issues) => issues,
+        Err(e) => {
+            print_err!("{}", e);
+            return;
+        }
+    };
+    print_issues(&issues);
+
+    let errors = issues.iter().filter(|issue| issue.error).count();
+    if errors > 0 {
+        print_err!(
+            "{} error(s) and {} warning(s) found",
+            errors,
+            issues.len() - errors
+        );
+    } else if !issues.is_empty() {
+        print_success!("Config is valid, with {} warning(s)", issues.len());
+    } else {
+        print_success!("Config is valid");
+    }
+}
+
+fn print_config_error(e: ConfigError) {
+    print_err!("{}", e);
+    match e {
+        ConfigError::SecretKey { .. } => {
+            print_solution!("Use the \"password\" and \"keys\" commands instead")
+        }
+        ConfigError::UnknownKey { .. } => {
+            print_solution!("Use \"config list\" to see the available settings")
+        }
+        _ => (),
+    }
+}
+
+/// Every setting of the config as `section.key` paths
+fn entries() -> Result<Vec<(String, Value)>, ConfigError> {
+    let table = Table::try_from(Config::load()?).map_err(GenericError::from)?;
+    let mut entries = vec![];
+    flatten("", &table, &mut entries);
+    Ok(entries)
+}
+
+fn flatten(prefix: &str, table: &Table, entries: &mut Vec<(String, Value)>) {
+    for (key, value) in table {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match value {
+            Value::Table(table) => flatten(&path, table, entries),
+            value => entries.push((path, value.clone())),
+        }
+    }
+}
+
+fn display_value(key: &str, value: &Value, show_secrets: bool) -> String {
+    if is_secret(key) && !show_secrets {
+        return REDACTED.to_string();
+    }
+    value.to_string()
+}
+
+fn is_secret(key: &str) -> bool {
+    SECRET_KEYS
+        .iter()
+        .any(|secret| key == *secret || key.starts_with(&format!("{}.", secret)))
+}
+
+// the password settings choose what the password protects, so they cannot change without it
+fn is_password_key(key: &str) -> bool {
+    key == "password" || key.starts_with("password.")
+}
+
+// sections containing secrets cannot be changed as a whole either
+fn check_writable(key: &str) -> Result<(), ConfigError> {
+    if key.split('.').any(str::is_empty) {
+        return Err(ConfigError::UnknownKey {
+            key: key.to_string(),
+        });
+    }
+    if READ_ONLY_KEYS.contains(&key) {
+        return Err(ConfigError::ReadOnlyKey {
+            key: key.to_string(),
+        });
+    }
+    if is_secret(key)
+        || SECRET_KEYS
+            .iter()
+            .any(|secret| secret.starts_with(&format!("{}.", key)))
+    {
+        return Err(ConfigError::SecretKey {
+            key: key.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn set_value(key: &str, raw_value: &str) -> Result<(), ConfigError> {
+    check_writable(key)?;
+
+    // the value is read as TOML when possible ("true", "30", "[1, 2]"),
+    // and as a plain string otherwise or when the setting expects one
+    let mut candidates = vec![];
+    if let Ok(mut table) = format!("value = {}", raw_value).parse::<Table>() {
+        if let Some(value) = table.remove("value") {
+            candidates.push(value);
+        }
+    }
+    candidates.push(Value::String(raw_value.to_string()));
+
+    Config::update(|config| {
+        let table = Table::try_from(&*config).map_err(GenericError::from)?;
+
+        let mut first_error = None;
+        for value in candidates {
+            let mut table = table.clone();
+            insert(&mut table, key, value)?;
+
+            match table.try_into::<Config>() {
+                Ok(updated) => {
+                    // settings unknown to mucli are dropped when deserializing
+                    let updated_table = Table::try_from(&updated).map_err(GenericError::from)?;
+                    if lookup(&updated_table, key).is_none() {
+                        return Err(ConfigError::UnknownKey {
+                            key: key.to_string(),
+                        });
+                    }
+                    *config = updated;
+                    return Ok(());
+                }
+                Err(e) => {
+                    first_error.get_or_insert(e.message().to_string());
+                }
+            }
+        }
+
+        Err(ConfigError::InvalidValue {
+            key: key.to_string(),
+            message: first_error.unwrap_or_default(),
+        })
+    })
+}
+
+fn unset_value(key: &str) -> Result<bool, ConfigError> {
+    check_writable(key)?;
+
+    Config::update(|config| {
+        let mut table = Table::try_from(&*config).map_err(GenericError::from)?;
+
+        let (parents, name) = key.rsplit_once('.').unwrap_or(("", key));
+        let parent = if parents.is_empty() {
+            Some(&mut table)
+        } else {
+            parents.split('.').try_fold(&mut table, |table, part| {
+                table.get_mut(part)?.as_table_mut()
+            })
+        };
+        let removed = match parent {
+            Some(parent) => parent.remove(name).is_some(),
+            None => false,
+        };
+
+        if removed {
+            *config = table.try_into().map_err(GenericError::from)?;
+        }
+        Ok(removed)
+    })
+}
+
+fn insert(table: &mut Table, key: &str, value: Value) -> Result<(), ConfigError> {
+    let (parents, name) = key.rsplit_once('.').unwrap_or(("", key));
+
+    let mut current = table;
+    for part in parents.split('.').filter(|part| !part.is_empty()) {
+        current = match current
+            .entry(part)
+            .or_insert_with(|| Value::Table(Table::new()))
+        {
+            Value::Table(table) => table,
+            _ => {
+                return Err(ConfigError::UnknownKey {
+                    key: key.to_string(),
+                })
+            }
+        };
+    }
+
+    current.insert(name.to_string(), value);
+    Ok(())
+}
+
+fn lookup<'a>(table: &'a Table, key: &str) -> Option<&'a Value> {
+    let (parents, name) = key.rsplit_once('.').unwrap_or(("", key));
+    let parent = parents
+        .split('.')
+        .filter(|part| !part.is_empty())
+        .try_fold(table, |table, part| table.get(part)?.as_table())?;
+    parent.get(name)
+}
+
+fn edit_config() -> Result<(), ConfigError> {
+    let config_path = get_config_path()?;
+
+    // the config stays locked while it is edited
+    Config::edit(|content| {
+        let dir = config_path.parent().unwrap_or(Path::new("."));
+        let temp_file = Builder::new().suffix(".toml").tempfile_in(dir)?;
+        fs::write(temp_file.path(), content)?;
+
+        edit_file(temp_file.path())?;
+
+        let edited = fs::read_to_string(temp_file.path())?;
+        let issues = validate_content(&edited, &config_path);
+        print_issues(&issues);
+        if issues.iter().any(|issue| issue.error) {
+            return Err(ConfigError::InvalidConfig);
+        }
+        Ok(edited)
+    })
+}
+
+fn validate() -> Result<Vec<Issue>, ConfigError> {
+    let path = get_config_path()?;
+    if path.exists() {
+        return Ok(validate_content(&fs::read_to_string(&path)?, &path));
+    }
+
+    // checked as is, without migrating it
+    if let Some(legacy_path) = legacy_config_path()?.filter(|legacy_path| legacy_path.exists()) {
+        print_info!(
+            "Checking {:?}, it will be migrated to {:?} on next use",
+            legacy_path,
+            path
+        );
+        return Ok(validate_legacy(&fs::read_to_string(&legacy_path)?));
+    }
+
+    print_info!("No config file yet, default settings are used");
+    Ok(vec![])
+}
+
+fn validate_content(content: &str, path: &Path) -> Vec<Issue> {
+    // duplicate keys are syntax errors in TOML
+    let raw: Table = match toml::from_str(content) {
+        Ok(raw) => raw,
+        Err(e) => return vec![Issue::error(e.to_string().trim_end().to_string())],
+    };
+
+    let mut issues = vec![];
+    if !raw.contains_key("version") {
+        issues.push(Issue::warning(
+            "\"version\" is missing, version 1 is assumed".to_string(),
+        ));
+    }
+
+    // settings of older versions are checked once migrated
+    let migrated = match parse_table(content, path) {
+        Ok(migrated) => migrated,
+        Err(e) => {
+            issues.push(Issue::error(e.to_string().trim_end().to_string()));
+            return issues;
+        }
+    };
+    let config: Config = match migrated.clone().try_into() {
+        Ok(config) => config,
+        Err(e) => {
+            issues.push(Issue::error(
+                GenericError::from(e).to_string().trim_end().to_string(),
+            ));
+            return issues;
+        }
+    };
+
+    // settings unknown to mucli would be dropped on the next write
+    let mut known = vec![];
+    if let Ok(table) = Table::try_from(&config) {
+        flatten("", &table, &mut known);
+    }
+    let mut entries = vec![];
+    flatten("", &migrated, &mut entries);
+    for (key, _) in entries {
+        if !known.iter().any(|(known_key, _)| *known_key == key) {
+            issues.push(Issue::warning(format!(
+                "Unknown setting \"{}\" is ignored",
+                key
+            )));
+        }
+    }
+
+    let password = &config.password;
+    if let Some(hash) = &password.hash {
+        if PasswordHash::new(hash).is_err() {
+            issues.push(Issue::error(
+                "\"password.hash\" is not a valid password hash".to_string(),
+            ));
+        }
+    }
+    if password.questions.is_some() && password.hash.is_none() {
+        issues.push(Issue::warning(
+            "\"password.questions\" is set but no password is".to_string(),
+        ));
+    }
+    let questions = password.questions.as_ref().map_or(0, Vec::len);
+    if password.required_answers == Some(0) {
+        issues.push(Issue::warning(
+            "\"password.required_answers\" is 0, 1 right answer is required anyway".to_string(),
+        ));
+    }
+    if questions > 0 && questions < password.required_answers() {
+        issues.push(Issue::warning(format!(
+            "Only {} recovery question(s) for {} required answers, the password cannot be reset",
+            questions,
+            password.required_answers()
+        )));
+    }
+    for command in password.gated_commands.iter().flatten() {
+        if !GATEABLE_COMMANDS.contains(&command.as_str()) {
+            issues.push(Issue::warning(format!(
+                "\"{}\" in \"password.gated_commands\" is not a command that can be gated",
+                command
+            )));
+        }
+    }
+
+    let encryption = &config.encryption;
+    if encryption.keys.is_some() && encryption.wrapped_keys.is_some() {
+        issues.push(Issue::warning(
+            "Both \"encryption.keys\" and \"encryption.wrapped_keys\" are set, only the protected keys are used".to_string(),
+        ));
+    }
+    if encryption.identities.is_some() && encryption.wrapped_identities.is_some() {
+        issues.push(Issue::warning(
+            "Both \"encryption.identities\" and \"encryption.wrapped_identities\" are set, only the protected identities are used".to_string(),
+        ));
+    }
+    if encryption.wrapped_identities.is_some() && encryption.wrapped_keys.is_none() {
+        issues.push(Issue::error(
+            "\"encryption.wrapped_keys\" is missing, protected identities cannot be unlocked"
+                .to_string(),
+        ));
+    }
+
+    issues
+}
+
+fn validate_legacy(content: &str) -> Vec<Issue> {
+    let mut issues = vec![];
+    let mut seen: Vec<&str> = vec![];
+
+    for line in legacy_lines(content) {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let key = line.split('=').next().unwrap_or_default();
+        if !LEGACY_KEYS.contains(&key) {
+            issues.push(Issue::warning(format!(
+                "Unknown key \"{}\" will be dropped by the migration",
+                key
+            )));
+            continue;
+        }
+        if Line::<serde_json::Value>::from(line).is_err() {
+            issues.push(Issue::error(format!(
+                "The value of \"{}\" cannot be parsed",
+                key
+            )));
+        }
+
+        if seen.contains(&key) {
+            issues.push(Issue::warning(format!(
+                "\"{}\" is set more than once, the last value is used",
+                key
+            )));
+        } else {
+            seen.push(key);
+        }
+    }
+
+    if seen.contains(&"MUCLI_PASSWORD") && !seen.contains(&"MUCLI_KEY_PASSWORD") {
+        issues.push(Issue::error(
+            "\"MUCLI_KEY_PASSWORD\" is missing, the password cannot be read".to_string(),
+        ));
+    }
+
+    issues
+}
+
+fn print_issues(issues: &[Issue]) {
+    for issue in issues {
+        if issue.error {
+            print_err!("{}", issue.message);
+        } else {
+            print_warn!("{}", issue.message);
+        }
+    }
+}
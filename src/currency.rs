@@ -4,7 +4,10 @@ use dialoguer::{theme::ColorfulTheme, Input, MultiSelect, Select};
 use dotenv_codegen::dotenv;
 use serde::{Deserialize, Serialize};
 
-use crate::{print_err, print_success, utils::GenericError};
+use crate::{
+    print_err, print_success,
+    utils::{config::Config, GenericError},
+};
 
 const SUPPORTED_CURRENCIES: [&str; 33] = [
     "EUR", "USD", "JPY", "BGN", "CZK", "DKK", "GBP", "HUF", "PLN", "RON", "SEK", "CHF", "ISK",
@@ -62,9 +65,12 @@ pub async fn currency_command() {
 }
 
 async fn get_exchange_rates(base_curr: &str, output: Vec<&str>) -> Result<Response, GenericError> {
-    const API_KEY: &str = dotenv!("CURRENCY_API_KEY");
+    let api_key = Config::load()?
+        .currency
+        .api_key
+        .unwrap_or_else(|| dotenv!("CURRENCY_API_KEY").to_string());
     let output_curr = output.join("%2C");
-    let url = format!("https://api.freecurrencyapi.com/v1/latest?apikey={API_KEY}&currencies={output_curr}&base_currency={base_curr}");
+    let url = format!("https://api.freecurrencyapi.com/v1/latest?apikey={api_key}&currencies={output_curr}&base_currency={base_curr}");
 
     let text = reqwest::get(url).await?.text().await?;
 
@@ -1,4 +1,11 @@
 mod annex;
+pub mod backup;
+mod directory;
+pub mod keyring;
+pub mod recipient;
+pub mod rotate;
+pub mod spool;
+pub mod store;
 
 use std::{
     env::current_dir,
@@ -8,30 +15,35 @@ use std::{
 
 use clap::ArgMatches;
 use simplecrypt::DecryptionError;
+use x25519_dalek::PublicKey;
+
+pub use self::annex::{
+    decrypt_to_spool, encrypt_with, init_encryption_key, init_new_encryption_key,
+};
 
 use crate::{
     encryption::annex::{
-        encrypt_file, encrypt_file_x, encrypted_file_path, init_encryption_key,
-        init_new_encryption_key, purge_encryption_keys, update_file_encryption_key,
+        encrypt_file, encrypt_file_x, encrypted_file_path, purge_encryption_keys,
+        retrieve_encryption_keys, update_file_encryption_key,
     },
-    parse_config_line, print_err, print_solution, print_success,
-    utils::{config_interact::Config, line::LineError, GenericError},
+    password::unlock_gate,
+    print_err, print_solution, print_success,
+    utils::{get_config_path, line::LineError, prompt::PromptError, GenericError},
 };
 use custom_error::custom_error;
 
-const ENCRYPTION_KEYWORD: &str = "MUCLI_ENCRYPT";
-
 custom_error! {pub EncryptionError
     Io{source: Error} = "{source}",
     Generic{source: GenericError} = "{source}",
     Decrypt{source: DecryptionError} = "{source}",
     Line{source: LineError} = "{source}",
-    NoKeyFound = "No key found in config.txt",
+    Prompt{source: PromptError} = "{source}",
+    NoKeyFound{config: String} = "No key found in {config}",
     RetrievingKey = "Error retrieving encryption key",
     KeyNotExist = "Encryption key does not exist",
-    NoVersionFound = "No version found in config.txt",
+    NoVersionFound{config: String} = "No version found in {config}",
     NoVersionSet = "No version set for this file",
-    VersionFormat = "Invalid version format found in config.txt",
+    VersionFormat{config: String} = "Invalid version format found in {config}",
     EncryptionFailed{filename: String} = "Encryption of \"{filename}\" failed",
     ConfigNotFound = "Config file not found or unreadable",
     UpdateBeforeInit = "Cannot update the key. Please init first.",
@@ -40,23 +52,79 @@ custom_error! {pub EncryptionError
     DecryptNotCryptedFile = "Cannot decrypt a non-encrypted file",
     KeyUpdateFailed = "Impossible to update encryption key",
     CannotUpdateLatest = "File is already at the latest encryption version",
-    CannotProcessVoidFile = "Cannot process empty file"
+    CannotProcessVoidFile = "Cannot process empty file",
+    TruncatedFile = "Encrypted file is truncated",
+    UnsupportedFormat{version: u8} = "Unsupported encrypted file format version {version}",
+    UnsupportedAlgorithm{algorithm: u8, kdf: u8} = "Unsupported encryption algorithm ({algorithm}) or key derivation ({kdf})",
+    FileTooLarge = "File is too large to be encrypted",
+    AuthenticationFailed = "File integrity check failed, it was either tampered with or truncated",
+    WrongKeyringPassword = "Wrong password, cannot unlock encryption keys",
+    KeyringAlreadyProtected = "Encryption keys are already protected by your password",
+    KeyringNotProtected = "Encryption keys are not protected by a password",
+    InvalidRecipient{recipient: String} = "\"{recipient}\" is neither a mucli public key nor a file of public keys",
+    TooManyRecipients{max: usize} = "A file cannot be encrypted for more than {max} recipients",
+    NoMatchingIdentity = "None of your identities can decrypt this file",
+    IdentityExists{name: String} = "An identity named \"{name}\" already exists",
+    RecipientFileNoVersion = "File is encrypted for recipients, it does not use an encryption key version",
+    InvalidBackup = "Not a mucli key backup or recovery sheet",
+    UnsupportedBackup{version: u32} = "Unsupported key backup version {version}",
+    RecoveryChecksum = "Recovery sheet checksum mismatch, check for typos",
+    KeyringMismatch = "Backup keys differ from the local ones, importing them would make files unreadable",
+    InvalidJournal{path: String} = "Rotation journal \"{path}\" is unreadable"
 }
 
 use self::annex::{decrypt_file, decrypt_file_entirely, decrypted_file_path};
+use self::directory::{decrypt_directory_command, encrypt_directory_command};
+use self::recipient::parse_recipients;
+
+// the config the encryption keys are kept in, named by the errors about them
+fn config_name() -> String {
+    get_config_path()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| "the config".to_string())
+}
+
+fn recipient_values(sub_matches: &ArgMatches) -> Result<Vec<PublicKey>, EncryptionError> {
+    let values: Vec<String> = sub_matches
+        .get_many::<String>("recipient")
+        .map(|values| values.cloned().collect())
+        .unwrap_or_default();
+
+    parse_recipients(&values)
+}
 
 pub fn encrypt_command(sub_matches: &ArgMatches) {
+    if !unlock_gate("encrypt") {
+        return;
+    }
     if let Err(_) = init_encryption_key() {
         // initialize encryption key if 1st time using command
         print_err!("Error initializing encryption key!");
         return;
     }
+    let recipients = match recipient_values(sub_matches) {
+        Ok(recipients) => recipients,
+        Err(e) => {
+            print_err!("{}", e);
+            print_solution!("Public keys are printed by \"mucli keys generate\"");
+            return;
+        }
+    };
     if let Some(filepath) = sub_matches.get_one::<PathBuf>("FILEPATH") {
         let file_path: &Path = Path::new(filepath);
         if file_path.exists() {
-            if let true = sub_matches.get_flag("sfile") {
+            if sub_matches.get_flag("recursive") {
+                if !file_path.is_dir() {
+                    print_err!("{:?} is not a directory", filepath);
+                    return;
+                }
+                encrypt_directory_command(sub_matches, file_path, &recipients);
+            } else if file_path.is_dir() {
+                print_err!("{:?} is a directory", filepath);
+                print_solution!("Use \"encrypt -r\" to encrypt every file it contains");
+            } else if sub_matches.get_flag("sfile") {
                 if let Some(times) = sub_matches.get_one::<u8>("times") {
-                    match encrypt_file_x(file_path, file_path, *times) {
+                    match encrypt_file_x(file_path, file_path, *times, &recipients) {
                         Ok(pb) => {
                             pb.finish_and_clear();
                             print_success!(
@@ -68,7 +136,7 @@ pub fn encrypt_command(sub_matches: &ArgMatches) {
                         Err(e) => print_err!("(encryption failed): {}", e),
                     };
                 } else {
-                    match encrypt_file(&file_path.to_path_buf(), &file_path.to_path_buf()) {
+                    match encrypt_file(file_path, file_path, &recipients) {
                         Ok(_) => {
                             print_success!("{:?} content replaced with crypted one!", &file_path)
                         }
@@ -80,7 +148,7 @@ pub fn encrypt_command(sub_matches: &ArgMatches) {
                     Ok(current_dir) => {
                         let output_path = encrypted_file_path(&file_path, &current_dir);
                         if let Some(times) = sub_matches.get_one::<u8>("times") {
-                            match encrypt_file_x(&file_path, &output_path, *times) {
+                            match encrypt_file_x(file_path, &output_path, *times, &recipients) {
                                 Ok(pb) => {
                                     pb.finish_and_clear();
                                     print_success!(
@@ -92,8 +160,7 @@ pub fn encrypt_command(sub_matches: &ArgMatches) {
                                 Err(e) => print_err!("(encryption failed): {}", e),
                             };
                         } else {
-                            match encrypt_file(&file_path.to_path_buf(), &output_path.to_path_buf())
-                            {
+                            match encrypt_file(file_path, &output_path, &recipients) {
                                 Ok(_) => {
                                     print_success!("Encrypted file saved as {:?}!", output_path)
                                 }
@@ -106,7 +173,7 @@ pub fn encrypt_command(sub_matches: &ArgMatches) {
                     }
                 }
             } else if let true = sub_matches.get_flag("ukey") {
-                match update_file_encryption_key(&file_path.to_path_buf()) {
+                match update_file_encryption_key(file_path) {
                     Ok(_) => print_success!(
                         "{} updated without issue",
                         file_path.file_name().unwrap().to_string_lossy().to_string()
@@ -121,7 +188,7 @@ pub fn encrypt_command(sub_matches: &ArgMatches) {
                     true => {
                         let output_path = encrypted_file_path(&file_path, &output_dir);
                         if let Some(times) = sub_matches.get_one::<u8>("times") {
-                            match encrypt_file_x(&file_path, &output_path, *times) {
+                            match encrypt_file_x(file_path, &output_path, *times, &recipients) {
                                 Ok(pb) => {
                                     pb.finish_and_clear();
                                     print_success!(
@@ -133,8 +200,7 @@ pub fn encrypt_command(sub_matches: &ArgMatches) {
                                 Err(e) => print_err!("(encryption failed) {}", e),
                             };
                         } else {
-                            match encrypt_file(&file_path.to_path_buf(), &output_path.to_path_buf())
-                            {
+                            match encrypt_file(file_path, &output_path, &recipients) {
                                 Ok(_) => {
                                     print_success!("Encrypted file saved as {:?}!", output_path)
                                 }
@@ -150,9 +216,10 @@ pub fn encrypt_command(sub_matches: &ArgMatches) {
                         let output_path = encrypted_file_path(&file_path, &parent_dir);
                         if let Some(times) = sub_matches.get_one::<u8>("times") {
                             match encrypt_file_x(
-                                &file_path.to_path_buf(),
+                                file_path,
                                 &output_path.to_path_buf(),
                                 *times,
+                                &recipients,
                             ) {
                                 Ok(pb) => {
                                     pb.finish_and_clear();
@@ -165,8 +232,7 @@ pub fn encrypt_command(sub_matches: &ArgMatches) {
                                 Err(e) => print_err!("(encryption failed): {}", e),
                             };
                         } else {
-                            match encrypt_file(&file_path.to_path_buf(), &output_path.to_path_buf())
-                            {
+                            match encrypt_file(file_path, &output_path, &recipients) {
                                 Ok(_) => {
                                     print_success!("Encrypted file saved as {:?}!", output_path)
                                 }
@@ -190,6 +256,19 @@ pub fn encrypt_command(sub_matches: &ArgMatches) {
         }
         print_success!("Encryption keys updated successfully")
     } else if let true = sub_matches.get_flag("purge") {
+        match backup::is_backed_up() {
+            Ok(true) => (),
+            Ok(false) if sub_matches.get_flag("force") => (),
+            Ok(false) => {
+                print_err!("Encryption keys were not backed up, purging them would make every encrypted file unrecoverable");
+                print_solution!("Use \"mucli keys export\" first, or \"encrypt --purge --force\"");
+                return;
+            }
+            Err(e) => {
+                print_err!("{}", e);
+                return;
+            }
+        }
         if let Err(_) = purge_encryption_keys() {
             // initialize encryption key if 1st time using command
             print_err!("Error purging encryption keys!");
@@ -200,6 +279,9 @@ pub fn encrypt_command(sub_matches: &ArgMatches) {
 }
 
 pub fn decrypt_command(sub_matches: &ArgMatches) {
+    if !unlock_gate("decrypt") {
+        return;
+    }
     if let Err(_) = init_encryption_key() {
         // initialize encryption key if 1st time using command
         print_err!("Error initializing encryption key!");
@@ -208,7 +290,16 @@ pub fn decrypt_command(sub_matches: &ArgMatches) {
     if let Some(filepath) = sub_matches.get_one::<PathBuf>("FILEPATH") {
         let file_path: &Path = Path::new(filepath);
         if file_path.exists() {
-            if let true = sub_matches.get_flag("sfile") {
+            if sub_matches.get_flag("recursive") {
+                if !file_path.is_dir() {
+                    print_err!("{:?} is not a directory", filepath);
+                    return;
+                }
+                decrypt_directory_command(sub_matches, file_path);
+            } else if file_path.is_dir() {
+                print_err!("{:?} is a directory", filepath);
+                print_solution!("Use \"decrypt -r\" to decrypt every file it contains");
+            } else if sub_matches.get_flag("sfile") {
                 if let true = sub_matches.get_flag("entirely") {
                     match decrypt_file_entirely(&file_path, &file_path) {
                         Ok(pb) => {
@@ -320,26 +411,15 @@ pub fn decrypt_command(sub_matches: &ArgMatches) {
 }
 
 pub fn latest_encryption_version() -> Result<u32, EncryptionError> {
-    let mut filtered_lines: Vec<u32> = Config::open()?.filter_map_lines(|line| {
-        if line.starts_with(&format!("{}=", ENCRYPTION_KEYWORD)) {
-            return Some(
-                parse_config_line!(line)
-                    .unwrap()
-                    .into_iter()
-                    .nth(1)
-                    .unwrap()
-                    .parse::<u32>()
-                    .unwrap(),
-            );
+    // versions are indexes into the MUCLI_ENCRYPT key list
+    let keys = retrieve_encryption_keys().map_err(|e| match e {
+        EncryptionError::NoKeyFound { .. } | EncryptionError::KeyNotExist => {
+            EncryptionError::NoVersionFound {
+                config: config_name(),
+            }
         }
-        None
+        e => e,
     })?;
 
-    if filtered_lines.len() == 0 {
-        return Err(EncryptionError::NoVersionFound);
-    }
-
-    filtered_lines.sort_by(|a, b| b.cmp(a));
-
-    Ok(filtered_lines[0])
+    Ok((keys.len() - 1) as u32)
 }
@@ -1,63 +1,444 @@
 use crate::crypted_file;
-use crate::utils::config_interact::Config;
-use crate::utils::file::CryptedFile;
+use crate::utils::file::{
+    legacy_header, AeadHeader, CryptedFile, FileFormat, AEAD_HEADER_MARKER, FORMAT_VERSION,
+    KDF_X25519, MAX_AEAD_HEADER_SIZE, NONCE_SIZE, SINGLE_SHOT_FORMAT_VERSION, TAG_SIZE,
+};
 use crate::utils::generate_encryption_key;
-use crate::utils::line::Line;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
 use indicatif::ProgressBar;
-use simplecrypt::{decrypt, encrypt};
+use sha2::Sha256;
+use simplecrypt::decrypt;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::thread;
-use std::time::Duration;
+use tempfile::NamedTempFile;
+use x25519_dalek::PublicKey;
 
-const ENCRYPTION_KEYWORD: &str = "MUCLI_ENCRYPT";
 use crate::utils::terminal::arrow_progress;
 
-use super::{latest_encryption_version, EncryptionError};
+use super::keyring::{keyring_exists, read_keys, write_keys};
+use super::recipient::{unwrap_file_key, wrap_file_key};
+use super::spool::Spool;
+use super::{config_name, latest_encryption_version, EncryptionError};
 
-pub fn encrypt_file(input_path: &PathBuf, output_path: &PathBuf) -> Result<(), EncryptionError> {
-    let mut input_file = crypted_file!(input_path.to_path_buf())?;
-
-    let key = nth_encription_key(input_file.encryption_version()? as usize)?;
+const FILE_KEY_INFO: &[u8] = b"mucli file encryption";
 
-    let encrypted: Vec<u8> = encrypt(&input_file.main_file_content()?, &key);
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
 
-    let mut output_file = crypted_file!(output_path.to_path_buf())?.from(&mut input_file)?;
-    output_file.increment_layer()?;
+/// Encrypts with the keyring, or for the given recipients only when there are some
+pub fn encrypt_file(
+    input_path: &Path,
+    output_path: &Path,
+    recipients: &[PublicKey],
+) -> Result<(), EncryptionError> {
+    let input_file = CryptedFile::read_only(input_path.to_path_buf());
+
+    let (key, header) = if recipients.is_empty() {
+        let (layer, version) = input_file.header()?;
+        (
+            nth_encription_key(version as usize)?,
+            AeadHeader::new(version, layer + 1),
+        )
+    } else {
+        let layer = input_file.encryption_layer()?;
+        let (file_key, stanzas) = wrap_file_key(recipients)?;
+        (
+            file_key,
+            AeadHeader::new(0, layer + 1).with_recipients(stanzas),
+        )
+    };
 
-    output_file.update_content(encrypted)?;
+    // the whole input, inner header included, becomes the plaintext of the new layer
+    let reader = BufReader::new(input_file.reader()?);
 
-    Ok(())
+    write_atomically(Some(input_path), output_path, |writer| {
+        writer.write_all(&header.to_bytes())?;
+        seal_stream(&key, &header, reader, writer)
+    })
 }
 
 pub fn decrypt_file(input_path: &PathBuf, output_path: &PathBuf) -> Result<(), EncryptionError> {
     let mut input_file = crypted_file!(input_path.to_path_buf())?;
 
-    if input_file.encryption_layer()? == 0 {
+    match input_file.format()? {
+        FileFormat::Plain => Err(EncryptionError::DecryptNotCryptedFile),
+        FileFormat::Legacy { layer: 0, .. } => Err(EncryptionError::DecryptNotCryptedFile),
+        FileFormat::Legacy { version, .. } => {
+            let key = nth_encription_key(version as usize)?;
+
+            let decrypted_content: Vec<u8> = decrypt(&input_file.main_file_content()?, &key)?;
+
+            let mut output_file =
+                crypted_file!(output_path.to_path_buf())?.from(&mut input_file)?;
+            output_file.decrement_layer()?;
+            output_file.update_content(decrypted_content)?;
+
+            Ok(())
+        }
+        FileFormat::Aead(header) if header.format_version == SINGLE_SHOT_FORMAT_VERSION => {
+            let key = nth_encription_key(header.key_version as usize)?;
+
+            let decrypted_content = open(&key, &header, &input_file.main_file_content()?)?;
+
+            write_atomically(Some(input_path), output_path, |writer| {
+                Ok(writer.write_all(&decrypted_content)?)
+            })
+        }
+        FileFormat::Aead(header) => {
+            let key = header_key(&header)?;
+
+            let mut reader = BufReader::new(input_file.reader()?);
+            reader.seek(SeekFrom::Start(header.size() as u64))?;
+
+            write_atomically(Some(input_path), output_path, |writer| {
+                open_stream(&key, &header, reader, writer)
+            })
+        }
+    }
+}
+
+/// Seals everything `reader` yields as a new single layer encrypted file using the latest key
+pub fn encrypt_stream<R: Read>(reader: R, output_path: &Path) -> Result<(), EncryptionError> {
+    let version = latest_encryption_version()?;
+    let key = nth_encription_key(version as usize)?;
+    let header = AeadHeader::new(version, 1);
+
+    write_atomically(None, output_path, |writer| {
+        writer.write_all(&header.to_bytes())?;
+        seal_stream(&key, &header, reader, writer)
+    })
+}
+
+/// Removes every authenticated encryption layer of a file without writing anything to disk
+pub fn decrypt_in_memory(input_path: &Path) -> Result<Vec<u8>, EncryptionError> {
+    let mut content = fs::read(input_path)?;
+
+    if !content.starts_with(&AEAD_HEADER_MARKER) {
         return Err(EncryptionError::DecryptNotCryptedFile);
     }
 
-    let key = nth_encription_key(input_file.encryption_version()? as usize)?;
+    while content.starts_with(&AEAD_HEADER_MARKER) {
+        let header = AeadHeader::from_bytes(&content)?;
+        let key = header_key(&header)?;
+        let payload = &content[header.size()..];
 
-    let decrypted_content: Vec<u8> = decrypt(&input_file.main_file_content()?, &key)?;
+        content = if header.format_version == SINGLE_SHOT_FORMAT_VERSION {
+            open(&key, &header, payload)?
+        } else {
+            let mut decrypted_content = Vec::with_capacity(payload.len());
+            open_stream(&key, &header, payload, &mut decrypted_content)?;
+            decrypted_content
+        };
+    }
 
-    let mut output_file = crypted_file!(output_path.to_path_buf())?.from(&mut input_file)?;
-    output_file.decrement_layer()?;
-    output_file.update_content(decrypted_content)?;
+    Ok(content)
+}
 
-    Ok(())
+/// Seals what `write` writes into a new single layer encrypted file using the latest key,
+/// nothing is written to `output_path` when it fails
+pub fn encrypt_with<T, E, F>(output_path: &Path, write: F) -> Result<T, E>
+where
+    E: From<EncryptionError> + From<io::Error>,
+    F: FnOnce(&mut dyn Write) -> Result<T, E>,
+{
+    let version = latest_encryption_version()?;
+    let key = nth_encription_key(version as usize)?;
+    let header = AeadHeader::new(version, 1);
+
+    write_atomically(None, output_path, |writer| {
+        writer.write_all(&header.to_bytes())?;
+        let mut sealing_writer = SealingWriter::new(&key, &header, writer)?;
+        let written = write(&mut sealing_writer)?;
+        sealing_writer.finish()?;
+        Ok(written)
+    })
+}
+
+/// Removes every authenticated encryption layer of a file one chunk at a time, into a spool
+/// rewound to its start
+pub fn decrypt_to_spool(input_path: &Path) -> Result<Spool, EncryptionError> {
+    let mut input_file = fs::File::open(input_path)?;
+
+    if !starts_with_aead_header(&mut input_file)? {
+        return Err(EncryptionError::DecryptNotCryptedFile);
+    }
+
+    let mut content = open_layer(&mut input_file)?;
+    while starts_with_aead_header(&mut content)? {
+        content = open_layer(&mut content)?;
+    }
+    Ok(content)
+}
+
+fn starts_with_aead_header<R: Read + Seek>(content: &mut R) -> Result<bool, EncryptionError> {
+    let mut marker = [0u8; AEAD_HEADER_MARKER.len()];
+    let is_marked = content.read_exact(&mut marker).is_ok() && marker == AEAD_HEADER_MARKER;
+    content.rewind()?;
+
+    Ok(is_marked)
+}
+
+// decrypts the outer layer of `content`, read from its start
+fn open_layer<R: Read + Seek>(content: &mut R) -> Result<Spool, EncryptionError> {
+    let mut start = Vec::with_capacity(MAX_AEAD_HEADER_SIZE);
+    content
+        .take(MAX_AEAD_HEADER_SIZE as u64)
+        .read_to_end(&mut start)?;
+    let header = AeadHeader::from_bytes(&start)?;
+    let key = header_key(&header)?;
+    content.seek(SeekFrom::Start(header.size() as u64))?;
+
+    let mut decrypted_content = Spool::new()?;
+    {
+        let mut writer = BufWriter::new(&mut decrypted_content);
+        if header.format_version == SINGLE_SHOT_FORMAT_VERSION {
+            let mut payload = Vec::new();
+            content.read_to_end(&mut payload)?;
+            writer.write_all(&open(&key, &header, &payload)?)?;
+        } else {
+            open_stream(&key, &header, BufReader::new(content), &mut writer)?;
+        }
+        writer.flush()?;
+    }
+    decrypted_content.rewind()?;
+
+    Ok(decrypted_content)
+}
+
+/// Key a layer was sealed with, taken from the keyring or recovered with a local identity
+fn header_key(header: &AeadHeader) -> Result<Vec<u8>, EncryptionError> {
+    if header.kdf == KDF_X25519 {
+        unwrap_file_key(header)
+    } else {
+        nth_encription_key(header.key_version as usize)
+    }
+}
+
+/// Writes the output next to its destination and renames it over the destination once complete,
+/// an error midway leaves the destination untouched
+fn write_atomically<T, E, F>(
+    permissions_from: Option<&Path>,
+    output_path: &Path,
+    write: F,
+) -> Result<T, E>
+where
+    E: From<EncryptionError> + From<io::Error>,
+    F: FnOnce(&mut BufWriter<&mut fs::File>) -> Result<T, E>,
+{
+    let output_dir = match output_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut temp_file = NamedTempFile::new_in(output_dir)?;
+
+    let written = {
+        let mut writer = BufWriter::new(temp_file.as_file_mut());
+        let written = write(&mut writer)?;
+        writer.flush()?;
+        written
+    };
+    temp_file.as_file().sync_all()?;
+    if let Some(source_path) = permissions_from {
+        fs::set_permissions(temp_file.path(), fs::metadata(source_path)?.permissions())?;
+    }
+
+    temp_file.persist(output_path).map_err(|e| e.error)?;
+    Ok(written)
+}
+
+fn file_cipher(key: &[u8], header: &AeadHeader) -> Result<XChaCha20Poly1305, EncryptionError> {
+    let mut file_key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&header.salt), key)
+        .expand(FILE_KEY_INFO, &mut file_key)
+        .map_err(|_| EncryptionError::RetrievingKey)?;
+
+    Ok(XChaCha20Poly1305::new(&file_key.into()))
+}
+
+// the last 5 nonce bytes hold the chunk counter and a final chunk flag,
+// so chunks cannot be reordered, dropped or the stream truncated unnoticed
+fn chunk_nonce(header: &AeadHeader, counter: u32, last: bool) -> XNonce {
+    let mut nonce = header.nonce;
+    nonce[NONCE_SIZE - 5..NONCE_SIZE - 1].copy_from_slice(&counter.to_be_bytes());
+    nonce[NONCE_SIZE - 1] = last as u8;
+
+    XNonce::from(nonce)
+}
+
+fn read_chunk<R: Read>(reader: &mut R, size: usize) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(size);
+    reader.by_ref().take(size as u64).read_to_end(&mut chunk)?;
+
+    Ok(chunk)
+}
+
+fn seal_stream<R: Read, W: Write>(
+    key: &[u8],
+    header: &AeadHeader,
+    mut reader: R,
+    writer: &mut W,
+) -> Result<(), EncryptionError> {
+    let mut sealing_writer = SealingWriter::new(key, header, writer)?;
+    io::copy(&mut reader, &mut sealing_writer)?;
+    sealing_writer.finish()
+}
+
+/// Seals what is written into it as the chunks of a stream, the last chunk is only known
+/// and sealed by `finish`
+struct SealingWriter<'h, W: Write> {
+    cipher: XChaCha20Poly1305,
+    header: &'h AeadHeader,
+    aad: Vec<u8>,
+    // written bytes not sealed yet, a full chunk is held back until more data comes
+    pending: Vec<u8>,
+    counter: u32,
+    writer: W,
+}
+
+impl<'h, W: Write> SealingWriter<'h, W> {
+    fn new(key: &[u8], header: &'h AeadHeader, writer: W) -> Result<Self, EncryptionError> {
+        Ok(SealingWriter {
+            cipher: file_cipher(key, header)?,
+            header,
+            aad: header.to_bytes(),
+            pending: Vec::with_capacity(header.chunk_size as usize),
+            counter: 0,
+            writer,
+        })
+    }
+
+    fn seal_chunk(&mut self, size: usize, last: bool) -> Result<(), EncryptionError> {
+        let sealed_chunk = self
+            .cipher
+            .encrypt(
+                &chunk_nonce(self.header, self.counter, last),
+                Payload {
+                    msg: &self.pending[..size],
+                    aad: &self.aad,
+                },
+            )
+            .map_err(|_| EncryptionError::EncryptionFailed {
+                filename: String::from("payload"),
+            })?;
+        self.writer.write_all(&sealed_chunk)?;
+        self.pending.drain(..size);
+
+        if !last {
+            self.counter = self
+                .counter
+                .checked_add(1)
+                .ok_or(EncryptionError::FileTooLarge)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), EncryptionError> {
+        if self.pending.is_empty() && self.counter == 0 {
+            return Err(EncryptionError::CannotProcessVoidFile);
+        }
+        self.seal_chunk(self.pending.len(), true)?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for SealingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+
+        let chunk_size = self.header.chunk_size as usize;
+        while self.pending.len() > chunk_size {
+            self.seal_chunk(chunk_size, false)
+                .map_err(io::Error::other)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn open_stream<R: Read, W: Write>(
+    key: &[u8],
+    header: &AeadHeader,
+    mut reader: R,
+    writer: &mut W,
+) -> Result<(), EncryptionError> {
+    let cipher = file_cipher(key, header)?;
+    let aad = header.to_bytes();
+    let chunk_size = header.chunk_size as usize + TAG_SIZE;
+
+    let mut chunk = read_chunk(&mut reader, chunk_size)?;
+    if chunk.len() < TAG_SIZE {
+        return Err(EncryptionError::TruncatedFile);
+    }
+
+    let mut counter: u32 = 0;
+    loop {
+        let next_chunk = read_chunk(&mut reader, chunk_size)?;
+        let last = next_chunk.is_empty();
+
+        let opened_chunk = cipher
+            .decrypt(
+                &chunk_nonce(header, counter, last),
+                Payload {
+                    msg: &chunk,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| EncryptionError::AuthenticationFailed)?;
+        writer.write_all(&opened_chunk)?;
+
+        if last {
+            return Ok(());
+        }
+        chunk = next_chunk;
+        counter = counter
+            .checked_add(1)
+            .ok_or(EncryptionError::FileTooLarge)?;
+    }
+}
+
+fn open(key: &[u8], header: &AeadHeader, content: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+    if content.len() < TAG_SIZE {
+        return Err(EncryptionError::TruncatedFile);
+    }
+    let aad = header.to_bytes();
+
+    file_cipher(key, header)?
+        .decrypt(
+            XNonce::from_slice(&header.nonce),
+            Payload {
+                msg: content,
+                aad: &aad,
+            },
+        )
+        .map_err(|_| EncryptionError::AuthenticationFailed)
 }
 
 pub fn encrypt_file_x(
     input_path: &Path,
     output_path: &Path,
     times: u8,
+    recipients: &[PublicKey],
 ) -> Result<ProgressBar, EncryptionError> {
     let mut counter = 0;
     let progress = arrow_progress(times as u64);
     progress.set_prefix("Encrypting file...");
 
     loop {
-        encrypt_file(&input_path.to_path_buf(), &output_path.to_path_buf())?;
+        // every layer after the first one wraps the previous output
+        let source = if counter == 0 {
+            input_path
+        } else {
+            output_path
+        };
+        encrypt_file(source, output_path, recipients)?;
         progress.inc(1);
         counter += 1;
 
@@ -83,7 +464,12 @@ pub fn decrypt_file_entirely(
     progress.set_prefix("Decrypting file...");
 
     loop {
-        decrypt_file(&input_path.to_path_buf(), &output_path.to_path_buf())?;
+        let source = if counter == 0 {
+            input_path
+        } else {
+            output_path
+        };
+        decrypt_file(&source.to_path_buf(), &output_path.to_path_buf())?;
         progress.inc(1);
         counter += 1;
 
@@ -114,96 +500,150 @@ pub fn decrypted_file_path(file_path: &Path, current_dir: &Path) -> PathBuf {
 }
 
 pub fn init_encryption_key() -> Result<(), EncryptionError> {
-    match Config::open()?.key_exists(ENCRYPTION_KEYWORD) {
-        Ok(val) => {
-            if let false = val {
-                set_encryption_key()?
-            }
-            Ok(())
-        }
+    match keyring_exists() {
+        Ok(false) => set_encryption_key(),
+        Ok(true) => Ok(()),
         Err(_) => Err(EncryptionError::ConfigNotFound),
     }
 }
 
 pub fn init_new_encryption_key() -> Result<(), EncryptionError> {
-    match Config::open()?.key_exists(ENCRYPTION_KEYWORD) {
-        Ok(val) => {
-            if let true = val {
-                set_encryption_key()?
-            }
-            Ok(())
-        }
+    match keyring_exists() {
+        Ok(true) => set_encryption_key(),
+        Ok(false) => Ok(()),
         Err(_) => Err(EncryptionError::ConfigNotFound),
     }
 }
 
-pub fn update_file_encryption_key(filepath: &PathBuf) -> Result<(), EncryptionError> {
+pub fn update_file_encryption_key(filepath: &Path) -> Result<(), EncryptionError> {
     //update file key to latest
-    let file = crypted_file!(filepath.to_path_buf())?;
-    let initial_layer = file.encryption_layer()?;
-
-    if file.encryption_version()? == latest_encryption_version()? {
+    match CryptedFile::read_only(filepath.to_path_buf()).format()? {
+        FileFormat::Plain | FileFormat::Legacy { layer: 0, .. } => {
+            return Err(EncryptionError::DecryptNotCryptedFile)
+        }
+        FileFormat::Aead(header) if header.kdf == KDF_X25519 => {
+            return Err(EncryptionError::RecipientFileNoVersion)
+        }
+        _ => (),
+    }
+    if !needs_rotation(filepath, latest_encryption_version()?)? {
         return Err(EncryptionError::CannotUpdateLatest);
     }
 
-    // first we decrypt the file
-    let progress_decrypt: ProgressBar = decrypt_file_entirely(&filepath, &filepath)?;
-    thread::sleep(Duration::from_millis(250));
-    progress_decrypt.finish_and_clear();
+    rotate_file(filepath)
+}
 
-    // we encrypt it again with newly generated key
-    let progress_encrypt: ProgressBar = encrypt_file_x(&filepath, &filepath, initial_layer as u8)?;
+/// Whether a file is encrypted with the keyring using an older key or an older format
+pub fn needs_rotation(file_path: &Path, latest_version: u32) -> Result<bool, EncryptionError> {
+    Ok(
+        match CryptedFile::read_only(file_path.to_path_buf()).format()? {
+            FileFormat::Plain | FileFormat::Legacy { layer: 0, .. } => false,
+            // files in an older format are always upgraded to the current one
+            FileFormat::Legacy { .. } => true,
+            FileFormat::Aead(header) if header.kdf == KDF_X25519 => false,
+            FileFormat::Aead(header) => {
+                header.key_version < latest_version || header.format_version != FORMAT_VERSION
+            }
+        },
+    )
+}
 
-    thread::sleep(Duration::from_millis(250));
-    progress_encrypt.finish_and_clear();
+/// Re-encrypts every keyring layer of a file with the latest key, layers sealed for
+/// recipients are kept as they are. The layers are opened and sealed again through spools,
+/// the original file is only replaced, by a rename, once the rotated version is complete
+pub fn rotate_file(file_path: &Path) -> Result<(), EncryptionError> {
+    let mut content: Box<dyn ReadSeek> = Box::new(BufReader::new(fs::File::open(file_path)?));
 
-    Ok(())
+    let mut layers = 0;
+    loop {
+        let format = FileFormat::read(&mut content)?;
+        content.rewind()?;
+        content = match format {
+            FileFormat::Legacy { layer, version } if layer > 0 => {
+                Box::new(open_legacy_layer(&mut content, layer, version)?)
+            }
+            FileFormat::Aead(header) if header.kdf != KDF_X25519 => {
+                Box::new(open_layer(&mut content)?)
+            }
+            _ => break,
+        };
+        layers += 1;
+    }
+    if layers == 0 {
+        return Err(EncryptionError::DecryptNotCryptedFile);
+    }
+
+    let mut layer = match FileFormat::read(&mut content)? {
+        FileFormat::Aead(header) => header.layer,
+        _ => 0,
+    };
+    content.rewind()?;
+
+    let version = latest_encryption_version()?;
+    let key = nth_encription_key(version as usize)?;
+    for _ in 1..layers {
+        layer += 1;
+        let header = AeadHeader::new(version, layer);
+
+        let mut sealed_content = Spool::new()?;
+        {
+            let mut writer = BufWriter::new(&mut sealed_content);
+            writer.write_all(&header.to_bytes())?;
+            seal_stream(&key, &header, &mut content, &mut writer)?;
+            writer.flush()?;
+        }
+        sealed_content.rewind()?;
+        content = Box::new(sealed_content);
+    }
+
+    let header = AeadHeader::new(version, layer + 1);
+    write_atomically(Some(file_path), file_path, |writer| {
+        writer.write_all(&header.to_bytes())?;
+        seal_stream(&key, &header, content, writer)
+    })
 }
 
-pub fn purge_encryption_keys() -> Result<(), EncryptionError> {
-    let mut config = Config::open()?;
+// removes one simplecrypt layer, the layers below keep a v1 header
+fn open_legacy_layer<R: Read>(
+    content: &mut R,
+    layer: u32,
+    version: u32,
+) -> Result<Spool, EncryptionError> {
+    let mut payload = Vec::new();
+    content.read_to_end(&mut payload)?;
+    let payload = &payload[FileFormat::Legacy { layer, version }.header_size()..];
+
+    let mut decrypted_content = Spool::new()?;
+    if layer > 1 {
+        decrypted_content.write_all(&legacy_header(layer - 1, version))?;
+    }
+    decrypted_content.write_all(&decrypt(payload, &nth_encription_key(version as usize)?)?)?;
+    decrypted_content.rewind()?;
 
-    let new_line = if let Some(line) = config.get_line(ENCRYPTION_KEYWORD) {
-        let mut parsed_line: Line<Vec<Vec<u8>>> = Line::from(&line)?;
-        parsed_line.set_new(vec![]);
-        parsed_line
-    } else {
-        Line::new(ENCRYPTION_KEYWORD, vec![])
-    };
+    Ok(decrypted_content)
+}
 
-    config.replace_key(new_line)?;
-    Ok(())
+pub fn purge_encryption_keys() -> Result<(), EncryptionError> {
+    write_keys(vec![])
 }
 
 fn set_encryption_key() -> Result<(), EncryptionError> {
-    let mut config = Config::open()?;
-    let new_line = if let Some(line) = config.get_line(ENCRYPTION_KEYWORD) {
-        let mut parsed_line: Line<Vec<Vec<u8>>> = Line::from(&line)?;
-        parsed_line.add(generate_encryption_key(32));
-        parsed_line
-    } else {
-        Line::new(ENCRYPTION_KEYWORD, vec![generate_encryption_key(32)])
-    };
+    let mut keys = read_keys()?.unwrap_or_default();
+    keys.push(generate_encryption_key(32));
 
-    config.set_line(new_line)?;
-
-    Ok(())
+    write_keys(keys)
 }
 
-fn retrieve_encryption_keys() -> Result<Vec<Vec<u8>>, EncryptionError> {
-    let config = Config::open()?;
-    let encryption_keys: Line<Vec<Vec<u8>>> =
-        if let Some(line) = config.get_line(ENCRYPTION_KEYWORD) {
-            Line::from(&line)?
-        } else {
-            return Err(EncryptionError::KeyNotExist);
-        };
+pub fn retrieve_encryption_keys() -> Result<Vec<Vec<u8>>, EncryptionError> {
+    let encryption_keys = read_keys()?.ok_or(EncryptionError::KeyNotExist)?;
 
-    if encryption_keys.value.is_empty() {
-        return Err(EncryptionError::NoKeyFound);
+    if encryption_keys.is_empty() {
+        return Err(EncryptionError::NoKeyFound {
+            config: config_name(),
+        });
     }
 
-    Ok(encryption_keys.value)
+    Ok(encryption_keys)
 }
 
 fn nth_encription_key(index: usize) -> Result<Vec<u8>, EncryptionError> {
@@ -214,3 +654,127 @@ fn nth_encription_key(index: usize) -> Result<Vec<u8>, EncryptionError> {
         Err(EncryptionError::KeyNotExist)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+    // small chunks so that a few bytes already make a stream of several chunks
+    const TEST_CHUNK_SIZE: u32 = 16;
+    const SEALED_CHUNK_SIZE: usize = TEST_CHUNK_SIZE as usize + TAG_SIZE;
+
+    fn header() -> AeadHeader {
+        let mut header = AeadHeader::new(0, 1);
+        header.chunk_size = TEST_CHUNK_SIZE;
+        header
+    }
+
+    fn content(size: usize) -> Vec<u8> {
+        (0..size).map(|i| i as u8).collect()
+    }
+
+    fn seal(header: &AeadHeader, content: &[u8]) -> Vec<u8> {
+        let mut sealed = Vec::new();
+        seal_stream(&KEY, header, content, &mut sealed).unwrap();
+        sealed
+    }
+
+    fn open(header: &AeadHeader, sealed: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        let mut opened = Vec::new();
+        open_stream(&KEY, header, sealed, &mut opened)?;
+        Ok(opened)
+    }
+
+    #[test]
+    fn stream_round_trip() {
+        let header = header();
+
+        for size in [1, 15, 16, 17, 32, 100] {
+            let sealed = seal(&header, &content(size));
+            assert_eq!(sealed.len(), size + size.div_ceil(16) * TAG_SIZE);
+            assert_eq!(open(&header, &sealed).unwrap(), content(size));
+        }
+    }
+
+    #[test]
+    fn empty_stream_is_refused() {
+        let mut sealed = Vec::new();
+        let result = seal_stream(&KEY, &header(), &[][..], &mut sealed);
+
+        assert!(matches!(
+            result,
+            Err(EncryptionError::CannotProcessVoidFile)
+        ));
+    }
+
+    #[test]
+    fn tampered_chunk_is_rejected() {
+        let header = header();
+        let mut sealed = seal(&header, &content(40));
+        sealed[SEALED_CHUNK_SIZE + 3] ^= 1;
+
+        assert!(matches!(
+            open(&header, &sealed),
+            Err(EncryptionError::AuthenticationFailed)
+        ));
+    }
+
+    #[test]
+    fn truncated_stream_is_rejected() {
+        let header = header();
+        let sealed = seal(&header, &content(40));
+
+        // the chunk left last was not sealed as the last one
+        assert!(matches!(
+            open(&header, &sealed[..2 * SEALED_CHUNK_SIZE]),
+            Err(EncryptionError::AuthenticationFailed)
+        ));
+        assert!(matches!(
+            open(&header, &sealed[..TAG_SIZE - 1]),
+            Err(EncryptionError::TruncatedFile)
+        ));
+    }
+
+    #[test]
+    fn extended_stream_is_rejected() {
+        let header = header();
+        let mut sealed = seal(&header, &content(40));
+        let first_chunk = sealed[..SEALED_CHUNK_SIZE].to_vec();
+        sealed.extend_from_slice(&first_chunk);
+
+        assert!(matches!(
+            open(&header, &sealed),
+            Err(EncryptionError::AuthenticationFailed)
+        ));
+    }
+
+    #[test]
+    fn reordered_chunks_are_rejected() {
+        let header = header();
+        let sealed = seal(&header, &content(40));
+
+        let mut reordered = sealed[SEALED_CHUNK_SIZE..2 * SEALED_CHUNK_SIZE].to_vec();
+        reordered.extend_from_slice(&sealed[..SEALED_CHUNK_SIZE]);
+        reordered.extend_from_slice(&sealed[2 * SEALED_CHUNK_SIZE..]);
+
+        assert!(matches!(
+            open(&header, &reordered),
+            Err(EncryptionError::AuthenticationFailed)
+        ));
+    }
+
+    #[test]
+    fn altered_header_is_rejected() {
+        let header = header();
+        let sealed = seal(&header, &content(40));
+
+        let mut altered_header = header.clone();
+        altered_header.layer = 2;
+
+        assert!(matches!(
+            open(&altered_header, &sealed),
+            Err(EncryptionError::AuthenticationFailed)
+        ));
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use data_encoding::BASE32_NOPAD;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::utils::config::Config;
+
+use super::{
+    config_name,
+    keyring::{
+        open_with_password, read_identities, read_keys, seal_with_password, write_identities,
+        write_keys, WrappedKeyring,
+    },
+    recipient::Identity,
+    EncryptionError,
+};
+
+const BACKUP_KIND: &str = "mucli-keyring-backup";
+const BACKUP_VERSION: u32 = 1;
+
+const RECOVERY_TITLE: &str = "MUCLI RECOVERY KEYS";
+const RECOVERY_VERSION: u8 = 1;
+const CHECKSUM_SIZE: usize = 4;
+const BLOCK_SIZE: usize = 4;
+const BLOCKS_PER_LINE: usize = 8;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupFile {
+    kind: String,
+    version: u32,
+    wrapped: WrappedKeyring,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct KeyBackup {
+    keys: Vec<Vec<u8>>,
+    #[serde(default)]
+    identities: Vec<Identity>,
+}
+
+/// How imported keys were merged with the local keyring
+pub enum ImportOutcome {
+    // the local keyring was empty or a prefix of the imported one
+    Restored { keys: usize, identities: usize },
+    // every imported key was already known
+    UpToDate { identities: usize },
+}
+
+/// Recovery sheet of the keyring, its keys only count as backed up once it was saved or printed
+pub struct RecoverySheet {
+    pub text: String,
+    keys: Vec<Vec<u8>>,
+}
+
+impl RecoverySheet {
+    pub fn mark_saved(&self) -> Result<(), EncryptionError> {
+        mark_backed_up(&self.keys)
+    }
+}
+
+/// Writes the keyring and identities to a backup file sealed with `password`
+pub fn export_backup(output_path: &Path, password: &str) -> Result<(), EncryptionError> {
+    let backup = KeyBackup {
+        keys: current_keys()?,
+        identities: read_identities()?.unwrap_or_default(),
+    };
+
+    let backup_file = BackupFile {
+        kind: BACKUP_KIND.to_string(),
+        version: BACKUP_VERSION,
+        wrapped: seal_with_password(&backup, password)?,
+    };
+    let content =
+        serde_json::to_string_pretty(&backup_file).map_err(|_| EncryptionError::KeyUpdateFailed)?;
+    write_private(output_path, content.as_bytes())?;
+
+    mark_backed_up(&backup.keys)
+}
+
+/// Formats the keyring as base32 blocks meant to be printed or written down,
+/// identities are not part of it
+pub fn export_recovery() -> Result<RecoverySheet, EncryptionError> {
+    let keys = current_keys()?;
+
+    let mut payload = vec![RECOVERY_VERSION];
+    payload.extend_from_slice(&(keys.len() as u16).to_be_bytes());
+    for key in &keys {
+        payload.push(key.len() as u8);
+        payload.extend_from_slice(key);
+    }
+    payload.extend_from_slice(&checksum(&payload));
+
+    let encoded = BASE32_NOPAD.encode(&payload);
+    let blocks: Vec<&str> = encoded
+        .as_bytes()
+        .chunks(BLOCK_SIZE)
+        .map(|block| std::str::from_utf8(block).unwrap())
+        .collect();
+    let lines: Vec<String> = blocks
+        .chunks(BLOCKS_PER_LINE)
+        .map(|line| line.join(" "))
+        .collect();
+
+    Ok(RecoverySheet {
+        text: format!("{}\n{}\n", RECOVERY_TITLE, lines.join("\n")),
+        keys,
+    })
+}
+
+/// Writes key material to a new file only its owner can read, an existing file is not
+/// replaced and nothing is left behind when the write fails
+pub fn write_private(path: &Path, content: &[u8]) -> Result<(), EncryptionError> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    if let Err(e) = file.write_all(content).and_then(|_| file.sync_all()) {
+        let _ = fs::remove_file(path);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Restores keys from a backup file or from a recovery sheet, `password` is only
+/// asked for backup files; with `force` the local keyring is replaced even when it differs
+pub fn import_backup<F>(
+    input_path: &Path,
+    password: F,
+    force: bool,
+) -> Result<ImportOutcome, EncryptionError>
+where
+    F: FnOnce() -> Result<String, EncryptionError>,
+{
+    let content = fs::read_to_string(input_path)?;
+
+    let backup = if content.trim_start().starts_with(RECOVERY_TITLE) {
+        KeyBackup {
+            keys: parse_recovery(&content)?,
+            identities: vec![],
+        }
+    } else {
+        let backup_file: BackupFile =
+            serde_json::from_str(&content).map_err(|_| EncryptionError::InvalidBackup)?;
+        if backup_file.kind != BACKUP_KIND {
+            return Err(EncryptionError::InvalidBackup);
+        }
+        if backup_file.version != BACKUP_VERSION {
+            return Err(EncryptionError::UnsupportedBackup {
+                version: backup_file.version,
+            });
+        }
+        open_with_password(&backup_file.wrapped, &password()?)?
+    };
+
+    let local_keys = read_keys()?.unwrap_or_default();
+    // key versions are indexes, the imported list may only extend the local one
+    let new_keys = if local_keys.starts_with(&backup.keys) {
+        None
+    } else if backup.keys.starts_with(&local_keys) {
+        Some(backup.keys.len() - local_keys.len())
+    } else if force {
+        Some(backup.keys.len())
+    } else {
+        return Err(EncryptionError::KeyringMismatch);
+    };
+
+    let identities = merge_identities(backup.identities)?;
+    match new_keys {
+        Some(keys) => {
+            // the imported file is a backup of the resulting keyring
+            mark_backed_up(&backup.keys)?;
+            write_keys(backup.keys)?;
+            Ok(ImportOutcome::Restored { keys, identities })
+        }
+        None => Ok(ImportOutcome::UpToDate { identities }),
+    }
+}
+
+/// Whether the current keyring has been exported since it last changed
+pub fn is_backed_up() -> Result<bool, EncryptionError> {
+    let keys = read_keys()?.unwrap_or_default();
+    if keys.is_empty() {
+        return Ok(true);
+    }
+
+    // purging is only allowed when the last exported keyring is the current one
+    let backup_fingerprint = Config::load()?.encryption.backup_fingerprint;
+    Ok(backup_fingerprint == Some(fingerprint(&keys)))
+}
+
+fn current_keys() -> Result<Vec<Vec<u8>>, EncryptionError> {
+    match read_keys()? {
+        Some(keys) if !keys.is_empty() => Ok(keys),
+        _ => Err(EncryptionError::NoKeyFound {
+            config: config_name(),
+        }),
+    }
+}
+
+fn parse_recovery(content: &str) -> Result<Vec<Vec<u8>>, EncryptionError> {
+    // the sheet ends at the first line that is not made of base32 blocks
+    let encoded: String = content
+        .trim_start()
+        .trim_start_matches(RECOVERY_TITLE)
+        .trim_start()
+        .lines()
+        .map(|line| line.trim().to_uppercase())
+        .take_while(|line| {
+            !line.is_empty()
+                && line
+                    .chars()
+                    .all(|c| c == ' ' || c.is_ascii_uppercase() || ('2'..='7').contains(&c))
+        })
+        .collect::<String>()
+        .replace(' ', "");
+
+    let payload = BASE32_NOPAD
+        .decode(encoded.as_bytes())
+        .map_err(|_| EncryptionError::InvalidBackup)?;
+    if payload.len() < 3 + CHECKSUM_SIZE {
+        return Err(EncryptionError::InvalidBackup);
+    }
+
+    let (payload, expected_checksum) = payload.split_at(payload.len() - CHECKSUM_SIZE);
+    if checksum(payload) != expected_checksum {
+        return Err(EncryptionError::RecoveryChecksum);
+    }
+    if payload[0] != RECOVERY_VERSION {
+        return Err(EncryptionError::UnsupportedBackup {
+            version: payload[0] as u32,
+        });
+    }
+
+    let count = u16::from_be_bytes([payload[1], payload[2]]) as usize;
+    let mut keys = Vec::with_capacity(count);
+    let mut offset = 3;
+    for _ in 0..count {
+        let size = *payload.get(offset).ok_or(EncryptionError::InvalidBackup)? as usize;
+        let key = payload
+            .get(offset + 1..offset + 1 + size)
+            .ok_or(EncryptionError::InvalidBackup)?;
+        keys.push(key.to_vec());
+        offset += 1 + size;
+    }
+
+    if offset != payload.len() {
+        return Err(EncryptionError::InvalidBackup);
+    }
+    Ok(keys)
+}
+
+// identities are merged by name, local ones win
+fn merge_identities(imported: Vec<Identity>) -> Result<usize, EncryptionError> {
+    if imported.is_empty() {
+        return Ok(0);
+    }
+
+    let mut identities: Vec<Identity> = read_identities()?.unwrap_or_default();
+    let mut added = 0;
+    for identity in imported {
+        if !identities.iter().any(|local| local.name == identity.name) {
+            identities.push(identity);
+            added += 1;
+        }
+    }
+
+    if added > 0 {
+        write_identities(identities)?;
+    }
+    Ok(added)
+}
+
+fn mark_backed_up(keys: &[Vec<u8>]) -> Result<(), EncryptionError> {
+    let fingerprint = fingerprint(keys);
+    Config::update(|config| {
+        config.encryption.backup_fingerprint = Some(fingerprint);
+        Ok::<(), EncryptionError>(())
+    })
+}
+
+fn fingerprint(keys: &[Vec<u8>]) -> String {
+    let mut hasher = Sha256::new();
+    for key in keys {
+        hasher.update((key.len() as u32).to_be_bytes());
+        hasher.update(key);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    Sha256::digest(payload)[..CHECKSUM_SIZE].try_into().unwrap()
+}
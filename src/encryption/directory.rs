@@ -0,0 +1,279 @@
+use std::{
+    env::current_dir,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::ArgMatches;
+use x25519_dalek::PublicKey;
+
+use crate::{
+    print_err, print_info, print_success,
+    utils::{
+        file::{CryptedFile, FileFormat},
+        terminal::arrow_progress,
+        walk::{walk_files, PathFilter},
+    },
+};
+
+use super::{
+    annex::{decrypt_file, decrypted_file_path, encrypt_file, encrypted_file_path},
+    EncryptionError,
+};
+
+pub enum BatchOutput {
+    // replace every file by its processed version
+    InPlace,
+    // write every output next to its source file
+    Beside,
+    // mirror the source tree under the given directory
+    Mirror(PathBuf),
+}
+
+#[derive(Default)]
+pub struct BatchSummary {
+    pub succeeded: usize,
+    pub skipped: usize,
+    pub failed: Vec<(PathBuf, EncryptionError)>,
+}
+
+impl BatchSummary {
+    pub fn print(&self, action: &str) {
+        print_success!("{} file(s) {}", self.succeeded, action);
+        if self.skipped > 0 {
+            print_info!("{} file(s) skipped", self.skipped);
+        }
+        for (path, error) in &self.failed {
+            print_err!("{}: {}", path.display(), error);
+        }
+    }
+}
+
+pub fn encrypt_directory_command(sub_matches: &ArgMatches, root: &Path, recipients: &[PublicKey]) {
+    let (output, filter) = match batch_options(sub_matches) {
+        Some(options) => options,
+        None => return,
+    };
+    let times = sub_matches.get_one::<u8>("times").copied().unwrap_or(1);
+
+    match encrypt_directory(root, &output, times, &filter, recipients) {
+        Ok(summary) => summary.print("encrypted"),
+        Err(e) => print_err!("(encryption failed): {}", e),
+    }
+}
+
+pub fn decrypt_directory_command(sub_matches: &ArgMatches, root: &Path) {
+    let (output, filter) = match batch_options(sub_matches) {
+        Some(options) => options,
+        None => return,
+    };
+    let entirely = sub_matches.get_flag("entirely");
+
+    match decrypt_directory(root, &output, entirely, &filter) {
+        Ok(summary) => summary.print("decrypted"),
+        Err(e) => print_err!("(decryption failed): {}", e),
+    }
+}
+
+fn batch_options(sub_matches: &ArgMatches) -> Option<(BatchOutput, PathFilter)> {
+    let output = if sub_matches.get_flag("sfile") {
+        BatchOutput::InPlace
+    } else if sub_matches.get_flag("cdir") {
+        match current_dir() {
+            Ok(current_dir) => BatchOutput::Mirror(current_dir),
+            Err(error) => {
+                print_err!("Failed to get current directory: {}", error);
+                return None;
+            }
+        }
+    } else if let Some(output_dir) = sub_matches.get_one::<PathBuf>("OUTPUTDIR") {
+        if !output_dir.is_dir() {
+            print_err!("Failed to get {:?} directory", output_dir);
+            return None;
+        }
+        BatchOutput::Mirror(output_dir.to_path_buf())
+    } else {
+        BatchOutput::Beside
+    };
+
+    let patterns = |id: &str| -> Vec<String> {
+        sub_matches
+            .get_many::<String>(id)
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default()
+    };
+
+    match PathFilter::new(&patterns("include"), &patterns("exclude")) {
+        Ok(filter) => Some((output, filter)),
+        Err(e) => {
+            print_err!("{}", e);
+            None
+        }
+    }
+}
+
+pub fn encrypt_directory(
+    root: &Path,
+    output: &BatchOutput,
+    times: u8,
+    filter: &PathFilter,
+    recipients: &[PublicKey],
+) -> Result<BatchSummary, EncryptionError> {
+    let files = batch_files(root, output, filter)?;
+    let mut summary = BatchSummary::default();
+    let skip_encrypted = outputs_among_sources(root, output)?;
+
+    let progress = arrow_progress(files.len() as u64);
+    progress.set_prefix("Encrypting files...");
+
+    for file in files {
+        progress.set_message(file.display().to_string());
+
+        // the outputs of a previous run lie among their sources, they are not encrypted again
+        if skip_encrypted {
+            match is_encrypted(&file) {
+                Ok(false) => (),
+                Ok(true) => {
+                    summary.skipped += 1;
+                    progress.inc(1);
+                    continue;
+                }
+                Err(e) => {
+                    summary.failed.push((file, e));
+                    progress.inc(1);
+                    continue;
+                }
+            }
+        }
+
+        let result =
+            batch_output_path(root, &file, output, encrypted_file_path).and_then(|output_path| {
+                encrypt_file(&file, &output_path, recipients)?;
+                for _ in 1..times {
+                    encrypt_file(&output_path, &output_path, recipients)?;
+                }
+                Ok(())
+            });
+
+        match result {
+            Ok(_) => summary.succeeded += 1,
+            Err(e) => summary.failed.push((file, e)),
+        }
+        progress.inc(1);
+    }
+
+    progress.finish_and_clear();
+    Ok(summary)
+}
+
+pub fn decrypt_directory(
+    root: &Path,
+    output: &BatchOutput,
+    entirely: bool,
+    filter: &PathFilter,
+) -> Result<BatchSummary, EncryptionError> {
+    let files = batch_files(root, output, filter)?;
+    let mut summary = BatchSummary::default();
+
+    let progress = arrow_progress(files.len() as u64);
+    progress.set_prefix("Decrypting files...");
+
+    for file in files {
+        progress.set_message(file.display().to_string());
+
+        // files that were never encrypted by mucli are left alone
+        match CryptedFile::read_only(file.to_path_buf()).encryption_layer() {
+            Ok(0) => {
+                summary.skipped += 1;
+                progress.inc(1);
+                continue;
+            }
+            Ok(_) => (),
+            Err(e) => {
+                summary.failed.push((file, e));
+                progress.inc(1);
+                continue;
+            }
+        }
+
+        let result =
+            batch_output_path(root, &file, output, decrypted_file_path).and_then(|output_path| {
+                decrypt_file(&file, &output_path)?;
+                while entirely && is_encrypted(&output_path)? {
+                    decrypt_file(&output_path, &output_path)?;
+                }
+                Ok(())
+            });
+
+        match result {
+            Ok(_) => summary.succeeded += 1,
+            Err(e) => summary.failed.push((file, e)),
+        }
+        progress.inc(1);
+    }
+
+    progress.finish_and_clear();
+    Ok(summary)
+}
+
+fn is_encrypted(path: &Path) -> Result<bool, EncryptionError> {
+    Ok(!matches!(
+        CryptedFile::read_only(path.to_path_buf()).format()?,
+        FileFormat::Plain | FileFormat::Legacy { layer: 0, .. }
+    ))
+}
+
+fn outputs_among_sources(root: &Path, output: &BatchOutput) -> Result<bool, EncryptionError> {
+    match output {
+        BatchOutput::InPlace => Ok(false),
+        BatchOutput::Beside => Ok(true),
+        BatchOutput::Mirror(output_dir) => {
+            Ok(fs::canonicalize(root)?.starts_with(fs::canonicalize(output_dir)?))
+        }
+    }
+}
+
+fn batch_files(
+    root: &Path,
+    output: &BatchOutput,
+    filter: &PathFilter,
+) -> Result<Vec<PathBuf>, EncryptionError> {
+    let files = walk_files(root, filter)?;
+
+    // never process what a previous run wrote into an output dir nested in the source tree
+    if let BatchOutput::Mirror(output_dir) = output {
+        let output_dir = fs::canonicalize(output_dir)?;
+        if output_dir != fs::canonicalize(root)? {
+            return Ok(files
+                .into_iter()
+                .filter(|file| match fs::canonicalize(file) {
+                    Ok(file) => !file.starts_with(&output_dir),
+                    Err(_) => true,
+                })
+                .collect());
+        }
+    }
+
+    Ok(files)
+}
+
+fn batch_output_path(
+    root: &Path,
+    file: &Path,
+    output: &BatchOutput,
+    output_name: fn(&Path, &Path) -> PathBuf,
+) -> Result<PathBuf, EncryptionError> {
+    let parent = file.parent().unwrap_or(root);
+
+    match output {
+        BatchOutput::InPlace => Ok(file.to_path_buf()),
+        BatchOutput::Beside => Ok(output_name(file, parent)),
+        BatchOutput::Mirror(output_dir) => {
+            let relative_parent = parent.strip_prefix(root).unwrap_or(Path::new(""));
+            let target_dir = output_dir.join(relative_parent);
+            fs::create_dir_all(&target_dir)?;
+
+            Ok(output_name(file, &target_dir))
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use std::sync::Mutex;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::agent;
+use crate::utils::{
+    bytes::{self, Bytes},
+    config::{Config, EncryptionConfig},
+    generate_encryption_key, prompt,
+};
+
+use super::{recipient::Identity, EncryptionError};
+
+// a secret entry of the encryption config, stored either in plaintext
+// or sealed with the mucli password
+struct SecretEntry<T> {
+    plain: fn(&mut EncryptionConfig) -> &mut Option<T>,
+    wrapped: fn(&mut EncryptionConfig) -> &mut Option<WrappedKeyring>,
+}
+
+const KEYS: SecretEntry<Vec<Bytes>> = SecretEntry {
+    plain: |encryption| &mut encryption.keys,
+    wrapped: |encryption| &mut encryption.wrapped_keys,
+};
+const IDENTITIES: SecretEntry<Vec<Identity>> = SecretEntry {
+    plain: |encryption| &mut encryption.identities,
+    wrapped: |encryption| &mut encryption.wrapped_identities,
+};
+
+const KEYRING_AAD: &[u8] = b"mucli keyring";
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 24;
+// argon2id defaults recommended by OWASP: 19 MiB, 2 iterations, 1 lane
+const ARGON2_M_COST: u32 = 19 * 1024;
+const ARGON2_T_COST: u32 = 2;
+const ARGON2_P_COST: u32 = 1;
+
+// key derived from the password, kept for the rest of the invocation once unlocked,
+// all wrapped entries share the same salt so that a single key opens them all
+static UNLOCKED_KEY: Mutex<Option<[u8; 32]>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WrappedKeyring {
+    #[serde(with = "bytes::base64")]
+    pub salt: Vec<u8>,
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+    #[serde(with = "bytes::base64")]
+    pub nonce: Vec<u8>,
+    #[serde(with = "bytes::base64")]
+    pub keys: Vec<u8>,
+}
+
+impl WrappedKeyring {
+    fn seal<T: Serialize>(
+        value: &T,
+        password_key: &[u8; 32],
+        salt: Vec<u8>,
+    ) -> Result<Self, EncryptionError> {
+        let nonce = generate_encryption_key(NONCE_SIZE);
+        let plain_value =
+            serde_json::to_vec(value).map_err(|_| EncryptionError::KeyUpdateFailed)?;
+
+        let sealed_value = XChaCha20Poly1305::new(password_key.into())
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &plain_value,
+                    aad: KEYRING_AAD,
+                },
+            )
+            .map_err(|_| EncryptionError::KeyUpdateFailed)?;
+
+        Ok(WrappedKeyring {
+            salt,
+            m_cost: ARGON2_M_COST,
+            t_cost: ARGON2_T_COST,
+            p_cost: ARGON2_P_COST,
+            nonce,
+            keys: sealed_value,
+        })
+    }
+
+    fn open<T: DeserializeOwned>(&self, password_key: &[u8; 32]) -> Result<T, EncryptionError> {
+        if self.nonce.len() != NONCE_SIZE {
+            return Err(EncryptionError::RetrievingKey);
+        }
+
+        let plain_value = XChaCha20Poly1305::new(password_key.into())
+            .decrypt(
+                XNonce::from_slice(&self.nonce),
+                Payload {
+                    msg: &self.keys,
+                    aad: KEYRING_AAD,
+                },
+            )
+            .map_err(|_| EncryptionError::WrongKeyringPassword)?;
+
+        serde_json::from_slice(&plain_value).map_err(|_| EncryptionError::RetrievingKey)
+    }
+
+    fn derive_key(&self, password: &str) -> Result<[u8; 32], EncryptionError> {
+        derive_password_key(password, &self.salt, self.m_cost, self.t_cost, self.p_cost)
+    }
+}
+
+/// Seals `value` with a key derived from `password` and a fresh salt,
+/// independently of the config entries (used for key backups)
+pub fn seal_with_password<T: Serialize>(
+    value: &T,
+    password: &str,
+) -> Result<WrappedKeyring, EncryptionError> {
+    let salt = generate_encryption_key(SALT_SIZE);
+    let password_key =
+        derive_password_key(password, &salt, ARGON2_M_COST, ARGON2_T_COST, ARGON2_P_COST)?;
+
+    WrappedKeyring::seal(value, &password_key, salt)
+}
+
+pub fn open_with_password<T: DeserializeOwned>(
+    wrapped: &WrappedKeyring,
+    password: &str,
+) -> Result<T, EncryptionError> {
+    wrapped.open(&wrapped.derive_key(password)?)
+}
+
+fn derive_password_key(
+    password: &str,
+    salt: &[u8],
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+) -> Result<[u8; 32], EncryptionError> {
+    let params = Params::new(m_cost, t_cost, p_cost, Some(32))
+        .map_err(|_| EncryptionError::RetrievingKey)?;
+
+    let mut password_key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, &mut password_key)
+        .map_err(|_| EncryptionError::RetrievingKey)?;
+
+    Ok(password_key)
+}
+
+pub fn keyring_exists() -> Result<bool, EncryptionError> {
+    let encryption = Config::load()?.encryption;
+    Ok(encryption.keys.is_some() || encryption.wrapped_keys.is_some())
+}
+
+pub fn is_protected() -> Result<bool, EncryptionError> {
+    Ok(Config::load()?.encryption.wrapped_keys.is_some())
+}
+
+/// Returns the keyring, prompting once for the mucli password when it is protected
+pub fn read_keys() -> Result<Option<Vec<Vec<u8>>>, EncryptionError> {
+    let keys = read_entry(&KEYS)?;
+    Ok(keys.map(|keys| keys.into_iter().map(|key| key.0).collect()))
+}
+
+pub fn write_keys(keys: Vec<Vec<u8>>) -> Result<(), EncryptionError> {
+    write_entry(&KEYS, keys.into_iter().map(Bytes).collect())
+}
+
+pub fn read_identities() -> Result<Option<Vec<Identity>>, EncryptionError> {
+    read_entry(&IDENTITIES)
+}
+
+pub fn write_identities(identities: Vec<Identity>) -> Result<(), EncryptionError> {
+    write_entry(&IDENTITIES, identities)
+}
+
+fn read_entry<T: DeserializeOwned>(entry: &SecretEntry<T>) -> Result<Option<T>, EncryptionError> {
+    let mut config = Config::load()?;
+
+    if let Some(wrapped) = (entry.wrapped)(&mut config.encryption).take() {
+        let password_key = unlock(&wrapped)?;
+        return Ok(Some(wrapped.open(&password_key)?));
+    }
+
+    Ok((entry.plain)(&mut config.encryption).take())
+}
+
+fn write_entry<T: Serialize>(entry: &SecretEntry<T>, value: T) -> Result<(), EncryptionError> {
+    // the password is asked before the config is locked for writing
+    let password_key = match Config::load()?.encryption.wrapped_keys {
+        Some(primary) => Some(unlock(&primary)?),
+        None => None,
+    };
+
+    Config::update(|config| {
+        let encryption = &mut config.encryption;
+        match &encryption.wrapped_keys {
+            Some(primary) => {
+                let password_key = password_key.ok_or(EncryptionError::RetrievingKey)?;
+                let wrapped = WrappedKeyring::seal(&value, &password_key, primary.salt.clone())?;
+                *(entry.wrapped)(encryption) = Some(wrapped);
+            }
+            None => *(entry.plain)(encryption) = Some(value),
+        }
+        Ok(())
+    })
+}
+
+/// Wraps the plaintext keyring with a key derived from `password`
+pub fn protect(password: &str) -> Result<(), EncryptionError> {
+    if is_protected()? {
+        return Err(EncryptionError::KeyringAlreadyProtected);
+    }
+
+    let salt = generate_encryption_key(SALT_SIZE);
+    let password_key =
+        derive_password_key(password, &salt, ARGON2_M_COST, ARGON2_T_COST, ARGON2_P_COST)?;
+
+    Config::update(|config| {
+        let encryption = &mut config.encryption;
+        if encryption.wrapped_keys.is_some() {
+            return Err(EncryptionError::KeyringAlreadyProtected);
+        }
+
+        // the keyring entry always exists once protected, it marks the protected mode,
+        // so it gets its first key now rather than staying empty
+        encryption
+            .keys
+            .get_or_insert_with(|| vec![Bytes(generate_encryption_key(32))]);
+        seal_entry(
+            &mut encryption.keys,
+            &mut encryption.wrapped_keys,
+            &password_key,
+            &salt,
+        )?;
+        seal_entry(
+            &mut encryption.identities,
+            &mut encryption.wrapped_identities,
+            &password_key,
+            &salt,
+        )
+    })?;
+
+    *UNLOCKED_KEY.lock().unwrap() = Some(password_key);
+    Ok(())
+}
+
+/// Stores the keyring back in plaintext
+pub fn unprotect(password: &str) -> Result<(), EncryptionError> {
+    let password_key = wrapped_keyring()?.derive_key(password)?;
+
+    Config::update(|config| {
+        let encryption = &mut config.encryption;
+        open_entry(
+            &mut encryption.wrapped_keys,
+            &mut encryption.keys,
+            &password_key,
+        )?;
+        open_entry(
+            &mut encryption.wrapped_identities,
+            &mut encryption.identities,
+            &password_key,
+        )
+    })?;
+
+    *UNLOCKED_KEY.lock().unwrap() = None;
+    Ok(())
+}
+
+/// Re-wraps the keyring for a new password, encrypted files are left untouched
+pub fn rewrap(old_password: &str, new_password: &str) -> Result<(), EncryptionError> {
+    let old_password_key = wrapped_keyring()?.derive_key(old_password)?;
+
+    let salt = generate_encryption_key(SALT_SIZE);
+    let password_key = derive_password_key(
+        new_password,
+        &salt,
+        ARGON2_M_COST,
+        ARGON2_T_COST,
+        ARGON2_P_COST,
+    )?;
+
+    Config::update(|config| {
+        let encryption = &mut config.encryption;
+        for sealed in [
+            &mut encryption.wrapped_keys,
+            &mut encryption.wrapped_identities,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            let value: Value = sealed.open(&old_password_key)?;
+            *sealed = WrappedKeyring::seal(&value, &password_key, salt.clone())?;
+        }
+        Ok::<(), EncryptionError>(())
+    })?;
+
+    *UNLOCKED_KEY.lock().unwrap() = Some(password_key);
+    Ok(())
+}
+
+fn seal_entry<T: Serialize>(
+    plain: &mut Option<T>,
+    wrapped: &mut Option<WrappedKeyring>,
+    password_key: &[u8; 32],
+    salt: &[u8],
+) -> Result<(), EncryptionError> {
+    if let Some(value) = plain.take() {
+        *wrapped = Some(WrappedKeyring::seal(&value, password_key, salt.to_vec())?);
+    }
+    Ok(())
+}
+
+fn open_entry<T: DeserializeOwned>(
+    wrapped: &mut Option<WrappedKeyring>,
+    plain: &mut Option<T>,
+    password_key: &[u8; 32],
+) -> Result<(), EncryptionError> {
+    if let Some(sealed) = wrapped.take() {
+        *plain = Some(sealed.open(password_key)?);
+    }
+    Ok(())
+}
+
+fn wrapped_keyring() -> Result<WrappedKeyring, EncryptionError> {
+    Config::load()?
+        .encryption
+        .wrapped_keys
+        .ok_or(EncryptionError::KeyringNotProtected)
+}
+
+/// Unlocks protected encryption keys with an already known password,
+/// so that they are not asked for again during this invocation
+pub fn unlock_with_password(password: &str) -> Result<(), EncryptionError> {
+    if let Some(password_key) = password_key(password)? {
+        *UNLOCKED_KEY.lock().unwrap() = Some(password_key);
+    }
+    Ok(())
+}
+
+/// Key opening the protected encryption keys, None when they are not protected
+pub fn password_key(password: &str) -> Result<Option<[u8; 32]>, EncryptionError> {
+    let wrapped = match Config::load()?.encryption.wrapped_keys {
+        Some(wrapped) => wrapped,
+        None => return Ok(None),
+    };
+
+    let password_key = wrapped.derive_key(password)?;
+    wrapped.open::<Value>(&password_key)?;
+    Ok(Some(password_key))
+}
+
+fn unlock(wrapped: &WrappedKeyring) -> Result<[u8; 32], EncryptionError> {
+    if let Some(password_key) = *UNLOCKED_KEY.lock().unwrap() {
+        return Ok(password_key);
+    }
+
+    // a key left by "mucli unlock" is outdated once the password changed
+    if let Some(password_key) = agent::session().and_then(|session| session.password_key) {
+        if wrapped.open::<Value>(&password_key).is_ok() {
+            *UNLOCKED_KEY.lock().unwrap() = Some(password_key);
+            return Ok(password_key);
+        }
+    }
+
+    let password = prompt::password("Enter your password to unlock encryption keys")?;
+    let password_key = wrapped.derive_key(&password)?;
+
+    // fail early on a wrong password instead of caching a useless key
+    wrapped.open::<Value>(&password_key)?;
+
+    *UNLOCKED_KEY.lock().unwrap() = Some(password_key);
+    Ok(password_key)
+}
@@ -0,0 +1,210 @@
+use std::{fs, path::Path};
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use crate::utils::{
+    bytes,
+    file::{AeadHeader, RecipientStanza, MAX_RECIPIENTS, NONCE_SIZE},
+    generate_encryption_key,
+};
+
+use super::{keyring, EncryptionError};
+
+pub const PUBLIC_KEY_PREFIX: &str = "mucli-pk-";
+const WRAP_KEY_INFO: &[u8] = b"mucli recipient";
+
+/// X25519 key pair used to decrypt files encrypted for its public key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Identity {
+    pub name: String,
+    #[serde(with = "bytes::base64")]
+    secret: Vec<u8>,
+}
+
+impl Identity {
+    fn generate(name: &str) -> Self {
+        Identity {
+            name: name.to_string(),
+            secret: StaticSecret::random_from_rng(OsRng).to_bytes().to_vec(),
+        }
+    }
+
+    fn secret(&self) -> Result<StaticSecret, EncryptionError> {
+        let bytes: [u8; 32] = self
+            .secret
+            .as_slice()
+            .try_into()
+            .map_err(|_| EncryptionError::RetrievingKey)?;
+        Ok(StaticSecret::from(bytes))
+    }
+
+    pub fn public_key(&self) -> Result<String, EncryptionError> {
+        Ok(encode_public_key(&PublicKey::from(&self.secret()?)))
+    }
+}
+
+pub fn read_identities() -> Result<Vec<Identity>, EncryptionError> {
+    Ok(keyring::read_identities()?.unwrap_or_default())
+}
+
+/// Creates a new identity and returns its public key
+pub fn generate_identity(name: &str) -> Result<String, EncryptionError> {
+    let mut identities = read_identities()?;
+    if identities.iter().any(|identity| identity.name == name) {
+        return Err(EncryptionError::IdentityExists {
+            name: name.to_string(),
+        });
+    }
+
+    let identity = Identity::generate(name);
+    let public_key = identity.public_key()?;
+    identities.push(identity);
+    keyring::write_identities(identities)?;
+
+    Ok(public_key)
+}
+
+pub fn encode_public_key(public_key: &PublicKey) -> String {
+    let hex: String = public_key
+        .as_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("{}{}", PUBLIC_KEY_PREFIX, hex)
+}
+
+pub fn decode_public_key(text: &str) -> Result<PublicKey, EncryptionError> {
+    let invalid = || EncryptionError::InvalidRecipient {
+        recipient: text.to_string(),
+    };
+
+    let hex = text
+        .trim()
+        .strip_prefix(PUBLIC_KEY_PREFIX)
+        .ok_or_else(invalid)?;
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+
+    let mut bytes = [0u8; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+
+    Ok(PublicKey::from(bytes))
+}
+
+/// Resolves every `--recipient` value, which is either a public key or a file listing
+/// public keys one per line (blank lines and `#` comments are ignored)
+pub fn parse_recipients(values: &[String]) -> Result<Vec<PublicKey>, EncryptionError> {
+    let mut recipients = vec![];
+
+    for value in values {
+        if value.starts_with(PUBLIC_KEY_PREFIX) {
+            recipients.push(decode_public_key(value)?);
+            continue;
+        }
+
+        if !Path::new(value).is_file() {
+            return Err(EncryptionError::InvalidRecipient {
+                recipient: value.to_string(),
+            });
+        }
+        for line in fs::read_to_string(value)?.lines() {
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                recipients.push(decode_public_key(line)?);
+            }
+        }
+    }
+
+    if recipients.len() > MAX_RECIPIENTS {
+        return Err(EncryptionError::TooManyRecipients {
+            max: MAX_RECIPIENTS,
+        });
+    }
+
+    Ok(recipients)
+}
+
+/// Generates a random file key and seals it for every recipient
+pub fn wrap_file_key(
+    recipients: &[PublicKey],
+) -> Result<(Vec<u8>, Vec<RecipientStanza>), EncryptionError> {
+    let file_key = generate_encryption_key(32);
+
+    let stanzas = recipients
+        .iter()
+        .map(|recipient| {
+            let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+            let ephemeral_public = PublicKey::from(&ephemeral_secret);
+            let shared_secret = ephemeral_secret.diffie_hellman(recipient);
+            if !shared_secret.was_contributory() {
+                return Err(EncryptionError::InvalidRecipient {
+                    recipient: encode_public_key(recipient),
+                });
+            }
+
+            let nonce = generate_encryption_key(NONCE_SIZE);
+            let wrapped_key = wrap_cipher(shared_secret.as_bytes(), &ephemeral_public, recipient)?
+                .encrypt(XNonce::from_slice(&nonce), file_key.as_slice())
+                .map_err(|_| EncryptionError::KeyUpdateFailed)?;
+
+            Ok(RecipientStanza {
+                ephemeral_public: ephemeral_public.to_bytes(),
+                nonce: nonce.try_into().unwrap(),
+                wrapped_key: wrapped_key
+                    .try_into()
+                    .map_err(|_| EncryptionError::KeyUpdateFailed)?,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((file_key, stanzas))
+}
+
+/// Tries every local identity against every recipient of the file to recover its file key
+pub fn unwrap_file_key(header: &AeadHeader) -> Result<Vec<u8>, EncryptionError> {
+    for identity in read_identities()? {
+        let secret = identity.secret()?;
+        let public_key = PublicKey::from(&secret);
+
+        for stanza in &header.recipients {
+            let ephemeral_public = PublicKey::from(stanza.ephemeral_public);
+            let shared_secret = secret.diffie_hellman(&ephemeral_public);
+
+            let file_key = wrap_cipher(shared_secret.as_bytes(), &ephemeral_public, &public_key)?
+                .decrypt(
+                    XNonce::from_slice(&stanza.nonce),
+                    stanza.wrapped_key.as_slice(),
+                );
+            if let Ok(file_key) = file_key {
+                return Ok(file_key);
+            }
+        }
+    }
+
+    Err(EncryptionError::NoMatchingIdentity)
+}
+
+fn wrap_cipher(
+    shared_secret: &[u8],
+    ephemeral_public: &PublicKey,
+    recipient: &PublicKey,
+) -> Result<XChaCha20Poly1305, EncryptionError> {
+    let mut salt = ephemeral_public.as_bytes().to_vec();
+    salt.extend_from_slice(recipient.as_bytes());
+
+    let mut wrap_key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt), shared_secret)
+        .expand(WRAP_KEY_INFO, &mut wrap_key)
+        .map_err(|_| EncryptionError::RetrievingKey)?;
+
+    Ok(XChaCha20Poly1305::new(&wrap_key.into()))
+}
@@ -0,0 +1,153 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::utils::{
+    terminal::arrow_progress,
+    walk::{walk_files, PathFilter},
+};
+
+use super::{
+    annex::{needs_rotation, retrieve_encryption_keys, rotate_file},
+    directory::BatchSummary,
+    latest_encryption_version, EncryptionError,
+};
+
+// written at the root of the rotated directory while a rotation is running
+pub const JOURNAL_FILE_NAME: &str = ".mucli-rotation.json";
+
+/// Files still to rotate, relative to the rotated directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Journal {
+    pending: Vec<PathBuf>,
+}
+
+impl Journal {
+    fn read(path: &Path) -> Result<Option<Self>, EncryptionError> {
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|_| EncryptionError::InvalidJournal {
+                path: path.display().to_string(),
+            })
+    }
+
+    // the journal itself is replaced atomically so that a crash never leaves it unreadable
+    fn write(&self, path: &Path) -> Result<(), EncryptionError> {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let mut temp_file = NamedTempFile::new_in(dir)?;
+        let content = serde_json::to_vec(self).map_err(|_| EncryptionError::KeyUpdateFailed)?;
+        temp_file.write_all(&content)?;
+        temp_file
+            .persist(path)
+            .map_err(|e| EncryptionError::Io { source: e.error })?;
+        Ok(())
+    }
+}
+
+pub struct RotationRun {
+    pub resumed: bool,
+    pub summary: BatchSummary,
+}
+
+/// Re-encrypts with the latest key every file of `root` encrypted with an older one,
+/// resuming from the journal of an interrupted run when there is one
+pub fn rotate_directory(root: &Path, workers: usize) -> Result<RotationRun, EncryptionError> {
+    let journal_path = root.join(JOURNAL_FILE_NAME);
+    let latest_version = latest_encryption_version()?;
+    // unlock a protected keyring once before the workers need it
+    retrieve_encryption_keys()?;
+
+    let (journal, resumed) = match Journal::read(&journal_path)? {
+        Some(journal) => (journal, true),
+        None => {
+            let filter = PathFilter::new(&[], &[JOURNAL_FILE_NAME.to_string()])?;
+
+            let mut pending = vec![];
+            for file in walk_files(root, &filter)? {
+                // unreadable files are reported when rotated
+                if needs_rotation(&file, latest_version).unwrap_or(true) {
+                    pending.push(file.strip_prefix(root).unwrap_or(&file).to_path_buf());
+                }
+            }
+            (Journal { pending }, false)
+        }
+    };
+
+    let mut summary = BatchSummary::default();
+    if journal.pending.is_empty() {
+        if resumed {
+            fs::remove_file(&journal_path)?;
+        }
+        return Ok(RotationRun { resumed, summary });
+    }
+    journal.write(&journal_path)?;
+
+    let files = journal.pending.clone();
+    let journal = Mutex::new(journal);
+    let summary_lock = Mutex::new(BatchSummary::default());
+    let next_file = AtomicUsize::new(0);
+
+    let progress = arrow_progress(files.len() as u64);
+    progress.set_prefix("Rotating keys...");
+
+    thread::scope(|scope| {
+        for _ in 0..workers.max(1) {
+            scope.spawn(|| loop {
+                let index = next_file.fetch_add(1, Ordering::SeqCst);
+                let relative_path = match files.get(index) {
+                    Some(relative_path) => relative_path,
+                    None => break,
+                };
+                let file = root.join(relative_path);
+
+                // a file rotated before an interruption is already at the latest version
+                let result = match needs_rotation(&file, latest_version) {
+                    Ok(true) => rotate_file(&file).map(|_| true),
+                    Ok(false) => Ok(false),
+                    Err(e) => Err(e),
+                };
+
+                let mut summary = summary_lock.lock().unwrap();
+                match result {
+                    Ok(true) => summary.succeeded += 1,
+                    Ok(false) => summary.skipped += 1,
+                    Err(e) => {
+                        summary.failed.push((file, e));
+                        progress.inc(1);
+                        continue;
+                    }
+                }
+
+                let mut journal = journal.lock().unwrap();
+                journal.pending.retain(|pending| pending != relative_path);
+                if let Err(e) = journal.write(&journal_path) {
+                    summary.failed.push((journal_path.clone(), e));
+                }
+                progress.inc(1);
+            });
+        }
+    });
+    progress.finish_and_clear();
+
+    summary = summary_lock.into_inner().unwrap();
+    // failed files stay in the journal so that the next run retries them
+    if summary.failed.is_empty() {
+        fs::remove_file(&journal_path)?;
+    }
+
+    Ok(RotationRun { resumed, summary })
+}
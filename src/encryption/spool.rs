@@ -0,0 +1,73 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+use chacha20::{
+    cipher::{KeyIvInit, StreamCipher, StreamCipherSeek},
+    ChaCha20,
+};
+
+/// Unnamed temporary file ciphered with a key only kept in memory, for content that has to be
+/// written with seeks or read back before being sealed. It is deleted once dropped and its
+/// plain content never reaches the disk
+pub struct Spool {
+    file: File,
+    cipher: ChaCha20,
+    position: u64,
+}
+
+impl Spool {
+    pub fn new() -> io::Result<Spool> {
+        let key: [u8; 32] = rand::random();
+        let nonce: [u8; 12] = rand::random();
+
+        Ok(Spool {
+            file: tempfile::tempfile()?,
+            cipher: ChaCha20::new(&key.into(), &nonce.into()),
+            position: 0,
+        })
+    }
+
+    // the keystream is positioned on the offset of the bytes, so that a byte
+    // is ciphered the same way whenever it is written or read
+    fn apply_keystream(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.cipher
+            .try_seek(self.position)
+            .and_then(|_| self.cipher.try_apply_keystream(buf))
+            .map_err(|_| io::Error::other("temporary file too large"))
+    }
+}
+
+impl Write for Spool {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut ciphered = buf.to_vec();
+        self.apply_keystream(&mut ciphered)?;
+
+        // written in full so that the file stays at the position the keystream was taken from
+        self.file.write_all(&ciphered)?;
+        self.position += ciphered.len() as u64;
+        Ok(ciphered.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Read for Spool {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.file.read(buf)?;
+        self.apply_keystream(&mut buf[..read])?;
+
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for Spool {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = self.file.seek(pos)?;
+        Ok(self.position)
+    }
+}
@@ -0,0 +1,57 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::utils::{config::lock_file, GenericError};
+
+use super::{
+    annex::{decrypt_in_memory, encrypt_stream, init_encryption_key},
+    EncryptionError,
+};
+
+/// Reads a document sealed by `update_sealed` under a shared lock,
+/// None when it was never written
+pub fn read_sealed<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, EncryptionError> {
+    let _lock = lock_file(&lock_path(path), false)?;
+    open_sealed(path)
+}
+
+/// Reads, modifies and seals back a document with the latest encryption key while
+/// holding an exclusive lock, nothing is written when `modify` fails
+pub fn update_sealed<T, R, E, F>(path: &Path, modify: F) -> Result<R, E>
+where
+    T: DeserializeOwned + Serialize + Default,
+    E: From<EncryptionError>,
+    F: FnOnce(&mut T) -> Result<R, E>,
+{
+    let _lock = lock_file(&lock_path(path), true).map_err(EncryptionError::from)?;
+
+    let mut document: T = open_sealed(path)?.unwrap_or_default();
+    let result = modify(&mut document)?;
+
+    init_encryption_key()?;
+    let content =
+        serde_json::to_vec(&document).map_err(|e| EncryptionError::from(GenericError::from(e)))?;
+    encrypt_stream(content.as_slice(), path)?;
+    Ok(result)
+}
+
+fn open_sealed<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, EncryptionError> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = decrypt_in_memory(path)?;
+    Ok(Some(
+        serde_json::from_slice(&content).map_err(GenericError::from)?,
+    ))
+}
+
+// next to the document, as in "config.vault.lock"
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock_path = OsString::from(path.as_os_str());
+    lock_path.push(".lock");
+    PathBuf::from(lock_path)
+}
@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+
+use clap::ArgMatches;
+use custom_error::custom_error;
+use rand::seq::SliceRandom;
+
+use crate::{
+    password::unlock_gate,
+    print_err, print_info, print_solution, print_success,
+    qrcode::render_qrcode,
+    vault::{store_entry, EntryKind, VaultEntry, VaultError},
+};
+
+custom_error! {pub GenerateError
+    NoCharacterClass = "Every character class was excluded",
+    TooShort{length: usize, min: usize} = "A password of {length} characters cannot hold a character of each class, {min} at least are needed"
+}
+
+pub const DEFAULT_PASSWORD_LENGTH: &str = "20";
+pub const DEFAULT_WORD_COUNT: &str = "6";
+pub const DEFAULT_SEPARATOR: &str = "-";
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!#$%&()*+,-./:;<=>?@[]^_{|}~";
+// characters easily mistaken for one another when read or typed
+const AMBIGUOUS: &str = "Il1|O0o";
+
+// BIP-39 english list, 2048 words of 3 to 8 letters
+const WORDLIST: &str = include_str!("generate/wordlist.txt");
+
+pub fn generate_command(sub_matches: &ArgMatches) {
+    match sub_matches.subcommand() {
+        Some(("password", sub_matches)) => password_command(sub_matches),
+        Some(("passphrase", sub_matches)) => passphrase_command(sub_matches),
+        _ => (),
+    }
+}
+
+fn password_command(sub_matches: &ArgMatches) {
+    let length = match sub_matches.get_one::<u64>("length") {
+        Some(length) => *length as usize,
+        None => return,
+    };
+    let classes: Vec<&str> = [
+        (LOWERCASE, "no-lower"),
+        (UPPERCASE, "no-upper"),
+        (DIGITS, "no-digits"),
+        (SYMBOLS, "no-symbols"),
+    ]
+    .iter()
+    .filter(|(_, excluded)| !sub_matches.get_flag(excluded))
+    .map(|(class, _)| *class)
+    .collect();
+
+    match generate_password(length, &classes, sub_matches.get_flag("exclude-ambiguous")) {
+        Ok((password, entropy)) => output(sub_matches, &password, entropy),
+        Err(e) => print_err!("{}", e),
+    }
+}
+
+fn passphrase_command(sub_matches: &ArgMatches) {
+    let words = match sub_matches.get_one::<u64>("words") {
+        Some(words) => *words as usize,
+        None => return,
+    };
+    let separator = sub_matches
+        .get_one::<String>("separator")
+        .map(|separator| separator.as_str())
+        .unwrap_or(DEFAULT_SEPARATOR);
+
+    let (passphrase, entropy) =
+        generate_passphrase(words, separator, sub_matches.get_flag("capitalize"));
+    output(sub_matches, &passphrase, entropy);
+}
+
+/// Random password of `length` characters holding at least one character of each class,
+/// returned with its entropy in bits
+pub fn generate_password(
+    length: usize,
+    classes: &[&str],
+    exclude_ambiguous: bool,
+) -> Result<(String, f64), GenerateError> {
+    let classes: Vec<Vec<char>> = classes
+        .iter()
+        .map(|class| {
+            class
+                .chars()
+                .filter(|c| !exclude_ambiguous || !AMBIGUOUS.contains(*c))
+                .collect::<Vec<char>>()
+        })
+        .filter(|class| !class.is_empty())
+        .collect();
+    if classes.is_empty() {
+        return Err(GenerateError::NoCharacterClass);
+    }
+    if length < classes.len() {
+        return Err(GenerateError::TooShort {
+            length,
+            min: classes.len(),
+        });
+    }
+
+    let pool: Vec<char> = classes.concat();
+    let mut rng = rand::thread_rng();
+
+    let mut password: Vec<char> = classes
+        .iter()
+        .filter_map(|class| class.choose(&mut rng).copied())
+        .collect();
+    while password.len() < length {
+        password.extend(pool.choose(&mut rng));
+    }
+    // the characters taken from each class are not left at the start
+    password.shuffle(&mut rng);
+
+    let entropy = length as f64 * (pool.len() as f64).log2();
+    Ok((password.into_iter().collect(), entropy))
+}
+
+/// Random words of the embedded list joined by `separator`, returned with its entropy in bits
+pub fn generate_passphrase(words: usize, separator: &str, capitalize: bool) -> (String, f64) {
+    let wordlist: Vec<&str> = WORDLIST.lines().collect();
+    let mut rng = rand::thread_rng();
+
+    let passphrase: Vec<String> = (0..words)
+        .filter_map(|_| wordlist.choose(&mut rng))
+        .map(|word| match capitalize {
+            true => capitalized(word),
+            false => word.to_string(),
+        })
+        .collect();
+
+    let entropy = words as f64 * (wordlist.len() as f64).log2();
+    (passphrase.join(separator), entropy)
+}
+
+fn capitalized(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// rough guidance for secrets an attacker can try offline
+fn strength(entropy: f64) -> &'static str {
+    match entropy {
+        entropy if entropy < 40.0 => "weak",
+        entropy if entropy < 64.0 => "fair",
+        entropy if entropy < 100.0 => "strong",
+        _ => "very strong",
+    }
+}
+
+// prints the secret, or stores it in the vault, and draws it as a QR code when asked
+fn output(sub_matches: &ArgMatches, secret: &str, entropy: f64) {
+    match sub_matches.get_one::<String>("vault") {
+        Some(name) => {
+            let force = sub_matches.get_flag("force");
+            if force && !unlock_gate("vault") {
+                return;
+            }
+            let fields = BTreeMap::from([(
+                EntryKind::Password.main_field().to_string(),
+                secret.to_string(),
+            )]);
+            let entry = VaultEntry::new(EntryKind::Password, fields);
+            match store_entry(name, entry, force) {
+                Ok(_) => print_success!("Generated secret stored in the vault as \"{}\"", name),
+                Err(VaultError::EntryExists { name }) => {
+                    print_err!("\"{}\" is already in the vault", name);
+                    print_solution!("Use --force to replace it");
+                    return;
+                }
+                Err(e) => {
+                    print_err!("(vault add failed): {}", e);
+                    return;
+                }
+            }
+        }
+        None => println!("{}", secret),
+    }
+
+    if sub_matches.get_flag("qr") {
+        match render_qrcode(secret) {
+            Ok(code) => print!("{}", code),
+            Err(e) => print_err!("{}", e),
+        }
+    }
+    print_info!(
+        "About {:.0} bits of entropy, {}",
+        entropy.floor(),
+        strength(entropy)
+    );
+}
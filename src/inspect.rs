@@ -0,0 +1,262 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::ArgMatches;
+use serde::Serialize;
+
+use crate::{
+    encryption::{
+        keyring::{is_protected, read_keys},
+        recipient::unwrap_file_key,
+        EncryptionError,
+    },
+    print_err, print_info,
+    utils::{
+        file::{CryptedFile, FileFormat, KDF_X25519, SINGLE_SHOT_FORMAT_VERSION},
+        walk::{walk_files, PathFilter},
+    },
+};
+
+/// What can be told about a file from its header alone
+#[derive(Debug, Default, Serialize)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub encrypted: bool,
+    // "none", "legacy" or "aead"
+    pub marker: String,
+    // whether the header after the marker could be parsed
+    pub valid: bool,
+    pub format_version: Option<u8>,
+    pub layers: u32,
+    pub key_version: Option<u32>,
+    pub recipients: Option<usize>,
+    pub chunk_size: Option<u32>,
+    pub payload_size: u64,
+    // unknown while the keyring is protected
+    pub key_available: Option<bool>,
+    pub error: Option<String>,
+}
+
+pub fn inspect_command(sub_matches: &ArgMatches) {
+    let path = match sub_matches.get_one::<PathBuf>("PATH") {
+        Some(path) => path,
+        None => return,
+    };
+    let json = sub_matches.get_flag("json");
+
+    if !path.exists() {
+        print_err!("{:?} does not exist!", path);
+        return;
+    }
+
+    let reports = if path.is_dir() {
+        match walk_files(path, &PathFilter::default()) {
+            Ok(files) => files
+                .iter()
+                .map(|file| inspect_file(file))
+                .filter(|report| report.encrypted || !report.valid)
+                .collect(),
+            Err(e) => {
+                print_err!("{}", e);
+                return;
+            }
+        }
+    } else {
+        vec![inspect_file(path)]
+    };
+
+    if json {
+        let output = if path.is_dir() {
+            serde_json::to_string_pretty(&reports)
+        } else {
+            serde_json::to_string_pretty(&reports[0])
+        };
+        match output {
+            Ok(output) => println!("{}", output),
+            Err(e) => print_err!("{}", e),
+        }
+        return;
+    }
+
+    if path.is_dir() {
+        if reports.is_empty() {
+            print_info!("No encrypted file found in {:?}", path);
+        }
+        for report in &reports {
+            println!("{}", report.summary_line());
+        }
+    } else {
+        reports[0].print();
+    }
+}
+
+pub fn inspect_file(path: &Path) -> FileReport {
+    let mut report = FileReport {
+        path: path.to_path_buf(),
+        marker: "none".to_string(),
+        valid: true,
+        ..Default::default()
+    };
+
+    let size = match fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            report.valid = false;
+            report.error = Some(e.to_string());
+            return report;
+        }
+    };
+
+    let format = match CryptedFile::read_only(path.to_path_buf()).format() {
+        Ok(format) => format,
+        Err(EncryptionError::Io { source }) => {
+            report.valid = false;
+            report.error = Some(source.to_string());
+            return report;
+        }
+        Err(e) => {
+            // only authenticated headers are parsed beyond the marker
+            report.marker = "aead".to_string();
+            report.encrypted = true;
+            report.valid = false;
+            report.error = Some(e.to_string());
+            return report;
+        }
+    };
+    report.payload_size = size.saturating_sub(format.header_size() as u64);
+
+    match &format {
+        FileFormat::Plain => (),
+        FileFormat::Legacy { layer, version } => {
+            report.marker = "legacy".to_string();
+            report.encrypted = *layer > 0;
+            report.format_version = Some(1);
+            report.layers = *layer;
+            report.key_version = Some(*version);
+        }
+        FileFormat::Aead(header) => {
+            report.marker = "aead".to_string();
+            report.encrypted = true;
+            report.format_version = Some(header.format_version);
+            report.layers = header.layer;
+            if header.kdf == KDF_X25519 {
+                report.recipients = Some(header.recipients.len());
+            } else {
+                report.key_version = Some(header.key_version);
+            }
+            if header.format_version != SINGLE_SHOT_FORMAT_VERSION {
+                report.chunk_size = Some(header.chunk_size);
+            }
+        }
+    }
+
+    if report.encrypted {
+        match key_available(&format) {
+            Ok(available) => report.key_available = available,
+            Err(e) => report.error = Some(e.to_string()),
+        }
+    }
+
+    report
+}
+
+// never prompts for the mucli password, a protected keyring is reported as unknown
+fn key_available(format: &FileFormat) -> Result<Option<bool>, EncryptionError> {
+    if is_protected()? {
+        return Ok(None);
+    }
+
+    let version = match format {
+        FileFormat::Plain => return Ok(Some(false)),
+        FileFormat::Legacy { version, .. } => *version,
+        FileFormat::Aead(header) if header.kdf == KDF_X25519 => {
+            return match unwrap_file_key(header) {
+                Ok(_) => Ok(Some(true)),
+                Err(EncryptionError::NoMatchingIdentity) => Ok(Some(false)),
+                Err(e) => Err(e),
+            };
+        }
+        FileFormat::Aead(header) => header.key_version,
+    };
+
+    let keys = read_keys()?.unwrap_or_default();
+    Ok(Some((version as usize) < keys.len()))
+}
+
+impl FileReport {
+    fn format_name(&self) -> String {
+        match self.format_version {
+            Some(version) => format!("v{}", version),
+            None => "unknown".to_string(),
+        }
+    }
+
+    fn key_name(&self) -> String {
+        match (self.key_version, self.recipients) {
+            (Some(version), _) => format!("key version {}", version),
+            (None, Some(recipients)) => format!("{} recipient(s)", recipients),
+            (None, None) => "unknown key".to_string(),
+        }
+    }
+
+    fn availability(&self) -> &str {
+        match self.key_available {
+            Some(true) => "available",
+            Some(false) => "missing",
+            None => "unknown",
+        }
+    }
+
+    fn summary_line(&self) -> String {
+        if !self.valid {
+            return format!(
+                "{}\tinvalid header: {}",
+                self.path.display(),
+                self.error.clone().unwrap_or_default()
+            );
+        }
+        format!(
+            "{}\t{}\t{} layer(s)\t{}\tkey {}",
+            self.path.display(),
+            self.format_name(),
+            self.layers,
+            self.key_name(),
+            self.availability()
+        )
+    }
+
+    fn print(&self) {
+        println!("file:          {}", self.path.display());
+        if !self.valid {
+            println!("marker:        {}", self.marker);
+            println!(
+                "header:        invalid ({})",
+                self.error.clone().unwrap_or_default()
+            );
+            return;
+        }
+        if !self.encrypted {
+            println!("encrypted:     no");
+            return;
+        }
+
+        println!("encrypted:     yes");
+        println!("marker:        {}", self.marker);
+        println!("format:        {}", self.format_name());
+        println!("layers:        {}", self.layers);
+        println!("key:           {}", self.key_name());
+        if let Some(chunk_size) = self.chunk_size {
+            println!("chunk size:    {} bytes", chunk_size);
+        }
+        println!("payload size:  {} bytes", self.payload_size);
+        match self.key_available {
+            Some(_) => println!("local key:     {}", self.availability()),
+            None => println!("local key:     unknown, encryption keys are protected"),
+        }
+        if let Some(error) = &self.error {
+            println!("error:         {}", error);
+        }
+    }
+}
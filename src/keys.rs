@@ -0,0 +1,214 @@
+use std::{fs, path::PathBuf, thread};
+
+use clap::ArgMatches;
+
+use crate::{
+    encryption::{
+        backup::{export_backup, export_recovery, import_backup, write_private, ImportOutcome},
+        init_encryption_key, init_new_encryption_key,
+        recipient::{generate_identity, read_identities},
+        rotate::{rotate_directory, JOURNAL_FILE_NAME},
+        EncryptionError,
+    },
+    password::unlock_gate,
+    print_err, print_info, print_solution, print_success,
+    utils::prompt,
+};
+
+const DEFAULT_IDENTITY_NAME: &str = "default";
+
+pub fn keys_command(sub_matches: &ArgMatches) {
+    match sub_matches.subcommand() {
+        Some(("generate", sub_matches)) => generate_command(sub_matches),
+        Some(("list", _)) => list_command(),
+        Some(("export", sub_matches)) => export_command(sub_matches),
+        Some(("import", sub_matches)) => import_command(sub_matches),
+        Some(("rotate", sub_matches)) => rotate_command(sub_matches),
+        _ => unreachable!("Exhausted list of subcommands and subcommand_required prevents `None`"),
+    }
+}
+
+fn generate_command(sub_matches: &ArgMatches) {
+    let name = sub_matches
+        .get_one::<String>("NAME")
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_IDENTITY_NAME);
+
+    let public_key = match generate_identity(name) {
+        Ok(public_key) => public_key,
+        Err(e) => {
+            print_err!("(identity generation failed): {}", e);
+            return;
+        }
+    };
+
+    print_success!("Identity \"{}\" generated!", name);
+    print_info!("Share this public key so that others can encrypt files for you:");
+    println!("{}", public_key);
+
+    if let Some(output_path) = sub_matches.get_one::<PathBuf>("output") {
+        match fs::write(output_path, format!("{}\n", public_key)) {
+            Ok(_) => print_success!("Public key saved as {:?}", output_path),
+            Err(e) => print_err!("Failed to save public key: {}", e),
+        }
+    }
+}
+
+fn list_command() {
+    let identities = match read_identities() {
+        Ok(identities) => identities,
+        Err(e) => {
+            print_err!("{}", e);
+            return;
+        }
+    };
+
+    if identities.is_empty() {
+        print_info!("No identity found");
+        print_solution!("Use \"mucli keys generate\" to create one");
+        return;
+    }
+
+    for identity in identities {
+        match identity.public_key() {
+            Ok(public_key) => println!("{}\t{}", identity.name, public_key),
+            Err(e) => print_err!("{}: {}", identity.name, e),
+        }
+    }
+}
+
+fn export_command(sub_matches: &ArgMatches) {
+    if !unlock_gate("keys export") {
+        return;
+    }
+    let output_path = sub_matches.get_one::<PathBuf>("FILE");
+
+    if sub_matches.get_flag("recovery") {
+        let sheet = match export_recovery() {
+            Ok(sheet) => sheet,
+            Err(e) => {
+                print_err!("(export failed): {}", e);
+                return;
+            }
+        };
+
+        match output_path {
+            Some(output_path) => match write_private(output_path, sheet.text.as_bytes()) {
+                Ok(_) => print_success!("Recovery sheet saved as {:?}", output_path),
+                Err(e) => {
+                    print_err!("Failed to save recovery sheet: {}", e);
+                    return;
+                }
+            },
+            None => print!("{}", sheet.text),
+        }
+        // only once the sheet exists, "encrypt --purge" relies on it
+        if let Err(e) = sheet.mark_saved() {
+            print_err!("{}", e);
+        }
+        print_info!("Anyone holding this sheet can decrypt your files, keep it offline");
+        return;
+    }
+
+    let output_path = match output_path {
+        Some(output_path) => output_path,
+        None => return,
+    };
+    let password =
+        match prompt::new_password("Choose a password for the backup", "Confirm the password") {
+            Ok(password) => password,
+            Err(e) => {
+                print_err!("{}", e);
+                return;
+            }
+        };
+
+    match export_backup(output_path, &password) {
+        Ok(_) => print_success!("Encryption keys backed up as {:?}", output_path),
+        Err(e) => print_err!("(export failed): {}", e),
+    }
+}
+
+fn import_command(sub_matches: &ArgMatches) {
+    if !unlock_gate("keys import") {
+        return;
+    }
+    let input_path = match sub_matches.get_one::<PathBuf>("FILE") {
+        Some(input_path) => input_path,
+        None => return,
+    };
+
+    let password = || -> Result<String, EncryptionError> {
+        Ok(prompt::password("Enter the backup password")?)
+    };
+
+    match import_backup(input_path, password, sub_matches.get_flag("force")) {
+        Ok(ImportOutcome::Restored { keys, identities }) => print_success!(
+            "{} encryption key(s) and {} identity(ies) imported",
+            keys,
+            identities
+        ),
+        Ok(ImportOutcome::UpToDate { identities }) => print_success!(
+            "Encryption keys already up to date, {} identity(ies) imported",
+            identities
+        ),
+        Err(EncryptionError::KeyringMismatch) => {
+            print_err!("(import failed): {}", EncryptionError::KeyringMismatch);
+            print_solution!("Export the local keys first, then use \"keys import --force\"");
+        }
+        Err(e) => print_err!("(import failed): {}", e),
+    }
+}
+
+fn rotate_command(sub_matches: &ArgMatches) {
+    if !unlock_gate("keys rotate") {
+        return;
+    }
+    let root = match sub_matches.get_one::<PathBuf>("DIR") {
+        Some(root) => root,
+        None => return,
+    };
+    if !root.is_dir() {
+        print_err!("{:?} is not a directory", root);
+        return;
+    }
+    if let Err(e) = init_encryption_key() {
+        print_err!("{}", e);
+        return;
+    }
+
+    if sub_matches.get_flag("new-key") {
+        if root.join(JOURNAL_FILE_NAME).exists() {
+            print_err!("A previous rotation of {:?} was interrupted", root);
+            print_solution!("Run \"keys rotate\" without --new-key to finish it first");
+            return;
+        }
+        if let Err(e) = init_new_encryption_key() {
+            print_err!("Error updating encryption key: {}", e);
+            return;
+        }
+        print_success!("New encryption key generated");
+    }
+
+    let workers = sub_matches
+        .get_one::<usize>("jobs")
+        .copied()
+        .unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(|workers| workers.get())
+                .unwrap_or(1)
+        });
+
+    match rotate_directory(root, workers) {
+        Ok(run) => {
+            if run.resumed {
+                print_info!("Resumed an interrupted rotation");
+            }
+            run.summary.print("rotated");
+            if !run.summary.failed.is_empty() {
+                print_solution!("Run \"keys rotate\" again to retry failed files");
+            }
+        }
+        Err(e) => print_err!("(rotation failed): {}", e),
+    }
+}
@@ -1,10 +1,16 @@
+mod agent;
 mod antivirus;
 mod compression;
+mod config;
 mod copy;
 mod currency;
 mod encryption;
+mod generate;
+mod inspect;
+mod keys;
 mod r#move;
 mod network;
+mod otp;
 mod password;
 mod qrcode;
 mod rename;
@@ -12,24 +18,42 @@ mod shell;
 mod timer;
 mod update;
 mod utils;
+mod vault;
 
+use crate::agent::{
+    agent_command, lock_command, parse_duration, unlock_command, DEFAULT_SESSION_DURATION,
+};
 use crate::antivirus::antivirus_command;
-use crate::compression::compress_command;
+use crate::compression::{
+    compress_command, parse_size, Format, Method, Overwrite, DEFAULT_OVERWRITE,
+};
 use crate::copy::copy_command;
+use crate::generate::{
+    generate_command, DEFAULT_PASSWORD_LENGTH, DEFAULT_SEPARATOR, DEFAULT_WORD_COUNT,
+};
+use crate::otp::{otp_command, Algorithm};
 use crate::qrcode::qrcode_command;
 use crate::r#move::move_command;
 use crate::rename::rename_command;
 use crate::shell::shell_command;
 use crate::timer::timer_command;
 use crate::update::update_command;
+use crate::vault::{vault_command, EntryKind, DEFAULT_CLEAR_SECONDS};
 use clap::{arg, command, ArgAction, ArgGroup, Command};
 
 use compression::extract_command;
+use config::config_command;
 use currency::currency_command;
 use encryption::{decrypt_command, encrypt_command};
+use inspect::inspect_command;
+use keys::keys_command;
 use network::network_command;
 use password::password_command;
 use std::path::PathBuf;
+use utils::{
+    prompt::{set_prompt_input, PromptInput},
+    set_config_location, ConfigLocation,
+};
 
 const VERSION: &str = "v1.0.0";
 
@@ -43,18 +67,39 @@ async fn main() {
         .propagate_version(true)
         .subcommand_required(true)
         .arg_required_else_help(true)
+        .arg(arg!(--"config" <PATH> "Use this config file instead of the default one").global(true).value_parser(clap::value_parser!(PathBuf)))
+        .arg(arg!(--"profile" <NAME> "Use the config of a named profile, kept apart from the default one").global(true).conflicts_with("config"))
+        .arg(arg!(--"password-stdin" "Read passwords and answers from stdin, one per line in the order they are asked").global(true).action(ArgAction::SetTrue))
+        .arg(arg!(--"password-file" <PATH> "Read passwords and answers from a file, one per line in the order they are asked").global(true).value_parser(clap::value_parser!(PathBuf)).conflicts_with("password-stdin"))
+        .arg(arg!(-'y' --"yes" "Answer yes to confirmations, passwords given as arguments are not typed again").global(true).action(ArgAction::SetTrue))
         .subcommand(
             Command::new("password")
                 .about("Set a security password to access sensible informations")
                 .group(
                     ArgGroup::new("password_action")
                         .required(true)
-                        .args(["init", "change", "reset", "modifyQ"]),
+                        .args(["init", "change", "reset", "modifyQ", "protect", "unprotect"]),
                 )
                 .arg(arg!(-'i' --"init" [NEW_PASSWORD] "Set password for first time").action(ArgAction::Set))
                 .arg(arg!(-'c' --"change" [ACTUAL_PASSWORD] "Change password when set").action(ArgAction::Set))
                 .arg(arg!(-'r' --"reset" "Reset password by answering a set of questions").action(ArgAction::SetTrue))
                 .arg(arg!(-'m' --"modifyQ" [PASSWORD] "Add and remove questions you will have to answer in order to reset your password").action(ArgAction::Set))
+                .arg(arg!(-'p' --"protect" [PASSWORD] "Protect encryption keys with your password").action(ArgAction::Set))
+                .arg(arg!(-'u' --"unprotect" [PASSWORD] "Store encryption keys unprotected again").action(ArgAction::Set))
+        )
+        .subcommand(
+            Command::new("unlock")
+                .about("Keep mucli unlocked for a while, so that your password is not asked for again")
+                .arg(arg!(--"for" <DURATION> "How long to stay unlocked, e.g. 90s, 15m or 1h30m").value_parser(parse_duration).default_value(DEFAULT_SESSION_DURATION))
+        )
+        .subcommand(
+            Command::new("lock")
+                .about("Lock mucli again before the end of an unlocked session")
+        )
+        .subcommand(
+            Command::new("agent")
+                .hide(true)
+                .arg(arg!(<SOCKET>).value_parser(clap::value_parser!(PathBuf)))
         )
         .subcommand(
             Command::new("encrypt")
@@ -71,7 +116,12 @@ async fn main() {
                 .arg(arg!(-'c' --"cdir" "Place output file in current dir").action(ArgAction::SetTrue))
                 .arg(arg!(-'s' --"sfile" "Select target file as output file").action(ArgAction::SetTrue))
                 .arg(arg!(-'p' --"purge" "Get rid of all the encryption keys to start anew").action(ArgAction::SetTrue))
+                .arg(arg!(-'f' --"force" "Purge even if the encryption keys were never backed up").action(ArgAction::SetTrue).requires("purge"))
                 .arg(arg!(-'t' --"times" <TIMES> "Encrypt x times the file").action(ArgAction::Set).value_parser(clap::value_parser!(u8)))
+                .arg(arg!(-'r' --"recursive" "Encrypt every file of the target directory").action(ArgAction::SetTrue).conflicts_with_all(["ukey", "purge"]))
+                .arg(arg!(--"include" <GLOB> "Only encrypt files matching this pattern (with -r)").action(ArgAction::Append).requires("recursive"))
+                .arg(arg!(--"exclude" <GLOB> "Skip files and directories matching this pattern (with -r)").action(ArgAction::Append).requires("recursive"))
+                .arg(arg!(--"recipient" <KEY_OR_FILE> "Encrypt for a public key, or for every public key listed in a file").action(ArgAction::Append).conflicts_with_all(["ukey", "purge"]))
                 .arg(arg!([FILEPATH] "file path of the target file").required_unless_present_any(["ukey", "purge"]).value_parser(clap::value_parser!(PathBuf)))
                 .arg(arg!([OUTPUTDIR] "output directory [defaults: file dir]").value_parser(clap::value_parser!(PathBuf))),
         )
@@ -86,9 +136,196 @@ async fn main() {
                 .arg(arg!(-'c' --"cdir" "Place output file in current dir").action(ArgAction::SetTrue))
                 .arg(arg!(-'s' --"sfile" "Select target file as output file").action(ArgAction::SetTrue))
                 .arg(arg!(-'e' --"entirely" "Entirely decrypt target file").action(ArgAction::SetTrue))
+                .arg(arg!(-'r' --"recursive" "Decrypt every encrypted file of the target directory").action(ArgAction::SetTrue))
+                .arg(arg!(--"include" <GLOB> "Only decrypt files matching this pattern (with -r)").action(ArgAction::Append).requires("recursive"))
+                .arg(arg!(--"exclude" <GLOB> "Skip files and directories matching this pattern (with -r)").action(ArgAction::Append).requires("recursive"))
                 .arg(arg!([FILEPATH] "file path of the target file").required(true).value_parser(clap::value_parser!(PathBuf)))
                 .arg(arg!([OUTPUTDIR] "output directory [defaults: file dir]").value_parser(clap::value_parser!(PathBuf))),
         )
+        .subcommand(
+            Command::new("inspect")
+                .about("Tell whether a file is encrypted and how, without decrypting it")
+                .arg(arg!(-'j' --"json" "Print a machine-readable report").action(ArgAction::SetTrue))
+                .arg(arg!([PATH] "file to inspect, or directory whose encrypted files to list").required(true).value_parser(clap::value_parser!(PathBuf)))
+        )
+        .subcommand(
+            Command::new("keys")
+                .about("Manage the identities used to exchange encrypted files with others")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("generate")
+                        .about("Generate a new identity and print its public key")
+                        .arg(arg!(-'o' --"output" <FILE> "Also save the public key in a file").value_parser(clap::value_parser!(PathBuf)))
+                        .arg(arg!([NAME] "name of the identity [defaults: default]"))
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List your identities and their public keys")
+                )
+                .subcommand(
+                    Command::new("export")
+                        .about("Back up encryption keys and identities in a password protected file")
+                        .arg(arg!(-'r' --"recovery" "Print a recovery sheet of the encryption keys instead, to keep on paper").action(ArgAction::SetTrue))
+                        .arg(arg!([FILE] "backup file to write").required_unless_present("recovery").value_parser(clap::value_parser!(PathBuf)))
+                )
+                .subcommand(
+                    Command::new("import")
+                        .about("Restore encryption keys and identities from a backup file or a recovery sheet")
+                        .arg(arg!(-'f' --"force" "Replace local encryption keys even when they differ from the backup").action(ArgAction::SetTrue))
+                        .arg(arg!([FILE] "backup file or recovery sheet").required(true).value_parser(clap::value_parser!(PathBuf)))
+                )
+                .subcommand(
+                    Command::new("rotate")
+                        .about("Re-encrypt every file of a directory encrypted with an older key, resuming an interrupted run")
+                        .arg(arg!(-'n' --"new-key" "Generate a new encryption key before rotating").action(ArgAction::SetTrue))
+                        .arg(arg!(-'j' --"jobs" <JOBS> "Number of files rotated in parallel [defaults: number of CPUs]").value_parser(clap::value_parser!(usize)))
+                        .arg(arg!([DIR] "directory to rotate").required(true).value_parser(clap::value_parser!(PathBuf)))
+                )
+        )
+        .subcommand(
+            Command::new("config")
+                .about("View and change mucli settings")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("list")
+                        .about("List every setting")
+                        .arg(arg!(-'s' --"show-secrets" "Show keys and passwords instead of redacting them").action(ArgAction::SetTrue))
+                )
+                .subcommand(
+                    Command::new("get")
+                        .about("Print a setting, or every setting of a section")
+                        .arg(arg!(-'s' --"show-secrets" "Show keys and passwords instead of redacting them").action(ArgAction::SetTrue))
+                        .arg(arg!([KEY] "setting to print, as section.name").required(true))
+                )
+                .subcommand(
+                    Command::new("set")
+                        .about("Change a setting")
+                        .arg(arg!([KEY] "setting to change, as section.name").required(true))
+                        .arg(arg!([VALUE] "new value").required(true))
+                )
+                .subcommand(
+                    Command::new("unset")
+                        .about("Remove a setting so that its default is used")
+                        .arg(arg!([KEY] "setting to remove, as section.name").required(true))
+                )
+                .subcommand(
+                    Command::new("path")
+                        .about("Print the location of the config file")
+                )
+                .subcommand(
+                    Command::new("edit")
+                        .about("Open the config file in $VISUAL or $EDITOR, it is only saved if valid")
+                )
+                .subcommand(
+                    Command::new("validate")
+                        .about("Check the config file for errors, unknown settings and missing values")
+                )
+        )
+        .subcommand(
+            Command::new("vault")
+                .about("Store API tokens, passwords and notes encrypted with your keys")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Add a secret, its value is asked for unless given as a field")
+                        .arg(arg!(<NAME> "Name of the entry"))
+                        .arg(arg!(-'k' --"kind" <KIND> "What the entry holds, its main field is named after it").value_parser(EntryKind::NAMES).default_value("password"))
+                        .arg(arg!(-'f' --"field" <FIELD> "Another field of the entry, as in username=bob").action(ArgAction::Append))
+                        .arg(arg!(--"force" "Replace an entry with the same name").action(ArgAction::SetTrue))
+                )
+                .subcommand(
+                    Command::new("get")
+                        .about("Print the main field of an entry, or another field")
+                        .arg(arg!(<NAME> "Name of the entry"))
+                        .arg(arg!(-'f' --"field" <FIELD> "Field to print instead of the main one"))
+                        .arg(arg!(-'c' --"clip" [SECONDS] "Copy to the clipboard instead, cleared after some seconds").value_parser(clap::value_parser!(u64)).default_missing_value(DEFAULT_CLEAR_SECONDS))
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List entry names and fields, values are not printed")
+                )
+                .subcommand(
+                    Command::new("rm")
+                        .about("Remove an entry")
+                        .arg(arg!(<NAME> "Name of the entry"))
+                )
+                .subcommand(
+                    Command::new("edit")
+                        .about("Edit the fields of an entry in your editor")
+                        .arg(arg!(<NAME> "Name of the entry"))
+                )
+                .subcommand(
+                    Command::new("clear-clipboard")
+                        .hide(true)
+                        .arg(arg!(<SECONDS>).value_parser(clap::value_parser!(u64)))
+                )
+        )
+        .subcommand(
+            Command::new("otp")
+                .about("Authenticator codes for accounts protected by TOTP or HOTP")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Add an account from its base32 secret or its otpauth:// URI")
+                        .arg(arg!(<NAME> "Name of the account"))
+                        .arg(arg!([SECRET] "Base32 secret or otpauth:// URI, asked for when not given"))
+                        .arg(arg!(--"hotp" "Counter based codes instead of time based ones").action(ArgAction::SetTrue))
+                        .arg(arg!(-'a' --"algorithm" <ALGORITHM> "Hash algorithm, sha1 by default").value_parser(Algorithm::NAMES))
+                        .arg(arg!(-'d' --"digits" <DIGITS> "Number of digits of the codes, 6 by default").value_parser(clap::value_parser!(u64).range(6..=8)))
+                        .arg(arg!(-'p' --"period" <SECONDS> "Seconds a time based code is valid, 30 by default").value_parser(clap::value_parser!(u64).range(1..)))
+                        .arg(arg!(-'c' --"counter" <COUNTER> "Next counter of counter based codes, 0 by default").value_parser(clap::value_parser!(u64)))
+                        .arg(arg!(-'i' --"issuer" <ISSUER> "Service the account belongs to"))
+                        .arg(arg!(--"force" "Replace an account with the same name").action(ArgAction::SetTrue))
+                        .arg(arg!(--"qr" "Draw the otpauth:// URI as a QR code, to scan with another app").action(ArgAction::SetTrue))
+                )
+                .subcommand(
+                    Command::new("code")
+                        .about("Print the current code of an account")
+                        .arg(arg!(<NAME> "Name of the account"))
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List accounts, secrets are not printed")
+                )
+                .subcommand(
+                    Command::new("rm")
+                        .about("Remove an account")
+                        .arg(arg!(<NAME> "Name of the account"))
+                )
+                .subcommand(
+                    Command::new("qr")
+                        .about("Draw the otpauth:// URI of an account as a QR code, to scan with another app")
+                        .arg(arg!(<NAME> "Name of the account"))
+                )
+        )
+        .subcommand(
+            Command::new("gen")
+                .about("Generate passwords and passphrases")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("password")
+                        .about("Generate a random password with a character of each class")
+                        .arg(arg!(-'l' --"length" <LENGTH> "Number of characters").value_parser(clap::value_parser!(u64).range(4..=1024)).default_value(DEFAULT_PASSWORD_LENGTH))
+                        .arg(arg!(--"no-lower" "Leave out lowercase letters").action(ArgAction::SetTrue))
+                        .arg(arg!(--"no-upper" "Leave out uppercase letters").action(ArgAction::SetTrue))
+                        .arg(arg!(--"no-digits" "Leave out digits").action(ArgAction::SetTrue))
+                        .arg(arg!(--"no-symbols" "Leave out symbols").action(ArgAction::SetTrue))
+                        .arg(arg!(-'a' --"exclude-ambiguous" "Leave out characters easily mistaken for one another, as in l, 1 and I").action(ArgAction::SetTrue))
+                        .arg(arg!(--"vault" <NAME> "Store it in the vault instead of printing it"))
+                        .arg(arg!(--"force" "Replace a vault entry with the same name").action(ArgAction::SetTrue).requires("vault"))
+                        .arg(arg!(--"qr" "Also draw it as a QR code").action(ArgAction::SetTrue))
+                )
+                .subcommand(
+                    Command::new("passphrase")
+                        .about("Generate a passphrase of random words, easier to type and remember")
+                        .arg(arg!(-'w' --"words" <COUNT> "Number of words").value_parser(clap::value_parser!(u64).range(1..=64)).default_value(DEFAULT_WORD_COUNT))
+                        .arg(arg!(-'s' --"separator" <SEPARATOR> "Put between words").default_value(DEFAULT_SEPARATOR))
+                        .arg(arg!(-'c' --"capitalize" "Start every word with an uppercase letter").action(ArgAction::SetTrue))
+                        .arg(arg!(--"vault" <NAME> "Store it in the vault instead of printing it"))
+                        .arg(arg!(--"force" "Replace a vault entry with the same name").action(ArgAction::SetTrue).requires("vault"))
+                        .arg(arg!(--"qr" "Also draw it as a QR code").action(ArgAction::SetTrue))
+                )
+        )
         .subcommand(
             Command::new("rename")
                 .about("Rename a file as specified")
@@ -109,28 +346,45 @@ async fn main() {
         )
         .subcommand(
             Command::new("zip")
-                .about("Compress the specified file/directory and place the output file in specified dir")
+                .about("Compress the specified file/directory as a zip or a tar and place the output file in specified dir")
                 .group(
                     ArgGroup::new("compress_actions")
                         .required(false)
                         .args(["cdir", "OUTPUTDIR"])
                 )
                 .arg(arg!(-'c' --"cdir" "Place output zip in current dir").action(ArgAction::SetTrue))
-                .arg(arg!(-'l' --"level" <LEVEL> "Compress using a specified compression level between 0 and 9").action(ArgAction::Set).value_parser(0..=9))
+                .arg(arg!(-'f' --"format" <FORMAT> "Archive format").value_parser(Format::NAMES).default_value("zip"))
+                .arg(arg!(-'m' --"method" <METHOD> "Compression method of a zip, deflate by default").value_parser(Method::NAMES))
+                .arg(arg!(-'l' --"level" <LEVEL> "Compression level, 0 to 9 for deflate, tar.gz and tar.xz, 1 to 9 for bzip2 and 1 to 22 for zstd and tar.zst").action(ArgAction::Set).value_parser(0..=22))
+                .arg(arg!(--"include" <GLOB> "Only archive files matching this pattern").action(ArgAction::Append))
+                .arg(arg!(--"exclude" <GLOB> "Skip files and directories matching this pattern").action(ArgAction::Append))
+                .arg(arg!(-'e' --"encrypt" "Encrypt the zip, the unencrypted archive is never written to disk").action(ArgAction::SetTrue))
+                .arg(arg!(-'p' --"password" "Protect the files of a zip with a password (AES-256), other tools can open it").action(ArgAction::SetTrue).conflicts_with("vault"))
+                .arg(arg!(--"vault" <ENTRY> "Protect the files of a zip with the password of this vault entry"))
                 .arg(arg!([PATH] "path of the source to compress").required(true).value_parser(clap::value_parser!(PathBuf)))
                 .arg(arg!([OUTPUTDIR] "output directory [defaults: file dir]").value_parser(clap::value_parser!(PathBuf))),
         )
         .subcommand(
             Command::new("unzip")
-                .about("Extract the specified zip and place the output extract in specified dir")
+                .about("Extract the specified archive (encrypted or not) and place the output extract in specified dir")
                 .group(
                     ArgGroup::new("compress_actions")
                         .required(false)
                         .args(["cdir", "OUTPUTDIR"])
                 )
                 .arg(arg!(-'c' --"cdir" "Place output extract in current dir").action(ArgAction::SetTrue))
-                .arg(arg!([PATH] "path of the zip to extract").required(true).value_parser(clap::value_parser!(PathBuf)))
-                .arg(arg!([OUTPUTDIR] "output directory [defaults: file dir]").value_parser(clap::value_parser!(PathBuf))),
+                .arg(arg!(-'l' --"list" "List the entries with their size, compressed size, time, mode and CRC instead").action(ArgAction::SetTrue).conflicts_with_all(["cdir", "OUTPUTDIR", "test", "strip-components", "overwrite", "keep-both", "max-size", "max-entries", "max-ratio", "vault"]))
+                .arg(arg!(-'t' --"test" "Check every entry can be read and matches its CRC, nothing is written").action(ArgAction::SetTrue).conflicts_with_all(["cdir", "OUTPUTDIR", "strip-components", "overwrite", "keep-both", "max-size", "max-entries", "max-ratio"]))
+                .arg(arg!(--"strip-components" <COUNT> "Remove the first directories of every path, entries with no more are skipped").value_parser(clap::value_parser!(usize)))
+                .arg(arg!(--"overwrite" <WHEN> "What to do with files that already exist, newer replaces the older ones").value_parser(Overwrite::NAMES).default_value(DEFAULT_OVERWRITE))
+                .arg(arg!(--"keep-both" "Keep files that already exist, the entry is written as \"name (1).ext\"").action(ArgAction::SetTrue).conflicts_with("overwrite"))
+                .arg(arg!(--"max-size" <SIZE> "Stop when more than this is written, e.g. 500M or 20G, 0 for no limit [default: archive.max_size or 10G]").value_parser(parse_size))
+                .arg(arg!(--"max-entries" <COUNT> "Stop past this many entries, 0 for no limit [default: archive.max_entries or 100000]").value_parser(clap::value_parser!(u64)))
+                .arg(arg!(--"max-ratio" <RATIO> "Stop when more than this many times the archive size is written, 0 for no limit [default: archive.max_ratio or 1000]").value_parser(clap::value_parser!(u64)))
+                .arg(arg!(--"vault" <ENTRY> "Open password protected zips with the password of this vault entry instead of asking"))
+                .arg(arg!([PATH] "path of the archive to extract").required(true).value_parser(clap::value_parser!(PathBuf)))
+                .arg(arg!([OUTPUTDIR] "output directory [defaults: file dir]").value_parser(clap::value_parser!(PathBuf)))
+                .arg(arg!([PATTERN] ... "Only these entries, glob patterns given after --").last(true)),
         )
         .subcommand(
             Command::new("shell")
@@ -173,10 +427,37 @@ This command returns:
                 .about("Check if a new update of mucli is available (coming soon)")
         ).get_matches();
 
+    let location = ConfigLocation {
+        file: matches.get_one::<PathBuf>("config").cloned(),
+        profile: matches.get_one::<String>("profile").cloned(),
+    };
+    if let Err(e) = set_config_location(location) {
+        print_err!("{}", e);
+        return;
+    }
+    let input = PromptInput {
+        stdin: matches.get_flag("password-stdin"),
+        file: matches.get_one::<PathBuf>("password-file").cloned(),
+        yes: matches.get_flag("yes"),
+    };
+    if let Err(e) = set_prompt_input(input) {
+        print_err!("{}", e);
+        return;
+    }
+
     match matches.subcommand() {
         Some(("encrypt", sub_matches)) => encrypt_command(sub_matches),
         Some(("decrypt", sub_matches)) => decrypt_command(sub_matches),
         Some(("password", sub_matches)) => password_command(sub_matches),
+        Some(("keys", sub_matches)) => keys_command(sub_matches),
+        Some(("unlock", sub_matches)) => unlock_command(sub_matches),
+        Some(("lock", _)) => lock_command(),
+        Some(("agent", sub_matches)) => agent_command(sub_matches),
+        Some(("inspect", sub_matches)) => inspect_command(sub_matches),
+        Some(("config", sub_matches)) => config_command(sub_matches),
+        Some(("vault", sub_matches)) => vault_command(sub_matches),
+        Some(("gen", sub_matches)) => generate_command(sub_matches),
+        Some(("otp", sub_matches)) => otp_command(sub_matches),
         Some(("update", _)) => update_command().await,
         Some(("rename", sub_matches)) => rename_command(sub_matches),
         Some(("cp", sub_matches)) => copy_command(sub_matches),
@@ -0,0 +1,584 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::ArgMatches;
+use custom_error::custom_error;
+use data_encoding::BASE32_NOPAD;
+use hmac::{
+    digest::{core_api::BlockSizeUser, Digest},
+    Mac, SimpleHmac,
+};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use url::Url;
+
+use crate::{
+    encryption::{
+        store::{read_sealed, update_sealed},
+        EncryptionError,
+    },
+    password::unlock_gate,
+    print_err, print_info, print_solution, print_success,
+    qrcode::render_qrcode,
+    utils::{
+        bytes::Bytes,
+        get_config_path,
+        prompt::{self, PromptError},
+        GenericError,
+    },
+};
+
+custom_error! {pub OtpError
+    Generic{source: GenericError} = "{source}",
+    Encryption{source: EncryptionError} = "{source}",
+    Prompt{source: PromptError} = "{source}",
+    QrCode{source: qrcode::types::QrError} = "{source}",
+    InvalidUri{message: String} = "Invalid otpauth URI: {message}",
+    InvalidSecret = "The secret is not valid base32",
+    InvalidName{name: String} = "Invalid account name \"{name}\", spaces and control characters are not allowed",
+    UnsupportedStore{version: u32} = "The authenticator accounts were written by a newer mucli (version {version})",
+    AccountExists{name: String} = "\"{name}\" is already an authenticator account",
+    AccountNotFound{name: String} = "\"{name}\" is not an authenticator account"
+}
+
+const STORE_VERSION: u32 = 1;
+pub const DEFAULT_DIGITS: u32 = 6;
+pub const DEFAULT_PERIOD: u64 = 30;
+const URI_SCHEME: &str = "otpauth";
+
+/// Time based (RFC 6238) or counter based (RFC 4226) codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OtpKind {
+    Totp,
+    Hotp,
+}
+
+impl OtpKind {
+    fn name(&self) -> &'static str {
+        match self {
+            OtpKind::Totp => "totp",
+            OtpKind::Hotp => "hotp",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    pub const NAMES: [&'static str; 3] = ["sha1", "sha256", "sha512"];
+
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        match name.to_lowercase().as_str() {
+            "sha1" => Some(Algorithm::Sha1),
+            "sha256" => Some(Algorithm::Sha256),
+            "sha512" => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    // as written in otpauth URIs
+    fn uri_name(&self) -> &'static str {
+        match self {
+            Algorithm::Sha1 => "SHA1",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha512 => "SHA512",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OtpAccount {
+    pub kind: OtpKind,
+    pub secret: Bytes,
+    pub algorithm: Algorithm,
+    pub digits: u32,
+    // seconds a time based code is valid
+    pub period: u64,
+    // next counter of a counter based code
+    pub counter: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+}
+
+impl OtpAccount {
+    fn new(secret: Vec<u8>) -> Self {
+        OtpAccount {
+            kind: OtpKind::Totp,
+            secret: Bytes(secret),
+            algorithm: Algorithm::Sha1,
+            digits: DEFAULT_DIGITS,
+            period: DEFAULT_PERIOD,
+            counter: 0,
+            issuer: None,
+        }
+    }
+}
+
+// sealed with the latest encryption key, as the vault
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct OtpStore {
+    version: u32,
+    accounts: BTreeMap<String, OtpAccount>,
+}
+
+pub fn otp_command(sub_matches: &ArgMatches) {
+    match sub_matches.subcommand() {
+        Some(("add", sub_matches)) => add_command(sub_matches),
+        Some(("code", sub_matches)) => code_command(sub_matches),
+        Some(("list", _)) => list_command(),
+        Some(("rm", sub_matches)) => remove_command(sub_matches),
+        Some(("qr", sub_matches)) => qr_command(sub_matches),
+        _ => (),
+    }
+}
+
+fn add_command(sub_matches: &ArgMatches) {
+    let name = match sub_matches.get_one::<String>("NAME") {
+        Some(name) => name,
+        None => return,
+    };
+
+    let account = match account_from_args(sub_matches) {
+        Ok(account) => account,
+        Err(e) => {
+            print_err!("{}", e);
+            return;
+        }
+    };
+
+    match store_account(name, &account, sub_matches.get_flag("force")) {
+        Ok(_) => print_success!("\"{}\" added to the authenticator", name),
+        Err(OtpError::AccountExists { name }) => {
+            print_err!("\"{}\" is already an authenticator account", name);
+            print_solution!("Use --force to replace it");
+            return;
+        }
+        Err(e) => {
+            print_err!("(otp add failed): {}", e);
+            return;
+        }
+    }
+
+    if sub_matches.get_flag("qr") {
+        print_qrcode(name, &account);
+    }
+}
+
+fn code_command(sub_matches: &ArgMatches) {
+    if !unlock_gate("otp") {
+        return;
+    }
+    let name = match sub_matches.get_one::<String>("NAME") {
+        Some(name) => name,
+        None => return,
+    };
+
+    match next_code(name) {
+        Ok((code, Some(remaining))) => {
+            println!("{}", code);
+            print_info!("Valid for {}s", remaining);
+        }
+        Ok((code, None)) => println!("{}", code),
+        Err(e) => print_err!("{}", e),
+    }
+}
+
+fn list_command() {
+    let accounts = match read_store() {
+        Ok(store) => store.accounts,
+        Err(e) => {
+            print_err!("{}", e);
+            return;
+        }
+    };
+    if accounts.is_empty() {
+        print_info!("No authenticator account yet");
+        print_solution!("Use \"otp add <NAME> <SECRET>\" to add one");
+        return;
+    }
+
+    let width = accounts.keys().map(|name| name.len()).max().unwrap_or(0);
+    for (name, account) in &accounts {
+        let parameters = match account.kind {
+            OtpKind::Totp => format!("every {}s", account.period),
+            OtpKind::Hotp => format!("counter {}", account.counter),
+        };
+        println!(
+            "{:width$}  {}  {} {} digits, {}  {}",
+            name,
+            account.kind.name(),
+            account.algorithm.uri_name(),
+            account.digits,
+            parameters,
+            account.issuer.as_deref().unwrap_or_default(),
+            width = width
+        );
+    }
+}
+
+fn remove_command(sub_matches: &ArgMatches) {
+    if !unlock_gate("otp") {
+        return;
+    }
+    let name = match sub_matches.get_one::<String>("NAME") {
+        Some(name) => name,
+        None => return,
+    };
+
+    match prompt::confirm(&format!("Remove \"{}\" from the authenticator?", name)) {
+        Ok(true) => (),
+        Ok(false) => return,
+        Err(e) => {
+            print_err!("{}", e);
+            return;
+        }
+    }
+
+    let removed = update_store(|store| match store.accounts.remove(name) {
+        Some(_) => Ok(()),
+        None => Err(OtpError::AccountNotFound {
+            name: name.to_string(),
+        }),
+    });
+    match removed {
+        Ok(_) => print_success!("\"{}\" removed from the authenticator", name),
+        Err(e) => print_err!("{}", e),
+    }
+}
+
+// the QR code holds the secret, another authenticator app can scan it
+fn qr_command(sub_matches: &ArgMatches) {
+    if !unlock_gate("otp") {
+        return;
+    }
+    let name = match sub_matches.get_one::<String>("NAME") {
+        Some(name) => name,
+        None => return,
+    };
+
+    match read_account(name) {
+        Ok(account) => print_qrcode(name, &account),
+        Err(e) => print_err!("{}", e),
+    }
+}
+
+fn print_qrcode(name: &str, account: &OtpAccount) {
+    match account_uri(name, account).and_then(|uri| Ok(render_qrcode(&uri)?)) {
+        Ok(code) => print!("{}", code),
+        Err(e) => print_err!("{}", e),
+    }
+}
+
+// options given on the command line win over the ones of an otpauth URI
+fn account_from_args(sub_matches: &ArgMatches) -> Result<OtpAccount, OtpError> {
+    let input = match sub_matches.get_one::<String>("SECRET") {
+        Some(input) => input.to_string(),
+        None => prompt::password("Enter the secret or otpauth:// URI")?,
+    };
+    let mut account = match input.trim() {
+        input if input.starts_with(&format!("{}://", URI_SCHEME)) => parse_uri(input)?,
+        input => OtpAccount::new(decode_secret(input)?),
+    };
+
+    if sub_matches.get_flag("hotp") {
+        account.kind = OtpKind::Hotp;
+    }
+    if let Some(algorithm) = sub_matches
+        .get_one::<String>("algorithm")
+        .and_then(|algorithm| Algorithm::from_name(algorithm))
+    {
+        account.algorithm = algorithm;
+    }
+    if let Some(digits) = sub_matches.get_one::<u64>("digits") {
+        account.digits = *digits as u32;
+    }
+    if let Some(period) = sub_matches.get_one::<u64>("period") {
+        account.period = *period;
+    }
+    if let Some(counter) = sub_matches.get_one::<u64>("counter") {
+        account.counter = *counter;
+    }
+    if let Some(issuer) = sub_matches.get_one::<String>("issuer") {
+        account.issuer = Some(issuer.to_string());
+    }
+    Ok(account)
+}
+
+/// Reads an account from an `otpauth://TYPE/LABEL?secret=...` URI
+pub fn parse_uri(uri: &str) -> Result<OtpAccount, OtpError> {
+    let invalid = |message: &str| OtpError::InvalidUri {
+        message: message.to_string(),
+    };
+    let uri = Url::parse(uri).map_err(|e| invalid(&e.to_string()))?;
+    if uri.scheme() != URI_SCHEME {
+        return Err(invalid("the scheme must be otpauth"));
+    }
+
+    let kind = match uri.host_str() {
+        Some("totp") => OtpKind::Totp,
+        Some("hotp") => OtpKind::Hotp,
+        _ => return Err(invalid("the type must be totp or hotp")),
+    };
+    let parameters: BTreeMap<String, String> = uri
+        .query_pairs()
+        .map(|(key, value)| (key.to_lowercase(), value.to_string()))
+        .collect();
+
+    let secret = parameters
+        .get("secret")
+        .ok_or(invalid("the secret is missing"))?;
+    let mut account = OtpAccount::new(decode_secret(secret)?);
+    account.kind = kind;
+
+    if let Some(algorithm) = parameters.get("algorithm") {
+        account.algorithm =
+            Algorithm::from_name(algorithm).ok_or(invalid("unsupported algorithm"))?;
+    }
+    if let Some(digits) = parameters.get("digits") {
+        account.digits = digits
+            .parse()
+            .ok()
+            .filter(|digits| (6..=8).contains(digits))
+            .ok_or(invalid("digits must be 6, 7 or 8"))?;
+    }
+    if let Some(period) = parameters.get("period") {
+        account.period = period
+            .parse()
+            .ok()
+            .filter(|period| *period > 0)
+            .ok_or(invalid("invalid period"))?;
+    }
+    if let Some(counter) = parameters.get("counter") {
+        account.counter = counter.parse().map_err(|_| invalid("invalid counter"))?;
+    }
+
+    // the issuer parameter wins over the "Issuer:account" label prefix
+    let label = percent_decode_str(uri.path().trim_start_matches('/'))
+        .decode_utf8_lossy()
+        .to_string();
+    account.issuer = parameters.get("issuer").cloned().or(label
+        .split_once(':')
+        .map(|(issuer, _)| issuer.trim().to_string()));
+    Ok(account)
+}
+
+/// URI to scan with another authenticator app
+pub fn account_uri(name: &str, account: &OtpAccount) -> Result<String, OtpError> {
+    let invalid = |e: url::ParseError| OtpError::InvalidUri {
+        message: e.to_string(),
+    };
+    let mut uri =
+        Url::parse(&format!("{}://{}/", URI_SCHEME, account.kind.name())).map_err(invalid)?;
+
+    let label = match &account.issuer {
+        Some(issuer) => format!("{}:{}", issuer, name),
+        None => name.to_string(),
+    };
+    uri.set_path(&label);
+    {
+        let mut parameters = uri.query_pairs_mut();
+        parameters.append_pair("secret", &BASE32_NOPAD.encode(&account.secret));
+        if let Some(issuer) = &account.issuer {
+            parameters.append_pair("issuer", issuer);
+        }
+        parameters.append_pair("algorithm", account.algorithm.uri_name());
+        parameters.append_pair("digits", &account.digits.to_string());
+        match account.kind {
+            OtpKind::Totp => parameters.append_pair("period", &account.period.to_string()),
+            OtpKind::Hotp => parameters.append_pair("counter", &account.counter.to_string()),
+        };
+    }
+    Ok(uri.to_string())
+}
+
+/// HOTP value of `counter` (RFC 4226), TOTP uses the number of periods since the epoch
+pub fn hotp(secret: &[u8], counter: u64, algorithm: Algorithm, digits: u32) -> String {
+    let hash = match algorithm {
+        Algorithm::Sha1 => hmac::<Sha1>(secret, counter),
+        Algorithm::Sha256 => hmac::<Sha256>(secret, counter),
+        Algorithm::Sha512 => hmac::<Sha512>(secret, counter),
+    };
+
+    // dynamic truncation, the last 4 bits give the offset of the 31 bits kept
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset],
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) & 0x7fff_ffff;
+
+    format!(
+        "{:0width$}",
+        binary as u64 % 10u64.pow(digits),
+        width = digits as usize
+    )
+}
+
+fn hmac<D: Digest + BlockSizeUser + Clone>(secret: &[u8], counter: u64) -> Vec<u8> {
+    // HMAC accepts keys of any length
+    let mut mac = <SimpleHmac<D> as Mac>::new_from_slice(secret).unwrap();
+    mac.update(&counter.to_be_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// spaces, dashes, padding and lowercase letters are common in secrets shown by services
+fn decode_secret(secret: &str) -> Result<Vec<u8>, OtpError> {
+    let secret: String = secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '=')
+        .collect::<String>()
+        .to_uppercase();
+
+    match BASE32_NOPAD.decode(secret.as_bytes()) {
+        Ok(secret) if !secret.is_empty() => Ok(secret),
+        _ => Err(OtpError::InvalidSecret),
+    }
+}
+
+// the code and the seconds it stays valid, counter based codes move to the next counter
+fn next_code(name: &str) -> Result<(String, Option<u64>), OtpError> {
+    let account = read_account(name)?;
+
+    match account.kind {
+        OtpKind::Totp => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0);
+            let code = hotp(
+                &account.secret,
+                now / account.period,
+                account.algorithm,
+                account.digits,
+            );
+            Ok((code, Some(account.period - now % account.period)))
+        }
+        OtpKind::Hotp => update_store(|store| {
+            let account = store
+                .accounts
+                .get_mut(name)
+                .ok_or(OtpError::AccountNotFound {
+                    name: name.to_string(),
+                })?;
+            let code = hotp(
+                &account.secret,
+                account.counter,
+                account.algorithm,
+                account.digits,
+            );
+            account.counter += 1;
+            Ok((code, None))
+        }),
+    }
+}
+
+fn store_account(name: &str, account: &OtpAccount, force: bool) -> Result<(), OtpError> {
+    if name.is_empty() || name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(OtpError::InvalidName {
+            name: name.to_string(),
+        });
+    }
+
+    update_store(|store| {
+        if !force && store.accounts.contains_key(name) {
+            return Err(OtpError::AccountExists {
+                name: name.to_string(),
+            });
+        }
+        store.accounts.insert(name.to_string(), account.clone());
+        Ok(())
+    })
+}
+
+fn read_account(name: &str) -> Result<OtpAccount, OtpError> {
+    read_store()?
+        .accounts
+        .remove(name)
+        .ok_or(OtpError::AccountNotFound {
+            name: name.to_string(),
+        })
+}
+
+// one store per config, so that profiles keep their own accounts
+fn store_path() -> Result<PathBuf, OtpError> {
+    Ok(get_config_path()?.with_extension("otp"))
+}
+
+fn read_store() -> Result<OtpStore, OtpError> {
+    let store: OtpStore = read_sealed(&store_path()?)?.unwrap_or_default();
+    check_version(&store)?;
+    Ok(store)
+}
+
+fn update_store<T, F>(modify: F) -> Result<T, OtpError>
+where
+    F: FnOnce(&mut OtpStore) -> Result<T, OtpError>,
+{
+    update_sealed(&store_path()?, |store: &mut OtpStore| {
+        check_version(store)?;
+        let result = modify(store)?;
+        store.version = STORE_VERSION;
+        Ok(result)
+    })
+}
+
+fn check_version(store: &OtpStore) -> Result<(), OtpError> {
+    if store.version > STORE_VERSION {
+        return Err(OtpError::UnsupportedStore {
+            version: store.version,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    // RFC 4226 appendix D
+    #[test]
+    fn hotp_matches_rfc_4226() {
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(
+                hotp(RFC_SECRET, counter as u64, Algorithm::Sha1, DEFAULT_DIGITS),
+                *code
+            );
+        }
+    }
+
+    // RFC 6238 appendix B, each algorithm has a seed of its own output size
+    #[test]
+    fn totp_matches_rfc_6238() {
+        let counter = 59 / DEFAULT_PERIOD;
+
+        assert_eq!(hotp(RFC_SECRET, counter, Algorithm::Sha1, 8), "94287082");
+        assert_eq!(
+            hotp(&RFC_SECRET.repeat(2)[..32], counter, Algorithm::Sha256, 8),
+            "46119246"
+        );
+        assert_eq!(
+            hotp(&RFC_SECRET.repeat(4)[..64], counter, Algorithm::Sha512, 8),
+            "90693936"
+        );
+    }
+}
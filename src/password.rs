@@ -1,404 +1,600 @@
 // update questions to store them as crypted content
-use crate::encryption::EncryptionError;
+use crate::agent;
+use crate::encryption::{keyring, EncryptionError};
 use crate::utils::generate_encryption_key;
-use crate::utils::line::{Line, LineError};
-use crate::utils::{config_interact::Config, GenericError};
+use crate::utils::line::LineError;
+use crate::utils::{
+    config::{Config, PasswordConfig, RecoveryQuestion},
+    prompt, GenericError,
+};
 use crate::{print_err, print_solution, print_success};
 
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
 use clap::ArgMatches;
 use custom_error::custom_error;
-use dialoguer::{theme::ColorfulTheme, Input, Password, Select};
-use simplecrypt::{decrypt, encrypt, DecryptionError};
+use dialoguer::{theme::ColorfulTheme, Input, Select};
 use std::io::Error;
 use std::num::ParseIntError;
+use std::time::{SystemTime, UNIX_EPOCH};
+use unicode_normalization::UnicodeNormalization;
 
 custom_error! {pub PasswordError
     Io{source: Error} = "{source}",
     Line{source: LineError} = "{source}",
     Format{source: ParseIntError} = "{source}",
     Generic{source: GenericError} = "{source}",
-    Decrypt{source: DecryptionError} = "{source}",
     Encryption{source: EncryptionError} = "{source}",
-    RetrievePassword = "Cannot retrieve password",
-    RetrievePassowrdKey = "Cannot retrieve password encryption key",
-    SetPassword = "Cannot set password in config.txt",
-    EncryptPassword = "Failed to encrypt password",
-    DecryptPassword = "Failed to decrypt password"
+    PasswordNotSet = "Password has not been set yet",
+    HashPassword = "Failed to hash password",
+    InvalidHash = "The stored password hash is invalid"
 }
 
-const PASSWORD_KEYWORD: &str = "MUCLI_PASSWORD";
-const PASSWORD_KEY_KEYWORD: &str = "MUCLI_KEY_PASSWORD";
-const QUESTION_KEYWORD: &str = "MUCLI_QUESTION";
+/// Commands that can ask for the password before running, see `unlock_gate`
+pub const GATEABLE_COMMANDS: [&str; 8] = [
+    "encrypt",
+    "decrypt",
+    "keys export",
+    "keys import",
+    "keys rotate",
+    "config secrets",
+    "vault",
+    "otp",
+];
+/// Commands gated when `password.gated_commands` is not set
+pub const DEFAULT_GATED_COMMANDS: [&str; 4] = ["decrypt", "keys export", "config secrets", "vault"];
+
+/// Right answers needed to reset the password when `password.required_answers` is not set
+pub const DEFAULT_REQUIRED_ANSWERS: usize = 3;
+// wrong recovery answers allowed before a lockout
+const MAX_FAILED_ANSWERS: u32 = 5;
+const LOCKOUT_SECONDS: u64 = 15 * 60;
+
+const SALT_SIZE: usize = 16;
 
 pub fn password_command(sub_matches: &ArgMatches) {
-    if let Err(_) = init_password_key() {
-        // initialize encryption key if 1st time using command
-        print_err!("Initializing password key failed!");
-        return;
-    }
-
     if let true = sub_matches.contains_id("init") {
-        match get_password() {
-            Ok(_) => {
+        match is_password_set() {
+            Ok(true) => {
                 print_err!("Password is already set");
                 print_solution!("Use \"password --change\" to modify it");
             }
-            Err(_) => {
-                let password: String =
-                    if let Some(new_password) = sub_matches.get_one::<String>("init") {
-                        Password::with_theme(&ColorfulTheme::default())
-                            .with_prompt("Confirm your password")
-                            .validate_with(|input: &String| -> Result<(), &str> {
-                                if input != new_password {
-                                    return Err("Passwords don't match");
-                                }
-                                Ok(())
-                            })
-                            .interact()
-                            .unwrap()
-                    } else {
-                        Password::with_theme(&ColorfulTheme::default())
-                            .with_prompt("Enter your password")
-                            .with_confirmation("Confirm your password", "The passwords don't match")
-                            .interact()
-                            .unwrap()
-                    };
+            Ok(false) => {
+                let password = match sub_matches.get_one::<String>("init") {
+                    Some(new_password) => {
+                        prompt::retype_password("Confirm your password", new_password)
+                            .map(|_| new_password.to_string())
+                    }
+                    None => prompt::new_password("Enter your password", "Confirm your password"),
+                };
+                let password = match password {
+                    Ok(password) => password,
+                    Err(e) => {
+                        print_err!("{}", e);
+                        return;
+                    }
+                };
 
                 match set_password(&password) {
-                    Ok(_) => {
-                        print_success!("\"{}\" was successfully set as your new password", password)
-                    }
+                    Ok(_) => print_success!("Your password was successfully set"),
                     Err(e) => print_err!("Failed to set password! > {}", e),
                 };
             }
+            Err(e) => print_err!("{}", e),
         }
     } else if let true = sub_matches.contains_id("change") {
-        match get_password() {
-            Ok(password) => {
-                if let Some(password_input) = sub_matches.get_one::<String>("change") {
-                    if *password_input != password {
-                        print_err!("Invalid password put as argument!");
-                        return;
-                    }
-                } else {
-                    let actual_password: String = Password::with_theme(&ColorfulTheme::default())
-                        .with_prompt("Enter your current password")
-                        .interact()
-                        .unwrap();
-                    if actual_password != password {
-                        print_err!("Wrong password!");
-                        return;
-                    }
+        let password = match current_password(sub_matches.get_one::<String>("change")) {
+            Some(password) => password,
+            None => return,
+        };
+
+        let new_password =
+            match prompt::new_password("Enter your new password", "Confirm your new password") {
+                Ok(new_password) => new_password,
+                Err(e) => {
+                    print_err!("{}", e);
+                    return;
                 }
-
-                let new_password: String = Password::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Enter your new password")
-                    .with_confirmation("Confirm your new password", "Passwords don't match")
-                    .interact()
-                    .unwrap();
-
-                match set_password(&new_password) {
-                    Ok(_) => {
-                        print_success!(
-                            "\"{}\" was successfully set as your new password",
-                            new_password
-                        );
-                        return;
-                    }
-                    Err(e) => {
-                        print_err!("{}", e);
-                        return;
-                    }
-                };
+            };
+        if new_password == password {
+            print_err!("The new password is the current one");
+            if prompt::new_password_from_password_var() {
+                print_solution!("Give the new password with MUCLI_NEW_PASSWORD");
             }
-            Err(_) => {
-                print_err!("Password has not been set yet");
-                print_solution!("Use \"password --init\" to set it");
+            return;
+        }
+
+        // protected encryption keys follow the password, files stay untouched
+        if let Ok(true) = keyring::is_protected() {
+            if let Err(e) = keyring::rewrap(&password, &new_password) {
+                print_err!("Failed to protect encryption keys with new password: {}", e);
                 return;
             }
         }
+
+        match set_password(&new_password) {
+            Ok(_) => print_success!("Your new password was successfully set"),
+            Err(e) => print_err!("{}", e),
+        };
     } else if let true = sub_matches.contains_id("modifyQ") {
-        match get_password() {
-            Ok(password) => {
-                if let Some(password_input) = sub_matches.get_one::<String>("modifyQ") {
-                    if *password_input != password {
-                        print_err!("Wrong password!");
-                        return;
-                    }
-                } else {
-                    let actual_password: String = Password::with_theme(&ColorfulTheme::default())
-                        .with_prompt("Enter your current password")
-                        .interact()
-                        .unwrap();
-                    if actual_password != password {
-                        print_err!("Wrong password!");
-                        return;
+        if !prompt::has_terminal() {
+            print_err!("Questions can only be modified from a terminal");
+            return;
+        }
+        if current_password(sub_matches.get_one::<String>("modifyQ")).is_none() {
+            return;
+        }
+        // password is right from here on
+
+        loop {
+            let items = vec![
+                "List questions",
+                "Add a question",
+                "Remove a question",
+                "Quit",
+            ];
+            let chosen: usize = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Choose an option")
+                .items(&items)
+                .default(0)
+                .interact()
+                .unwrap();
+
+            if chosen == 0 {
+                match retrieve_questions() {
+                    Ok(questions) => {
+                        println!("Questions List:");
+                        for question in &questions {
+                            println!("• [{}] {}", question.id, question.question);
+                        }
                     }
+                    Err(_) => print_err!("No question set"),
                 }
-                // password is right from here on
-
-                loop {
-                    let items = vec![
-                        "List questions",
-                        "Add a question",
-                        "Remove a question",
-                        "Quit",
-                    ];
-                    let chosen: usize = Select::with_theme(&ColorfulTheme::default())
-                        .with_prompt("Choose an option")
-                        .items(&items)
-                        .default(0)
-                        .interact()
-                        .unwrap();
-
-                    if chosen == 0 {
-                        match retrieve_questions() {
-                            Ok(questions) => {
-                                println!("Questions List:");
-                                for (question, _) in &questions.value {
-                                    println!("• {}", question);
-                                }
-                            }
-                            Err(_) => print_err!("No question set"),
+            } else if chosen == 1 {
+                let question: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Your Question")
+                    .validate_with(|input: &String| -> Result<(), &str> {
+                        let questions = retrieve_questions().unwrap_or_default();
+                        if questions.iter().any(|q| q.question == input.trim()) {
+                            Err("Cannot set a question twice")
+                        } else if input.len() > 9 {
+                            Ok(())
+                        } else {
+                            Err("Question must be at least 10 characters long")
                         }
-                    } else if chosen == 1 {
-                        let question: String = Input::with_theme(&ColorfulTheme::default())
-                            .with_prompt("Your Question")
-                            .validate_with(|input: &String| -> Result<(), &str> {
-                                let questions = match retrieve_questions() {
-                                    Ok(questions) => questions.value,
-                                    Err(_) => vec![],
-                                };
-                                if questions.iter().any(|(q, _)| q == &format!("{}", input)) {
-                                    Err("Cannot set a question twice")
-                                } else if input.len() > 9 {
-                                    Ok(())
-                                } else {
-                                    Err("Question must be at least 10 characters long")
-                                }
-                            })
-                            .interact_text()
-                            .unwrap();
-
-                        let answer: String = Input::with_theme(&ColorfulTheme::default())
-                            .with_prompt("The Answer")
-                            .validate_with(|input: &String| -> Result<(), &str> {
-                                if input.len() > 1 {
-                                    Ok(())
-                                } else {
-                                    Err("Question must be at least 3 characters long")
-                                }
-                            })
-                            .interact_text()
-                            .unwrap();
-
-                        match add_password_recovery_question(&question, &answer) {
-                            Ok(_) => {
-                                print_success!("Question and answer add successfully!")
-                            }
-                            Err(_) => print_err!("Failed to add question and answer"),
-                        };
-                    } else if chosen == 2 {
-                        let mut choices: Vec<String> = match retrieve_questions() {
-                            Ok(questions) => questions.value.into_iter().map(|(q, _)| q).collect(),
-                            Err(_) => {
-                                print_err!("No question set");
-                                continue;
-                            }
-                        };
-                        choices.push("Cancel".to_string());
-
-                        let chosen: usize = Select::with_theme(&ColorfulTheme::default())
-                            .with_prompt("Which Question to remove?")
-                            .items(&choices)
-                            .default(0)
-                            .interact()
-                            .unwrap();
-
-                        if chosen == choices.len() - 1 {
-                            continue;
+                    })
+                    .interact_text()
+                    .unwrap();
+
+                let answer: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("The Answer")
+                    .validate_with(|input: &String| -> Result<(), &str> {
+                        if normalize_answer(input).chars().count() > 1 {
+                            Ok(())
+                        } else {
+                            Err("Answer must be at least 2 characters long")
                         }
-                        match remove_password_recovery_question(chosen) {
-                            Ok(_) => print_success!("Question removed successfully!"),
-                            Err(_) => print_err!("Failed to remove question"),
-                        };
-                    } else if chosen == 3 {
-                        break;
+                    })
+                    .interact_text()
+                    .unwrap();
+
+                match add_password_recovery_question(&question, &answer) {
+                    Ok(_) => {
+                        print_success!("Question and answer add successfully!")
+                    }
+                    Err(_) => print_err!("Failed to add question and answer"),
+                };
+            } else if chosen == 2 {
+                let questions = match retrieve_questions() {
+                    Ok(questions) => questions,
+                    Err(_) => {
+                        print_err!("No question set");
+                        continue;
                     }
+                };
+                let mut choices: Vec<&str> =
+                    questions.iter().map(|q| q.question.as_str()).collect();
+                choices.push("Cancel");
+
+                let chosen: usize = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Which Question to remove?")
+                    .items(&choices)
+                    .default(0)
+                    .interact()
+                    .unwrap();
+
+                if chosen == choices.len() - 1 {
+                    continue;
                 }
+                match remove_password_recovery_question(questions[chosen].id) {
+                    Ok(_) => print_success!("Question removed successfully!"),
+                    Err(_) => print_err!("Failed to remove question"),
+                };
+            } else if chosen == 3 {
+                break;
             }
-            Err(_) => {
+        }
+    } else if sub_matches.contains_id("protect") {
+        let password = match current_password(sub_matches.get_one::<String>("protect")) {
+            Some(password) => password,
+            None => return,
+        };
+
+        match keyring::protect(&password) {
+            Ok(_) => print_success!("Encryption keys are now protected by your password"),
+            Err(e) => print_err!("Failed to protect encryption keys: {}", e),
+        }
+    } else if sub_matches.contains_id("unprotect") {
+        let password = match current_password(sub_matches.get_one::<String>("unprotect")) {
+            Some(password) => password,
+            None => return,
+        };
+
+        match keyring::unprotect(&password) {
+            Ok(_) => print_success!("Encryption keys are no longer protected"),
+            Err(e) => print_err!("Failed to unprotect encryption keys: {}", e),
+        }
+    } else if let true = sub_matches.contains_id("reset") {
+        if let Ok(true) = keyring::is_protected() {
+            // the keyring can only be unwrapped with the current password
+            print_err!("Encryption keys are protected by your current password");
+            print_solution!("Use \"password --change\" if you still know it");
+            return;
+        }
+        match is_password_set() {
+            Ok(true) => reset_password(),
+            Ok(false) => {
                 print_err!("Password has not been set yet");
                 print_solution!("Use \"password --init\" to set it");
-                return;
             }
+            Err(e) => print_err!("{}", e),
         }
-    } else if let true = sub_matches.contains_id("reset") {
-        match get_password() {
-            Ok(_) => match retrieve_questions() {
-                Ok(question_line) => {
-                    let mut questions: Vec<String> = question_line
-                        .value
-                        .iter()
-                        .map(|(q, _)| q.to_string())
-                        .collect();
-                    let answers: Vec<String> = question_line
-                        .value
-                        .iter()
-                        .map(|(_, a)| a.to_string())
-                        .collect();
-                    if questions.len() < 3 {
-                        print_err!("Not enough questions were set");
-                        print_solution!("3 questions are needed to reset password");
-                        return;
-                    }
-                    questions.push("Cancel".to_string());
-                    let mut answered_questions: Vec<String> = vec![];
-
-                    loop {
-                        let chosen: usize = Select::with_theme(&ColorfulTheme::default())
-                            .with_prompt("A question to answer")
-                            .items(&questions)
-                            .default(0)
-                            .interact()
-                            .unwrap();
-
-                        if chosen == questions.len() - 1 {
-                            return;
-                        }
+    }
+}
 
-                        let answer: String = Input::with_theme(&ColorfulTheme::default())
-                            .with_prompt("Your Answer")
-                            .interact_text()
-                            .unwrap();
+// asks recovery questions until enough are answered right, then for a new password
+fn reset_password() {
+    let password_config = match Config::load() {
+        Ok(config) => config.password,
+        Err(e) => {
+            print_err!("{}", e);
+            return;
+        }
+    };
+    if let Some(seconds) = lockout_remaining(&password_config) {
+        print_err!(
+            "Too many wrong answers, try again in {} minute(s)",
+            seconds.div_ceil(60)
+        );
+        return;
+    }
 
-                        questions = if answer == answers[chosen + answered_questions.len()] {
-                            questions.remove(chosen);
-                            answered_questions.push(answer);
-                            questions
-                        } else {
-                            questions
-                                .into_iter()
-                                .filter(|s| !answered_questions.contains(s))
-                                .collect()
-                        };
-
-                        if answered_questions.len() == 3 {
-                            print_success!("That's 3 right answers! Change your password now!");
-                            break;
-                        }
-                    }
-                    let new_password: String = Password::with_theme(&ColorfulTheme::default())
-                        .with_prompt("Enter your new password")
-                        .with_confirmation("Confirm your new password", "The passwords don't match")
-                        .interact()
-                        .unwrap();
-
-                    match set_password(&new_password) {
-                        Ok(_) => {
-                            print_success!("New password \"{}\" set successfully!", new_password)
-                        }
-                        Err(_) => print_err!("Failed to set new password"),
-                    };
-                }
-                Err(_) => {
-                    print_err!("No password recovery question set");
-                    print_solution!("Use \"password -m\" to set questions");
+    let required = password_config.required_answers();
+    let questions = password_config.questions.unwrap_or_default();
+    if questions.is_empty() {
+        print_err!("No password recovery question set");
+        print_solution!("Use \"password -m\" to set questions");
+        return;
+    }
+    if questions.len() < required {
+        print_err!("Not enough questions were set");
+        print_solution!("{} questions are needed to reset password", required);
+        return;
+    }
+
+    // answered questions are taken out of the list, wrong answers only count as failures
+    let mut remaining: Vec<&RecoveryQuestion> = questions.iter().collect();
+    let mut right_answers = 0;
+    // scripts answer the questions in the order they are listed, until one is wrong
+    let interactive = prompt::is_interactive();
+    while right_answers < required {
+        let (chosen, answer) = if interactive {
+            let mut choices: Vec<&str> = remaining.iter().map(|q| q.question.as_str()).collect();
+            choices.push("Cancel");
+
+            let chosen: usize = match Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("A question to answer")
+                .items(&choices)
+                .default(0)
+                .interact()
+            {
+                Ok(chosen) => chosen,
+                Err(e) => {
+                    print_err!("{}", e);
                     return;
                 }
-            },
-            Err(_) => {
-                print_err!("Password has not been set yet");
-                print_solution!("Use \"password --init\" to set it");
+            };
+            if chosen == choices.len() - 1 {
+                return;
+            }
+            (chosen, prompt::answer("Your Answer"))
+        } else {
+            (0, prompt::answer(&remaining[0].question))
+        };
+        let answer = match answer {
+            Ok(answer) => answer,
+            Err(e) => {
+                print_err!("{}", e);
+                return;
+            }
+        };
+
+        if check_answer(remaining[chosen], &answer) {
+            remaining.remove(chosen);
+            right_answers += 1;
+            continue;
+        }
+
+        match record_failed_answer() {
+            Ok(0) => {
+                print_err!(
+                    "Too many wrong answers, try again in {} minute(s)",
+                    LOCKOUT_SECONDS / 60
+                );
+                return;
+            }
+            Ok(attempts_left) if interactive => {
+                print_err!("Wrong answer, {} attempt(s) left", attempts_left)
+            }
+            Ok(attempts_left) => {
+                print_err!("Wrong answer, {} attempt(s) left", attempts_left);
+                return;
+            }
+            Err(e) => {
+                print_err!("{}", e);
+                return;
+            }
+        }
+    }
+
+    if let Err(e) = clear_failed_answers() {
+        print_err!("{}", e);
+        return;
+    }
+    print_success!(
+        "That's {} right answers! Change your password now!",
+        required
+    );
+
+    let new_password =
+        match prompt::new_password("Enter your new password", "Confirm your new password") {
+            Ok(new_password) => new_password,
+            Err(e) => {
+                print_err!("{}", e);
                 return;
             }
+        };
+
+    match set_password(&new_password) {
+        Ok(_) => print_success!("New password set successfully!"),
+        Err(_) => print_err!("Failed to set new password"),
+    };
+}
+
+/// Asks for the password before running `command` when it is one of the gated commands,
+/// returns whether the command may run. Nothing is gated until a password is set
+pub fn unlock_gate(command: &str) -> bool {
+    let password_config = match Config::load() {
+        Ok(config) => config.password,
+        Err(e) => {
+            print_err!("{}", e);
+            return false;
         }
+    };
+    if !password_config
+        .gated_commands()
+        .iter()
+        .any(|gated| gated == command)
+    {
+        return true;
     }
+    require_password()
 }
 
-fn set_password(password: &str) -> Result<(), PasswordError> {
-    Config::open()?.replace_key(Line::new(PASSWORD_KEYWORD, encrypt_password(password)?))?;
-    Ok(())
+/// Asks for the password whatever the gated commands are, for changes that could lift the
+/// gates themselves. Returns whether to go on, always when no password is set
+pub fn require_password() -> bool {
+    match is_password_set() {
+        Ok(false) => return true,
+        Ok(true) => (),
+        Err(e) => {
+            print_err!("{}", e);
+            return false;
+        }
+    }
+    // the password was given to "mucli unlock", it is checked again as only the hash is trusted
+    let session_password = agent::session().and_then(|session| session.password);
+    if session_password.is_some_and(|password| check_password(&password).unwrap_or(false)) {
+        return true;
+    }
+
+    let password = match prompt::password("Enter your password") {
+        Ok(password) => password,
+        Err(e) => {
+            print_err!("{}", e);
+            return false;
+        }
+    };
+
+    match check_password(&password) {
+        Ok(true) => {
+            // spares a second prompt when the encryption keys are protected too
+            let _ = keyring::unlock_with_password(&password);
+            true
+        }
+        Ok(false) => {
+            print_err!("Wrong password!");
+            false
+        }
+        Err(e) => {
+            print_err!("{}", e);
+            false
+        }
+    }
 }
 
-fn get_password() -> Result<String, PasswordError> {
-    let config = Config::open()?;
+// asks for the current password unless given as argument,
+// None once the reason it cannot be used is printed
+fn current_password(password_input: Option<&String>) -> Option<String> {
+    let password = match password_input {
+        Some(password_input) => password_input.to_string(),
+        None => match prompt::password("Enter your current password") {
+            Ok(password) => password,
+            Err(e) => {
+                print_err!("{}", e);
+                return None;
+            }
+        },
+    };
 
-    if let Some(password_line) = config.get_line(PASSWORD_KEYWORD) {
-        let password: Line<Vec<u8>> = Line::from(&password_line)?;
-        Ok(decrypt_password(password.value)?)
-    } else {
-        Err(PasswordError::RetrievePassword)
+    match check_password(&password) {
+        Ok(true) => Some(password),
+        Ok(false) => {
+            print_err!("Wrong password!");
+            None
+        }
+        Err(PasswordError::PasswordNotSet) => {
+            print_err!("Password has not been set yet");
+            print_solution!("Use \"password --init\" to set it");
+            None
+        }
+        Err(e) => {
+            print_err!("{}", e);
+            None
+        }
     }
 }
 
-fn encrypt_password(password: &str) -> Result<Vec<u8>, PasswordError> {
-    encrypt_decrypt_password(password.as_bytes().to_vec(), true)
+/// Hashes `password` with Argon2id and a random salt, as a PHC string
+pub fn hash_password(password: &str) -> Result<String, PasswordError> {
+    let salt = SaltString::encode_b64(&generate_encryption_key(SALT_SIZE))
+        .map_err(|_| PasswordError::HashPassword)?;
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|_| PasswordError::HashPassword)?;
+    Ok(hash.to_string())
 }
 
-fn decrypt_password(crypted_value: Vec<u8>) -> Result<String, PasswordError> {
-    let decrypted = encrypt_decrypt_password(crypted_value, false)?;
-    Ok(String::from_utf8(decrypted).map_err(|_| PasswordError::DecryptPassword)?)
+/// Puts an answer in a canonical form before it is hashed or checked, so that
+/// case, spacing and equivalent Unicode forms do not matter
+pub fn normalize_answer(answer: &str) -> String {
+    answer
+        .nfkc()
+        .collect::<String>()
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
 }
 
-fn init_password_key() -> Result<(), PasswordError> {
-    let mut config = Config::open()?;
-    if let None = config.get_line(PASSWORD_KEY_KEYWORD) {
-        config.set_line(Line::new(PASSWORD_KEY_KEYWORD, generate_encryption_key(32)))?
-    }
-    Ok(())
+pub fn hash_answer(answer: &str) -> Result<String, PasswordError> {
+    hash_password(&normalize_answer(answer))
 }
 
-fn add_password_recovery_question(question: &str, answer: &str) -> Result<(), PasswordError> {
-    let config = Config::open()?;
-
-    let line = if let Some(line) = config.get_line(QUESTION_KEYWORD) {
-        let mut parsed_line: Line<Vec<(String, String)>> = Line::from(&line)?;
-        parsed_line.add((question.trim().to_string(), answer.trim().to_string()));
-        parsed_line
-    } else {
-        Line::new(
-            QUESTION_KEYWORD,
-            vec![(question.trim().to_string(), answer.trim().to_string())],
-        )
-    };
-    Config::open()?.replace_key(line)?;
-    Ok(())
+fn check_answer(question: &RecoveryQuestion, answer: &str) -> bool {
+    verify_hash(&normalize_answer(answer), &question.answer_hash).unwrap_or(false)
 }
-fn remove_password_recovery_question(index: usize) -> Result<(), PasswordError> {
-    let mut questions = retrieve_questions()?;
 
-    questions.value.remove(index);
+pub fn check_password(password: &str) -> Result<bool, PasswordError> {
+    let hash = Config::load()?
+        .password
+        .hash
+        .ok_or(PasswordError::PasswordNotSet)?;
+    verify_hash(password, &hash)
+}
 
-    Config::open()?.replace_key(questions)?;
-    Ok(())
+fn verify_hash(secret: &str, hash: &str) -> Result<bool, PasswordError> {
+    let hash = PasswordHash::new(hash).map_err(|_| PasswordError::InvalidHash)?;
+
+    // the hashes are compared in constant time
+    Ok(Argon2::default()
+        .verify_password(secret.as_bytes(), &hash)
+        .is_ok())
+}
+
+// seconds left before recovery answers are accepted again
+fn lockout_remaining(password_config: &PasswordConfig) -> Option<u64> {
+    let now = unix_time();
+    password_config
+        .locked_until
+        .filter(|locked_until| *locked_until > now)
+        .map(|locked_until| locked_until - now)
 }
-fn retrieve_questions() -> Result<Line<Vec<(String, String)>>, PasswordError> {
-    let config: Config = Config::open()?;
-    if let Some(line) = config.get_line(QUESTION_KEYWORD) {
-        Ok(Line::from(&line)?)
-    } else {
-        return Err(GenericError::KeyNotFound {
-            key: QUESTION_KEYWORD.to_owned(),
+
+// returns how many wrong answers are left before the lockout, 0 once locked out
+fn record_failed_answer() -> Result<u32, PasswordError> {
+    Config::update(|config| {
+        let password_config = &mut config.password;
+        let failed_answers = password_config.failed_answers.unwrap_or(0) + 1;
+
+        if failed_answers >= MAX_FAILED_ANSWERS {
+            password_config.failed_answers = None;
+            password_config.locked_until = Some(unix_time() + LOCKOUT_SECONDS);
+            return Ok(0);
         }
-        .into());
-    }
+        password_config.failed_answers = Some(failed_answers);
+        Ok(MAX_FAILED_ANSWERS - failed_answers)
+    })
+}
+
+fn clear_failed_answers() -> Result<(), PasswordError> {
+    Config::update(|config| {
+        config.password.failed_answers = None;
+        config.password.locked_until = None;
+        Ok::<(), PasswordError>(())
+    })
 }
 
-fn encrypt_decrypt_password(
-    password: Vec<u8>,
-    encrypt_bool: bool,
-) -> Result<Vec<u8>, PasswordError> {
-    let config = Config::open()?;
-    if let Some(password_line) = config.get_line(PASSWORD_KEY_KEYWORD) {
-        let line: Line<Vec<u8>> = Line::from(&password_line)?;
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
 
-        if encrypt_bool {
-            return Ok(encrypt(&password, &line.value));
-        } else {
-            return Ok(decrypt(&password, &line.value)?);
+fn is_password_set() -> Result<bool, PasswordError> {
+    Ok(Config::load()?.password.hash.is_some())
+}
+
+fn set_password(password: &str) -> Result<(), PasswordError> {
+    let hash = hash_password(password)?;
+    Config::update(|config| {
+        config.password.hash = Some(hash);
+        Ok::<(), PasswordError>(())
+    })?;
+
+    // a session unlocked with the previous password is over
+    agent::end_session();
+    Ok(())
+}
+
+fn add_password_recovery_question(question: &str, answer: &str) -> Result<(), PasswordError> {
+    let answer_hash = hash_answer(answer)?;
+    Config::update(|config| {
+        let questions = config.password.questions.get_or_insert_with(Vec::new);
+        let id = questions.iter().map(|q| q.id).max().unwrap_or(0) + 1;
+        questions.push(RecoveryQuestion {
+            id,
+            question: question.trim().to_string(),
+            answer_hash,
+        });
+        Ok::<(), PasswordError>(())
+    })
+}
+fn remove_password_recovery_question(id: u32) -> Result<(), PasswordError> {
+    Config::update(|config| {
+        if let Some(questions) = config.password.questions.as_mut() {
+            questions.retain(|q| q.id != id);
+        }
+        Ok::<(), PasswordError>(())
+    })
+}
+fn retrieve_questions() -> Result<Vec<RecoveryQuestion>, PasswordError> {
+    match Config::load()?.password.questions {
+        Some(questions) => Ok(questions),
+        None => Err(GenericError::KeyNotFound {
+            key: "password.questions".to_owned(),
         }
+        .into()),
     }
-
-    Err(PasswordError::EncryptPassword)
 }
@@ -1,7 +1,7 @@
 use clap::ArgMatches;
 use dialoguer::{theme::ColorfulTheme, Input, Select};
 use itertools::Itertools;
-use qrcode::QrCode;
+use qrcode::{types::QrError, QrCode};
 use wifi_qr_code::{AuthenticationType, Visibility, WifiCredentials};
 use wifiscanner::{self};
 
@@ -66,13 +66,20 @@ pub fn qrcode_command(sub_matches: &ArgMatches) {
         wifi_credentials.encode()
     };
 
-    let code = QrCode::new(format!("{string}").as_bytes()).unwrap();
-    let string = code
+    match render_qrcode(&string) {
+        Ok(code) => print!("{}", code),
+        Err(e) => print_err!("{}", e),
+    }
+}
+
+/// Draws `text` as a QR code with characters, for the terminal
+pub fn render_qrcode(text: &str) -> Result<String, QrError> {
+    let code = QrCode::new(text.as_bytes())?;
+    Ok(code
         .render::<char>()
         .quiet_zone(false)
         .module_dimensions(2, 1)
-        .build();
-    print!("{}", string);
+        .build())
 }
 
 fn protocol(security: &str) -> AuthenticationType {
@@ -1,6 +1,6 @@
+use crate::utils::prompt;
 use crate::{print_err, print_info, print_success, VERSION};
 use custom_error::custom_error;
-use dialoguer::{theme::ColorfulTheme, Confirm};
 use std::io::Error;
 custom_error! {pub UpdateError
     Io{source: Error} = "{source}",
@@ -21,13 +21,13 @@ pub async fn update_command() {
                     "This version is superior to current version \"{}\"",
                     VERSION
                 );
-                let confirmation = Confirm::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Would you like to upgrade to the latest version?")
-                    .interact()
-                    .unwrap();
-
-                if !confirmation {
-                    return;
+                match prompt::confirm("Would you like to upgrade to the latest version?") {
+                    Ok(true) => (),
+                    Ok(false) => return,
+                    Err(e) => {
+                        print_err!("{}", e);
+                        return;
+                    }
                 }
                 //update version
                 print_info!("Feature coming soon!");
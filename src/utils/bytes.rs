@@ -0,0 +1,47 @@
+use std::ops::Deref;
+
+use data_encoding::BASE64;
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Binary value stored as base64 text in the config file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Bytes(#[serde(with = "base64")] pub Vec<u8>);
+
+impl From<Vec<u8>> for Bytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Bytes(bytes)
+    }
+}
+
+impl Deref for Bytes {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Serde helpers writing `Vec<u8>` fields as base64, arrays of numbers
+/// written by older versions are still accepted
+pub mod base64 {
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Encoded {
+        Text(String),
+        Numbers(Vec<u8>),
+    }
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        match Encoded::deserialize(deserializer)? {
+            Encoded::Text(text) => BASE64.decode(text.as_bytes()).map_err(D::Error::custom),
+            Encoded::Numbers(bytes) => Ok(bytes),
+        }
+    }
+}
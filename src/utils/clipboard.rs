@@ -0,0 +1,117 @@
+use std::{
+    env,
+    io::{self, Write},
+    process::{Command, Stdio},
+};
+
+use custom_error::custom_error;
+
+custom_error! {pub ClipboardError
+    Io{source: io::Error} = "{source}",
+    NoClipboard = "No clipboard command found, install wl-clipboard, xclip or xsel",
+    Failed{program: String} = "\"{program}\" failed to access the clipboard"
+}
+
+// clipboard commands tried in order, the first one installed is used
+#[cfg(target_os = "macos")]
+fn copy_commands() -> Vec<Vec<&'static str>> {
+    vec![vec!["pbcopy"]]
+}
+#[cfg(target_os = "macos")]
+fn paste_commands() -> Vec<Vec<&'static str>> {
+    vec![vec!["pbpaste"]]
+}
+
+#[cfg(windows)]
+fn copy_commands() -> Vec<Vec<&'static str>> {
+    vec![vec!["clip"]]
+}
+#[cfg(windows)]
+fn paste_commands() -> Vec<Vec<&'static str>> {
+    vec![vec![
+        "powershell",
+        "-NoProfile",
+        "-Command",
+        "Get-Clipboard",
+    ]]
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn copy_commands() -> Vec<Vec<&'static str>> {
+    let mut commands = vec![
+        vec!["xclip", "-selection", "clipboard"],
+        vec!["xsel", "--clipboard", "--input"],
+    ];
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        commands.insert(0, vec!["wl-copy"]);
+    }
+    commands
+}
+#[cfg(not(any(target_os = "macos", windows)))]
+fn paste_commands() -> Vec<Vec<&'static str>> {
+    let mut commands = vec![
+        vec!["xclip", "-selection", "clipboard", "-o"],
+        vec!["xsel", "--clipboard", "--output"],
+    ];
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        commands.insert(0, vec!["wl-paste", "--no-newline"]);
+    }
+    commands
+}
+
+/// Puts `text` in the system clipboard
+pub fn copy(text: &str) -> Result<(), ClipboardError> {
+    for command in copy_commands() {
+        // the clipboard owner may keep running in the background, nothing is read from it
+        let mut child = match Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        if !child.wait()?.success() {
+            return Err(ClipboardError::Failed {
+                program: command[0].to_string(),
+            });
+        }
+        return Ok(());
+    }
+    Err(ClipboardError::NoClipboard)
+}
+
+/// Current text of the system clipboard
+pub fn paste() -> Result<String, ClipboardError> {
+    for command in paste_commands() {
+        let output = match Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+        {
+            Ok(output) => output,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        if !output.status.success() {
+            return Err(ClipboardError::Failed {
+                program: command[0].to_string(),
+            });
+        }
+        return Ok(String::from_utf8_lossy(&output.stdout).to_string());
+    }
+    Err(ClipboardError::NoClipboard)
+}
+
+pub fn clear() -> Result<(), ClipboardError> {
+    copy("")
+}
@@ -0,0 +1,395 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::{
+    compression::{ExtractLimits, DEFAULT_MAX_ENTRIES, DEFAULT_MAX_RATIO, DEFAULT_MAX_SIZE},
+    encryption::{keyring::WrappedKeyring, recipient::Identity},
+    password::{hash_answer, hash_password, DEFAULT_GATED_COMMANDS, DEFAULT_REQUIRED_ANSWERS},
+};
+
+use super::{bytes::Bytes, get_config_path, is_default_config, line::Line, GenericError};
+
+/// Version of the config layout written by this build, files with an older version
+/// go through `MIGRATIONS` when read
+pub const CONFIG_VERSION: u32 = 1;
+
+// MIGRATIONS[n] upgrades a table from version n + 1 to version n + 2
+const MIGRATIONS: [fn(&mut toml::Table); 0] = [];
+
+const LEGACY_CONFIG_FILE_NAME: &str = "mucli_config.txt";
+pub const LEGACY_KEYS: [&str; 8] = [
+    "MUCLI_ENCRYPT",
+    "MUCLI_ENCRYPT_WRAPPED",
+    "MUCLI_IDENTITY",
+    "MUCLI_IDENTITY_WRAPPED",
+    "MUCLI_KEYS_BACKUP",
+    "MUCLI_PASSWORD",
+    "MUCLI_KEY_PASSWORD",
+    "MUCLI_QUESTION",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub version: u32,
+    pub encryption: EncryptionConfig,
+    pub password: PasswordConfig,
+    pub currency: CurrencyConfig,
+    pub antivirus: AntivirusConfig,
+    pub archive: ArchiveConfig,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    // versioned keyring, a key version is its index
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keys: Option<Vec<Bytes>>,
+    // keyring sealed with the mucli password, replaces `keys` once protected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wrapped_keys: Option<WrappedKeyring>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identities: Option<Vec<Identity>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wrapped_identities: Option<WrappedKeyring>,
+    // fingerprint of the last keyring that was exported
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup_fingerprint: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasswordConfig {
+    // Argon2id hash of the mucli password, as a PHC string
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub questions: Option<Vec<RecoveryQuestion>>,
+    // right answers needed to reset the password, `DEFAULT_REQUIRED_ANSWERS` when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub required_answers: Option<usize>,
+    // wrong answers since the last lockout or reset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_answers: Option<u32>,
+    // unix time before which the password cannot be reset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locked_until: Option<u64>,
+    // commands asking for the password before running, `DEFAULT_GATED_COMMANDS` when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gated_commands: Option<Vec<String>>,
+}
+
+/// Question asked to reset a forgotten password, only a hash of its answer is kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveryQuestion {
+    // unchanged when other questions are removed
+    pub id: u32,
+    pub question: String,
+    pub answer_hash: String,
+}
+
+impl PasswordConfig {
+    pub fn required_answers(&self) -> usize {
+        self.required_answers
+            .unwrap_or(DEFAULT_REQUIRED_ANSWERS)
+            .max(1)
+    }
+
+    pub fn gated_commands(&self) -> Vec<String> {
+        self.gated_commands.clone().unwrap_or_else(|| {
+            DEFAULT_GATED_COMMANDS
+                .iter()
+                .map(|command| command.to_string())
+                .collect()
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CurrencyConfig {
+    // overrides the key mucli was built with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AntivirusConfig {
+    // overrides the key mucli was built with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+}
+
+// limits of "unzip", 0 lifts one
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    // bytes an extraction writes, `DEFAULT_MAX_SIZE` when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    // entries of an archive, `DEFAULT_MAX_ENTRIES` when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_entries: Option<u64>,
+    // bytes written per byte of archive, `DEFAULT_MAX_RATIO` when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_ratio: Option<u64>,
+}
+
+impl ArchiveConfig {
+    pub fn limits(&self) -> ExtractLimits {
+        ExtractLimits {
+            max_size: self.max_size.unwrap_or(DEFAULT_MAX_SIZE),
+            max_entries: self.max_entries.unwrap_or(DEFAULT_MAX_ENTRIES),
+            max_ratio: self.max_ratio.unwrap_or(DEFAULT_MAX_RATIO),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            version: CONFIG_VERSION,
+            encryption: EncryptionConfig::default(),
+            password: PasswordConfig::default(),
+            currency: CurrencyConfig::default(),
+            antivirus: AntivirusConfig::default(),
+            archive: ArchiveConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config under a shared lock, a legacy or older config is migrated in memory
+    /// only and written back by the next `Config::update`
+    pub fn load() -> Result<Config, GenericError> {
+        let _lock = lock(false)?;
+        Config::read()
+    }
+
+    /// Reads, modifies and writes back the config while holding an exclusive lock,
+    /// nothing is written when `modify` fails. `Config::load` must not be called
+    /// from `modify`, the lock is not reentrant
+    pub fn update<T, E, F>(modify: F) -> Result<T, E>
+    where
+        E: From<GenericError>,
+        F: FnOnce(&mut Config) -> Result<T, E>,
+    {
+        let _lock = lock(true)?;
+        let legacy_path = pending_legacy()?;
+
+        let mut config = Config::read()?;
+        let result = modify(&mut config)?;
+        config.version = CONFIG_VERSION;
+        config.write()?;
+
+        // the old file is kept aside rather than deleted, in case something went unnoticed
+        if let Some(legacy_path) = legacy_path {
+            fs::rename(&legacy_path, legacy_path.with_extension("txt.bak"))
+                .map_err(GenericError::from)?;
+        }
+
+        Ok(result)
+    }
+
+    fn read() -> Result<Config, GenericError> {
+        let path = get_config_path()?;
+        if let Some(legacy_path) = pending_legacy()? {
+            return from_legacy(&fs::read_to_string(legacy_path)?);
+        }
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        Config::parse(&fs::read_to_string(&path)?, &path)
+    }
+
+    /// Parses the content of a config file, migrating it to `CONFIG_VERSION`
+    pub fn parse(content: &str, path: &Path) -> Result<Config, GenericError> {
+        Ok(parse_table(content, path)?.try_into()?)
+    }
+
+    /// Lets `edit` change the raw content of the config while holding the exclusive lock,
+    /// the returned content is parsed and written back
+    pub fn edit<E, F>(edit: F) -> Result<(), E>
+    where
+        E: From<GenericError>,
+        F: FnOnce(&str) -> Result<String, E>,
+    {
+        Config::update(|config| {
+            let path = get_config_path()?;
+            let content = toml::to_string_pretty(config).map_err(GenericError::from)?;
+            *config = Config::parse(&edit(&content)?, &path)?;
+            Ok(())
+        })
+    }
+
+    // written next to the config and renamed over it so that it is never seen half-written
+    fn write(&self) -> Result<(), GenericError> {
+        let path = get_config_path()?;
+        let dir = path.parent().ok_or(GenericError::Custom {
+            message: "Invalid config path".to_string(),
+        })?;
+
+        let mut temp_file = NamedTempFile::new_in(dir)?;
+        temp_file.write_all(toml::to_string_pretty(self)?.as_bytes())?;
+        temp_file.as_file().sync_all()?;
+        temp_file.persist(&path).map_err(|e| e.error)?;
+
+        Ok(())
+    }
+}
+
+/// Parses the content of a config file as a table, migrated to `CONFIG_VERSION`
+pub fn parse_table(content: &str, path: &Path) -> Result<toml::Table, GenericError> {
+    let mut table: toml::Table = toml::from_str(content)?;
+    let version = table
+        .get("version")
+        .and_then(|version| version.as_integer())
+        .unwrap_or(1) as u32;
+
+    if version > CONFIG_VERSION {
+        return Err(GenericError::ConfigVersion {
+            version,
+            path: path.display().to_string(),
+        });
+    }
+    for migration in MIGRATIONS.iter().skip(version.max(1) as usize - 1) {
+        migration(&mut table);
+    }
+
+    Ok(table)
+}
+
+fn lock(exclusive: bool) -> Result<File, GenericError> {
+    lock_file(&get_config_path()?.with_extension("lock"), exclusive)
+}
+
+/// Takes a shared or exclusive lock on `path`, created when missing,
+/// held until the returned file is dropped
+pub fn lock_file(path: &Path, exclusive: bool) -> Result<File, GenericError> {
+    let dir = path.parent().ok_or(GenericError::Custom {
+        message: "Invalid config path".to_string(),
+    })?;
+    fs::create_dir_all(dir)?;
+
+    let lock_file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    if exclusive {
+        lock_file.lock()?;
+    } else {
+        lock_file.lock_shared()?;
+    }
+
+    // released when the file is dropped
+    Ok(lock_file)
+}
+
+/// Location of the config written by older versions, only migrated to the default config
+pub fn legacy_config_path() -> Result<Option<PathBuf>, GenericError> {
+    if !is_default_config() {
+        return Ok(None);
+    }
+    let home_dir = dirs::home_dir().ok_or(GenericError::Custom {
+        message: "Cannot access home dir".to_string(),
+    })?;
+    Ok(Some(home_dir.join(LEGACY_CONFIG_FILE_NAME)))
+}
+
+// the legacy config still to migrate, if any
+fn pending_legacy() -> Result<Option<PathBuf>, GenericError> {
+    if get_config_path()?.exists() {
+        return Ok(None);
+    }
+    Ok(legacy_config_path()?.filter(|legacy_path| legacy_path.exists()))
+}
+
+/// Converts the `KEY=<json>` lines of mucli_config.txt, unknown keys are dropped
+fn from_legacy(content: &str) -> Result<Config, GenericError> {
+    fn value<T: DeserializeOwned + Serialize>(line: &str) -> Result<T, GenericError> {
+        Ok(Line::<T>::from(line)?.value)
+    }
+    fn bytes(line: &str) -> Result<Bytes, GenericError> {
+        Ok(Bytes(value(line)?))
+    }
+
+    let mut config = Config::default();
+    let mut password = None;
+    let mut password_key = None;
+
+    for line in legacy_lines(content) {
+        let key = line.split('=').next().unwrap_or_default();
+        match key {
+            "MUCLI_ENCRYPT" => {
+                let keys: Vec<Vec<u8>> = value(line)?;
+                config.encryption.keys = Some(keys.into_iter().map(Bytes).collect());
+            }
+            "MUCLI_ENCRYPT_WRAPPED" => config.encryption.wrapped_keys = Some(value(line)?),
+            "MUCLI_IDENTITY" => config.encryption.identities = Some(value(line)?),
+            "MUCLI_IDENTITY_WRAPPED" => config.encryption.wrapped_identities = Some(value(line)?),
+            "MUCLI_KEYS_BACKUP" => config.encryption.backup_fingerprint = Some(value(line)?),
+            "MUCLI_PASSWORD" => password = Some(bytes(line)?),
+            "MUCLI_KEY_PASSWORD" => password_key = Some(bytes(line)?),
+            "MUCLI_QUESTION" => config.password.questions = Some(recovery_questions(value(line)?)),
+            _ => (),
+        }
+    }
+
+    if let (Some(password), Some(password_key)) = (password, password_key) {
+        config.password.hash = hash_legacy_password(&password, &password_key);
+    }
+    Ok(config)
+}
+
+fn recovery_questions(questions: Vec<(String, String)>) -> Vec<RecoveryQuestion> {
+    questions
+        .into_iter()
+        .zip(1..)
+        .filter_map(|((question, answer), id)| {
+            Some(RecoveryQuestion {
+                id,
+                question,
+                answer_hash: hash_answer(&answer).ok()?,
+            })
+        })
+        .collect()
+}
+
+// a password that cannot be decrypted is dropped, "password --init" sets a new one
+fn hash_legacy_password(encrypted_password: &[u8], password_key: &[u8]) -> Option<String> {
+    let password = simplecrypt::decrypt(encrypted_password, password_key).ok()?;
+    hash_password(&String::from_utf8(password).ok()?).ok()
+}
+
+// the old replace_key dropped the trailing newline, so an entry appended afterwards
+// could end up on the same line as the previous one
+pub fn legacy_lines(content: &str) -> Vec<&str> {
+    let mut lines = vec![];
+
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        let mut rest = line;
+        // searched after the first character, which may take more than one byte
+        while let Some(split) = rest.chars().next().and_then(|first| {
+            let start = first.len_utf8();
+            LEGACY_KEYS
+                .iter()
+                .filter_map(|key| rest[start..].find(&format!("{}=", key)).map(|i| i + start))
+                .min()
+        }) {
+            lines.push(&rest[..split]);
+            rest = &rest[split..];
+        }
+        lines.push(rest);
+    }
+
+    lines
+}
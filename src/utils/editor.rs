@@ -0,0 +1,40 @@
+use std::{env, path::Path, process::Command};
+
+use custom_error::custom_error;
+
+#[cfg(windows)]
+const DEFAULT_EDITOR: &str = "notepad";
+#[cfg(not(windows))]
+const DEFAULT_EDITOR: &str = "vi";
+
+custom_error! {pub EditorError
+    NoEditor = "No editor set in $VISUAL or $EDITOR",
+    CannotRun{editor: String, message: String} = "Cannot run \"{editor}\": {message}",
+    Failed{editor: String, status: String} = "\"{editor}\" exited with {status}, nothing was saved"
+}
+
+/// Opens `path` in `$VISUAL`, `$EDITOR` or the platform default editor and waits for it
+pub fn edit_file(path: &Path) -> Result<(), EditorError> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| DEFAULT_EDITOR.to_string());
+
+    // the editor may come with arguments, as in "code --wait"
+    let mut words = editor.split_whitespace();
+    let program = words.next().ok_or(EditorError::NoEditor)?;
+    let status = Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()
+        .map_err(|e| EditorError::CannotRun {
+            editor: editor.to_string(),
+            message: e.to_string(),
+        })?;
+    if !status.success() {
+        return Err(EditorError::Failed {
+            editor,
+            status: status.to_string(),
+        });
+    }
+    Ok(())
+}
@@ -3,11 +3,39 @@ use std::{
     io::{Read, Write},
     path::PathBuf,
 };
+
+use rand::RngCore;
+
+// v1 header: marker, key version and layer count, followed by a raw simplecrypt payload
 const HEADER_MARKER: [u8; 4] = [0xAA, 0xBB, 0xCC, 0xDD];
 const HEADER_SIZE: usize = 4;
 const VERSION_SIZE: usize = 4;
 const LAYER_SIZE: usize = 4;
 
+// v2+ header: authenticated container, the whole header is used as AEAD associated data
+// v2 seals the payload in one piece, v3 seals it in fixed-size chunks and stores the chunk size,
+// v3 files encrypted for recipients also carry the file key wrapped for each of them
+pub const AEAD_HEADER_MARKER: [u8; 4] = *b"MUCE";
+pub const SINGLE_SHOT_FORMAT_VERSION: u8 = 2;
+pub const FORMAT_VERSION: u8 = 3;
+pub const ALGORITHM_XCHACHA20_POLY1305: u8 = 1;
+pub const KDF_HKDF_SHA256: u8 = 1;
+pub const KDF_X25519: u8 = 2;
+pub const SALT_SIZE: usize = 16;
+pub const NONCE_SIZE: usize = 24;
+pub const TAG_SIZE: usize = 16;
+pub const CHUNK_SIZE: u32 = 64 * 1024;
+const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+const CHUNK_SIZE_SIZE: usize = 4;
+const SINGLE_SHOT_HEADER_SIZE: usize =
+    HEADER_SIZE + 4 + VERSION_SIZE + LAYER_SIZE + SALT_SIZE + NONCE_SIZE;
+const AEAD_HEADER_SIZE: usize = SINGLE_SHOT_HEADER_SIZE + CHUNK_SIZE_SIZE;
+pub const MAX_RECIPIENTS: usize = 64;
+const RECIPIENT_COUNT_SIZE: usize = 2;
+const RECIPIENT_STANZA_SIZE: usize = 32 + NONCE_SIZE + 32 + TAG_SIZE;
+pub const MAX_AEAD_HEADER_SIZE: usize =
+    AEAD_HEADER_SIZE + RECIPIENT_COUNT_SIZE + MAX_RECIPIENTS * RECIPIENT_STANZA_SIZE;
+
 #[macro_export]
 macro_rules! file_as_str {
     ($name: expr) => {{
@@ -95,6 +123,220 @@ macro_rules! crypted_file {
 
 use crate::encryption::{latest_encryption_version, EncryptionError};
 
+/// File key sealed for one recipient with a key agreed between an ephemeral key and theirs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecipientStanza {
+    pub ephemeral_public: [u8; 32],
+    pub nonce: [u8; NONCE_SIZE],
+    pub wrapped_key: [u8; 32 + TAG_SIZE],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AeadHeader {
+    pub format_version: u8,
+    pub algorithm: u8,
+    pub kdf: u8,
+    pub key_version: u32,
+    pub layer: u32,
+    pub salt: [u8; SALT_SIZE],
+    pub nonce: [u8; NONCE_SIZE],
+    pub chunk_size: u32,
+    pub recipients: Vec<RecipientStanza>,
+}
+
+impl AeadHeader {
+    pub fn new(key_version: u32, layer: u32) -> Self {
+        let mut salt = [0u8; SALT_SIZE];
+        let mut nonce = [0u8; NONCE_SIZE];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        AeadHeader {
+            format_version: FORMAT_VERSION,
+            algorithm: ALGORITHM_XCHACHA20_POLY1305,
+            kdf: KDF_HKDF_SHA256,
+            key_version,
+            layer,
+            salt,
+            nonce,
+            chunk_size: CHUNK_SIZE,
+            recipients: vec![],
+        }
+    }
+
+    pub fn with_recipients(mut self, recipients: Vec<RecipientStanza>) -> Self {
+        self.kdf = KDF_X25519;
+        self.key_version = 0;
+        self.recipients = recipients;
+        self
+    }
+
+    pub fn size(&self) -> usize {
+        if self.format_version == SINGLE_SHOT_FORMAT_VERSION {
+            SINGLE_SHOT_HEADER_SIZE
+        } else if self.kdf == KDF_X25519 {
+            AEAD_HEADER_SIZE + RECIPIENT_COUNT_SIZE + self.recipients.len() * RECIPIENT_STANZA_SIZE
+        } else {
+            AEAD_HEADER_SIZE
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut header_content = Vec::with_capacity(self.size());
+
+        header_content.extend_from_slice(&AEAD_HEADER_MARKER);
+        header_content.extend_from_slice(&[self.format_version, self.algorithm, self.kdf, 0]);
+        header_content.extend_from_slice(&self.key_version.to_be_bytes());
+        header_content.extend_from_slice(&self.layer.to_be_bytes());
+        header_content.extend_from_slice(&self.salt);
+        header_content.extend_from_slice(&self.nonce);
+        if self.format_version != SINGLE_SHOT_FORMAT_VERSION {
+            header_content.extend_from_slice(&self.chunk_size.to_be_bytes());
+        }
+        if self.kdf == KDF_X25519 {
+            header_content.extend_from_slice(&(self.recipients.len() as u16).to_be_bytes());
+            for recipient in &self.recipients {
+                header_content.extend_from_slice(&recipient.ephemeral_public);
+                header_content.extend_from_slice(&recipient.nonce);
+                header_content.extend_from_slice(&recipient.wrapped_key);
+            }
+        }
+
+        header_content
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, EncryptionError> {
+        if bytes.len() < HEADER_SIZE + 4 {
+            return Err(EncryptionError::TruncatedFile);
+        }
+
+        let (format_version, algorithm, kdf) = (
+            bytes[HEADER_SIZE],
+            bytes[HEADER_SIZE + 1],
+            bytes[HEADER_SIZE + 2],
+        );
+        if format_version != FORMAT_VERSION && format_version != SINGLE_SHOT_FORMAT_VERSION {
+            return Err(EncryptionError::UnsupportedFormat {
+                version: format_version,
+            });
+        }
+        let recipient_kdf = kdf == KDF_X25519 && format_version != SINGLE_SHOT_FORMAT_VERSION;
+        if algorithm != ALGORITHM_XCHACHA20_POLY1305 || (kdf != KDF_HKDF_SHA256 && !recipient_kdf) {
+            return Err(EncryptionError::UnsupportedAlgorithm { algorithm, kdf });
+        }
+        let header_size = if format_version == SINGLE_SHOT_FORMAT_VERSION {
+            SINGLE_SHOT_HEADER_SIZE
+        } else {
+            AEAD_HEADER_SIZE
+        };
+        if bytes.len() < header_size {
+            return Err(EncryptionError::TruncatedFile);
+        }
+
+        let mut offset = HEADER_SIZE + 4;
+        let key_version =
+            u32::from_be_bytes(bytes[offset..offset + VERSION_SIZE].try_into().unwrap());
+        offset += VERSION_SIZE;
+        let layer = u32::from_be_bytes(bytes[offset..offset + LAYER_SIZE].try_into().unwrap());
+        offset += LAYER_SIZE;
+        let salt: [u8; SALT_SIZE] = bytes[offset..offset + SALT_SIZE].try_into().unwrap();
+        offset += SALT_SIZE;
+        let nonce: [u8; NONCE_SIZE] = bytes[offset..offset + NONCE_SIZE].try_into().unwrap();
+        offset += NONCE_SIZE;
+        let chunk_size = if format_version == SINGLE_SHOT_FORMAT_VERSION {
+            0
+        } else {
+            u32::from_be_bytes(bytes[offset..offset + CHUNK_SIZE_SIZE].try_into().unwrap())
+        };
+        if format_version != SINGLE_SHOT_FORMAT_VERSION
+            && (chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE)
+        {
+            return Err(EncryptionError::InvalidFileContent);
+        }
+        offset += CHUNK_SIZE_SIZE;
+
+        let mut recipients = vec![];
+        if recipient_kdf {
+            let count_bytes = bytes
+                .get(offset..offset + RECIPIENT_COUNT_SIZE)
+                .ok_or(EncryptionError::TruncatedFile)?;
+            let count = u16::from_be_bytes(count_bytes.try_into().unwrap()) as usize;
+            if count == 0 || count > MAX_RECIPIENTS {
+                return Err(EncryptionError::InvalidFileContent);
+            }
+            offset += RECIPIENT_COUNT_SIZE;
+
+            for _ in 0..count {
+                let stanza = bytes
+                    .get(offset..offset + RECIPIENT_STANZA_SIZE)
+                    .ok_or(EncryptionError::TruncatedFile)?;
+                recipients.push(RecipientStanza {
+                    ephemeral_public: stanza[..32].try_into().unwrap(),
+                    nonce: stanza[32..32 + NONCE_SIZE].try_into().unwrap(),
+                    wrapped_key: stanza[32 + NONCE_SIZE..].try_into().unwrap(),
+                });
+                offset += RECIPIENT_STANZA_SIZE;
+            }
+        }
+
+        Ok(AeadHeader {
+            format_version,
+            algorithm,
+            kdf,
+            key_version,
+            layer,
+            salt,
+            nonce,
+            chunk_size,
+            recipients,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum FileFormat {
+    Plain,
+    Legacy { layer: u32, version: u32 },
+    Aead(AeadHeader),
+}
+
+impl FileFormat {
+    /// Format of the content `reader` starts with, only its header is read
+    pub fn read<R: Read>(reader: &mut R) -> Result<FileFormat, EncryptionError> {
+        let mut start = Vec::with_capacity(AEAD_HEADER_SIZE);
+        reader
+            .take(MAX_AEAD_HEADER_SIZE as u64)
+            .read_to_end(&mut start)?;
+
+        if start.len() >= HEADER_SIZE && start[0..HEADER_SIZE] == AEAD_HEADER_MARKER {
+            return Ok(FileFormat::Aead(AeadHeader::from_bytes(&start)?));
+        }
+
+        if start.len() < HEADER_SIZE + VERSION_SIZE + LAYER_SIZE
+            || start[0..HEADER_SIZE] != HEADER_MARKER
+        {
+            return Ok(FileFormat::Plain);
+        }
+
+        let version_bytes = &start[HEADER_SIZE..HEADER_SIZE + VERSION_SIZE];
+        let layer_bytes =
+            &start[HEADER_SIZE + VERSION_SIZE..HEADER_SIZE + VERSION_SIZE + LAYER_SIZE];
+
+        Ok(FileFormat::Legacy {
+            layer: u32::from_be_bytes(layer_bytes.try_into().unwrap()),
+            version: u32::from_be_bytes(version_bytes.try_into().unwrap()),
+        })
+    }
+
+    pub fn header_size(&self) -> usize {
+        match self {
+            FileFormat::Plain => 0,
+            FileFormat::Legacy { .. } => HEADER_SIZE + VERSION_SIZE + LAYER_SIZE,
+            FileFormat::Aead(header) => header.size(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CryptedFile {
     pub path: PathBuf,
@@ -116,6 +358,17 @@ impl CryptedFile {
             read: common_options,
         })
     }
+    /// Only ever opens `path` for reading, a missing file is not created and the content
+    /// of a read-only one can still be read
+    pub fn read_only(path: PathBuf) -> Self {
+        let read_options = std::fs::OpenOptions::new().read(true).clone();
+
+        CryptedFile {
+            path,
+            edit: read_options.clone(),
+            read: read_options,
+        }
+    }
     pub fn from(mut self, other_file: &mut CryptedFile) -> Result<Self, EncryptionError> {
         let (layer, version) = other_file.header()?;
 
@@ -125,7 +378,7 @@ impl CryptedFile {
         Ok(self)
     }
 
-    fn reader(&self) -> Result<File, EncryptionError> {
+    pub fn reader(&self) -> Result<File, EncryptionError> {
         Ok(self.read.open(&self.path)?)
     }
     fn editor(&self) -> Result<File, EncryptionError> {
@@ -146,9 +399,6 @@ impl CryptedFile {
     pub fn encryption_layer(&self) -> Result<u32, EncryptionError> {
         Ok(self.header()?.0)
     }
-    pub fn encryption_version(&self) -> Result<u32, EncryptionError> {
-        Ok(self.header()?.1)
-    }
 
     pub fn update_content(&mut self, new_content: Vec<u8>) -> Result<(), EncryptionError> {
         let (layer, version) = self.header()?;
@@ -180,12 +430,6 @@ impl CryptedFile {
         Ok(())
     }
 
-    pub fn increment_layer(&mut self) -> Result<(), EncryptionError> {
-        let (layer, version) = self.header()?;
-        let new_header = self.generate_header(layer + 1, version)?;
-        self.update_header(new_header)?;
-        Ok(())
-    }
     pub fn decrement_layer(&mut self) -> Result<(), EncryptionError> {
         let (layer, version) = self.header()?;
         let new_header = self.generate_header(layer - 1, version)?;
@@ -207,35 +451,20 @@ impl CryptedFile {
     //     Ok(())
     // }
 
-    pub fn header(&self) -> Result<(u32, u32), EncryptionError> {
-        let content_as_bytes = self.content_as_bytes()?;
-
-        if content_as_bytes.len() < HEADER_SIZE + VERSION_SIZE + LAYER_SIZE
-            || &content_as_bytes[0..HEADER_SIZE] != HEADER_MARKER
-        {
-            return Ok((0, latest_encryption_version()?));
-        } else {
-            let version_bytes = &content_as_bytes[HEADER_SIZE..HEADER_SIZE + VERSION_SIZE];
-            let layer_bytes = &content_as_bytes
-                [HEADER_SIZE + VERSION_SIZE..HEADER_SIZE + VERSION_SIZE + LAYER_SIZE];
-
-            let version = u32::from_be_bytes(version_bytes.try_into().unwrap());
-            let layer = u32::from_be_bytes(layer_bytes.try_into().unwrap());
+    pub fn format(&self) -> Result<FileFormat, EncryptionError> {
+        FileFormat::read(&mut self.reader()?)
+    }
 
-            Ok((layer, version))
+    pub fn header(&self) -> Result<(u32, u32), EncryptionError> {
+        match self.format()? {
+            FileFormat::Plain => Ok((0, latest_encryption_version()?)),
+            FileFormat::Legacy { layer, version } => Ok((layer, version)),
+            FileFormat::Aead(header) => Ok((header.layer, header.key_version)),
         }
     }
 
     fn generate_header(&self, layer: u32, version: u32) -> Result<Vec<u8>, EncryptionError> {
-        let mut header_content = Vec::new();
-
-        let version_bytes: [u8; VERSION_SIZE] = version.to_be_bytes();
-        let layer_bytes: [u8; LAYER_SIZE] = layer.to_be_bytes();
-        header_content.extend_from_slice(&HEADER_MARKER);
-        header_content.extend_from_slice(&version_bytes);
-        header_content.extend_from_slice(&layer_bytes);
-
-        Ok(header_content)
+        Ok(legacy_header(layer, version))
     }
 
     pub fn main_file_content(&self) -> Result<Vec<u8>, EncryptionError> {
@@ -245,6 +474,11 @@ impl CryptedFile {
             return Err(EncryptionError::CannotProcessVoidFile);
         }
 
+        if content_as_bytes.starts_with(&AEAD_HEADER_MARKER) {
+            let header = AeadHeader::from_bytes(&content_as_bytes)?;
+            return Ok(content_as_bytes[header.size()..].to_vec());
+        }
+
         if &content_as_bytes[0..HEADER_SIZE] != HEADER_MARKER {
             return Ok(content_as_bytes.to_vec());
         }
@@ -261,3 +495,128 @@ impl CryptedFile {
         Ok(file_content.to_vec())
     }
 }
+
+/// v1 header of a file with `layer` simplecrypt layers made with the key `version`
+pub fn legacy_header(layer: u32, version: u32) -> Vec<u8> {
+    let mut header_content = Vec::new();
+
+    let version_bytes: [u8; VERSION_SIZE] = version.to_be_bytes();
+    let layer_bytes: [u8; LAYER_SIZE] = layer.to_be_bytes();
+    header_content.extend_from_slice(&HEADER_MARKER);
+    header_content.extend_from_slice(&version_bytes);
+    header_content.extend_from_slice(&layer_bytes);
+
+    header_content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipient(seed: u8) -> RecipientStanza {
+        RecipientStanza {
+            ephemeral_public: [seed; 32],
+            nonce: [seed + 1; NONCE_SIZE],
+            wrapped_key: [seed + 2; 32 + TAG_SIZE],
+        }
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let header = AeadHeader::new(3, 2);
+        let bytes = header.to_bytes();
+
+        assert_eq!(bytes.len(), header.size());
+        assert_eq!(AeadHeader::from_bytes(&bytes).unwrap(), header);
+    }
+
+    #[test]
+    fn recipient_header_round_trip() {
+        let header = AeadHeader::new(0, 1).with_recipients(vec![recipient(1), recipient(4)]);
+        let bytes = header.to_bytes();
+
+        assert_eq!(bytes.len(), header.size());
+        assert_eq!(AeadHeader::from_bytes(&bytes).unwrap(), header);
+    }
+
+    #[test]
+    fn header_is_parsed_from_its_fields() {
+        let mut bytes = AEAD_HEADER_MARKER.to_vec();
+        bytes.extend_from_slice(&[
+            FORMAT_VERSION,
+            ALGORITHM_XCHACHA20_POLY1305,
+            KDF_HKDF_SHA256,
+            0,
+        ]);
+        bytes.extend_from_slice(&5u32.to_be_bytes());
+        bytes.extend_from_slice(&2u32.to_be_bytes());
+        bytes.extend_from_slice(&[1; SALT_SIZE]);
+        bytes.extend_from_slice(&[2; NONCE_SIZE]);
+        bytes.extend_from_slice(&CHUNK_SIZE.to_be_bytes());
+
+        let header = AeadHeader::from_bytes(&bytes).unwrap();
+        assert_eq!(header.key_version, 5);
+        assert_eq!(header.layer, 2);
+        assert_eq!(header.salt, [1; SALT_SIZE]);
+        assert_eq!(header.nonce, [2; NONCE_SIZE]);
+        assert_eq!(header.chunk_size, CHUNK_SIZE);
+        assert!(header.recipients.is_empty());
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected() {
+        let bytes = AeadHeader::new(0, 1).to_bytes();
+
+        assert!(matches!(
+            AeadHeader::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(EncryptionError::TruncatedFile)
+        ));
+
+        let mut unknown_format = bytes.clone();
+        unknown_format[HEADER_SIZE] = FORMAT_VERSION + 1;
+        assert!(matches!(
+            AeadHeader::from_bytes(&unknown_format),
+            Err(EncryptionError::UnsupportedFormat { .. })
+        ));
+
+        let mut empty_chunks = bytes.clone();
+        let chunk_size_offset = bytes.len() - CHUNK_SIZE_SIZE;
+        empty_chunks[chunk_size_offset..].copy_from_slice(&0u32.to_be_bytes());
+        assert!(matches!(
+            AeadHeader::from_bytes(&empty_chunks),
+            Err(EncryptionError::InvalidFileContent)
+        ));
+
+        let no_recipient = AeadHeader::new(0, 1).with_recipients(vec![]).to_bytes();
+        assert!(matches!(
+            AeadHeader::from_bytes(&no_recipient),
+            Err(EncryptionError::InvalidFileContent)
+        ));
+    }
+
+    #[test]
+    fn format_is_read_from_the_header() {
+        let header = AeadHeader::new(1, 1);
+        let mut content = header.to_bytes();
+        content.extend_from_slice(b"payload");
+        assert!(matches!(
+            FileFormat::read(&mut content.as_slice()).unwrap(),
+            FileFormat::Aead(read_header) if read_header == header
+        ));
+
+        let mut content = legacy_header(2, 4);
+        content.extend_from_slice(b"payload");
+        assert!(matches!(
+            FileFormat::read(&mut content.as_slice()).unwrap(),
+            FileFormat::Legacy {
+                layer: 2,
+                version: 4
+            }
+        ));
+
+        assert!(matches!(
+            FileFormat::read(&mut &b"plain text"[..]).unwrap(),
+            FileFormat::Plain
+        ));
+    }
+}
@@ -1,7 +1,4 @@
-use std::{
-    fmt::{Debug, Display},
-    io,
-};
+use std::{fmt::Debug, io};
 
 use custom_error::custom_error;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
@@ -19,28 +16,10 @@ custom_error! {pub LineError
 pub struct Line<T> {
     pub key: String,
     pub value: T,
-    // struct for lines that possess a struct key=value
+    // line of the former mucli_config.txt, read once to migrate it
 }
 
-impl<T: DeserializeOwned + Serialize> Display for Line<T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}={}",
-            self.key,
-            serde_json::to_string(&self.value).unwrap()
-        )
-    }
-}
-
-impl<T: for<'a> Deserialize<'a> + Serialize + DeserializeOwned> Line<T> {
-    pub fn new(key: &str, value: T) -> Line<T> {
-        Self {
-            key: key.to_string(),
-            value,
-        }
-    }
-
+impl<T: DeserializeOwned> Line<T> {
     pub fn from(string: &str) -> Result<Line<T>, LineError> {
         let mut parts = string.splitn(2, '=');
         let key = parts
@@ -57,20 +36,3 @@ impl<T: for<'a> Deserialize<'a> + Serialize + DeserializeOwned> Line<T> {
         Ok(Self { key, value })
     }
 }
-impl<T: Serialize> Line<T> {
-    pub fn format(&self) -> Result<String, LineError> {
-        let value: String =
-            serde_json::to_string(&self.value).map_err(|_| LineError::FormatError)?;
-
-        Ok(format!("{}={}", self.key, value))
-    }
-}
-
-impl<E> Line<Vec<E>> {
-    pub fn add(&mut self, new_element: E) {
-        self.value.push(new_element);
-    }
-    pub fn set_new(&mut self, new_vec: Vec<E>) {
-        self.value = new_vec;
-    }
-}
@@ -1,16 +1,27 @@
-pub mod config_interact;
+pub mod bytes;
+pub mod clipboard;
+pub mod config;
+pub mod editor;
 pub mod file;
 pub mod line;
+pub mod prompt;
 pub mod terminal;
+pub mod walk;
 
 extern crate custom_error;
-use std::{io::Error, num::ParseIntError, path::PathBuf};
+use std::{env, io::Error, num::ParseIntError, path::PathBuf, sync::OnceLock};
 
 use custom_error::custom_error;
 use rand::RngCore;
 
 use self::line::LineError;
 
+const MUCLI_HOME_VAR: &str = "MUCLI_HOME";
+const CONFIG_FILE_NAME: &str = "config.toml";
+const PROFILES_DIR_NAME: &str = "profiles";
+
+static CONFIG_LOCATION: OnceLock<ConfigLocation> = OnceLock::new();
+
 custom_error! {pub GenericError
     Io{source: Error} = "{source}",
     Line{source: LineError} = "{source}",
@@ -20,6 +31,10 @@ custom_error! {pub GenericError
     KeyNotFound{key: String} = "Key \"{key}\" not found in config file.",
     Unknown = "unknown error",
     Custom{message: String} = "{message}",
+    ConfigSyntax{source: toml::de::Error} = "Invalid config file: {source}",
+    ConfigFormat{source: toml::ser::Error} = "Cannot write config file: {source}",
+    ConfigVersion{version: u32, path: String} = "Config \"{path}\" was written by a newer mucli (version {version})",
+    InvalidProfile{name: String} = "Invalid profile name \"{name}\", only letters, digits, '-' and '_' are allowed",
 }
 
 pub fn generate_encryption_key(length: usize) -> Vec<u8> {
@@ -28,10 +43,64 @@ pub fn generate_encryption_key(length: usize) -> Vec<u8> {
     key
 }
 
+/// Config chosen on the command line, set once before any command runs
+#[derive(Debug, Default)]
+pub struct ConfigLocation {
+    pub file: Option<PathBuf>,
+    pub profile: Option<String>,
+}
+
+pub fn set_config_location(location: ConfigLocation) -> Result<(), GenericError> {
+    if let Some(profile) = &location.profile {
+        // the profile name becomes a directory name
+        if profile.is_empty()
+            || !profile
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(GenericError::InvalidProfile {
+                name: profile.to_string(),
+            });
+        }
+    }
+
+    CONFIG_LOCATION
+        .set(location)
+        .map_err(|_| GenericError::Custom {
+            message: "Config location already set".to_string(),
+        })
+}
+
+/// `--config` wins over `MUCLI_HOME`, which replaces the default mucli config dir,
+/// profiles live in their own directory under it
 pub fn get_config_path() -> Result<PathBuf, GenericError> {
-    let home_dir = dirs::home_dir().ok_or(GenericError::Custom {
-        message: "Cannot access home dir".to_string(),
-    })?;
-    let config_path = home_dir.join("mucli_config.txt");
-    Ok(config_path)
+    let location = CONFIG_LOCATION.get();
+    if let Some(file) = location.and_then(|location| location.file.clone()) {
+        return Ok(file);
+    }
+
+    let home = match env::var_os(MUCLI_HOME_VAR) {
+        Some(home) if !home.is_empty() => PathBuf::from(home),
+        _ => dirs::config_dir()
+            .ok_or(GenericError::Custom {
+                message: "Cannot access config dir".to_string(),
+            })?
+            .join("mucli"),
+    };
+
+    match location.and_then(|location| location.profile.as_ref()) {
+        Some(profile) => Ok(home
+            .join(PROFILES_DIR_NAME)
+            .join(profile)
+            .join(CONFIG_FILE_NAME)),
+        None => Ok(home.join(CONFIG_FILE_NAME)),
+    }
+}
+
+/// Whether neither `--config`, `--profile` nor `MUCLI_HOME` moved the config
+pub fn is_default_config() -> bool {
+    let location_set = CONFIG_LOCATION
+        .get()
+        .is_some_and(|location| location.file.is_some() || location.profile.is_some());
+    !location_set && env::var_os(MUCLI_HOME_VAR).is_none_or(|home| home.is_empty())
 }
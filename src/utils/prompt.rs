@@ -0,0 +1,198 @@
+use std::{
+    collections::VecDeque,
+    env, fs,
+    io::{self, IsTerminal, Read},
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+};
+
+use custom_error::custom_error;
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, Password};
+
+use super::GenericError;
+
+const PASSWORD_VAR: &str = "MUCLI_PASSWORD";
+const NEW_PASSWORD_VAR: &str = "MUCLI_NEW_PASSWORD";
+
+static PROMPT_INPUT: OnceLock<PromptInput> = OnceLock::new();
+// lines of the password input not used yet, read on first use
+static INPUT_LINES: Mutex<Option<VecDeque<String>>> = Mutex::new(None);
+
+custom_error! {pub PromptError
+    Io{source: io::Error} = "{source}",
+    NoTerminal{prompt: String} = "Cannot ask \"{prompt}\" without a terminal, use --password-stdin, --password-file or MUCLI_PASSWORD",
+    NoAnswer{prompt: String} = "Cannot ask \"{prompt}\" without a terminal, use --password-stdin or --password-file",
+    NoConfirmation{prompt: String} = "Cannot ask \"{prompt}\" without a terminal, use --yes",
+    InputExhausted{prompt: String} = "The password input has no line left for \"{prompt}\"",
+}
+
+/// Answers given on the command line instead of typed in, set once before any command runs
+#[derive(Debug, Default)]
+pub struct PromptInput {
+    // one answer per line, in the order mucli asks for them
+    pub stdin: bool,
+    pub file: Option<PathBuf>,
+    // confirmations are answered yes
+    pub yes: bool,
+}
+
+pub fn set_prompt_input(input: PromptInput) -> Result<(), GenericError> {
+    PROMPT_INPUT.set(input).map_err(|_| GenericError::Custom {
+        message: "Prompt input already set".to_string(),
+    })
+}
+
+/// Whether questions are typed in, scripts give answers in a fixed order instead
+pub fn is_interactive() -> bool {
+    !has_input_lines() && has_terminal()
+}
+
+/// Whether confirmations are answered yes without asking
+pub fn assume_yes() -> bool {
+    PROMPT_INPUT.get().is_some_and(|input| input.yes)
+}
+
+/// Asks for a known password, taken from the password input or `MUCLI_PASSWORD` first
+pub fn password(prompt: &str) -> Result<String, PromptError> {
+    if let Some(line) = next_line(prompt)? {
+        return Ok(line);
+    }
+    if let Some(password) = env_password(PASSWORD_VAR) {
+        return Ok(password);
+    }
+
+    if !has_terminal() {
+        return Err(PromptError::NoTerminal {
+            prompt: prompt.to_string(),
+        });
+    }
+    Ok(Password::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .interact()?)
+}
+
+/// Asks for a password to set, typed twice. `MUCLI_NEW_PASSWORD` is used
+/// before `MUCLI_PASSWORD`, so that both passwords of a change can be given
+pub fn new_password(prompt: &str, confirmation: &str) -> Result<String, PromptError> {
+    if let Some(line) = next_line(prompt)? {
+        return Ok(line);
+    }
+    if let Some(password) = env_password(NEW_PASSWORD_VAR).or_else(|| env_password(PASSWORD_VAR)) {
+        return Ok(password);
+    }
+
+    if !has_terminal() {
+        return Err(PromptError::NoTerminal {
+            prompt: prompt.to_string(),
+        });
+    }
+    Ok(Password::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .with_confirmation(confirmation, "Passwords don't match")
+        .interact()?)
+}
+
+/// Whether `new_password` falls back on `MUCLI_PASSWORD`, `MUCLI_NEW_PASSWORD` being unset
+pub fn new_password_from_password_var() -> bool {
+    !has_input_lines()
+        && env_password(NEW_PASSWORD_VAR).is_none()
+        && env_password(PASSWORD_VAR).is_some()
+}
+
+/// Asks to type again a password given on the command line, not with `--yes`
+pub fn retype_password(prompt: &str, password: &str) -> Result<(), PromptError> {
+    if assume_yes() {
+        return Ok(());
+    }
+
+    if !has_terminal() {
+        return Err(PromptError::NoConfirmation {
+            prompt: prompt.to_string(),
+        });
+    }
+    Password::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .validate_with(|input: &String| -> Result<(), &str> {
+            if input != password {
+                return Err("Passwords don't match");
+            }
+            Ok(())
+        })
+        .interact()?;
+    Ok(())
+}
+
+/// Asks for a plain text answer, taken from the password input first
+pub fn answer(prompt: &str) -> Result<String, PromptError> {
+    if let Some(line) = next_line(prompt)? {
+        return Ok(line);
+    }
+
+    if !has_terminal() {
+        return Err(PromptError::NoAnswer {
+            prompt: prompt.to_string(),
+        });
+    }
+    Ok(Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .interact_text()?)
+}
+
+/// Asks a yes or no question, always yes with `--yes`
+pub fn confirm(prompt: &str) -> Result<bool, PromptError> {
+    if assume_yes() {
+        return Ok(true);
+    }
+
+    if !has_terminal() {
+        return Err(PromptError::NoConfirmation {
+            prompt: prompt.to_string(),
+        });
+    }
+    Ok(Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .interact()?)
+}
+
+/// Whether menus and prompts can be shown
+pub fn has_terminal() -> bool {
+    io::stdin().is_terminal() && io::stderr().is_terminal()
+}
+
+fn has_input_lines() -> bool {
+    PROMPT_INPUT
+        .get()
+        .is_some_and(|input| input.stdin || input.file.is_some())
+}
+
+fn env_password(var: &str) -> Option<String> {
+    env::var(var).ok().filter(|password| !password.is_empty())
+}
+
+// None when answers are not given through the password input
+fn next_line(prompt: &str) -> Result<Option<String>, PromptError> {
+    let input = match PROMPT_INPUT.get() {
+        Some(input) if input.stdin || input.file.is_some() => input,
+        _ => return Ok(None),
+    };
+
+    let mut lines = INPUT_LINES.lock().unwrap();
+    if lines.is_none() {
+        let content = match &input.file {
+            Some(file) => fs::read_to_string(file)?,
+            None => {
+                let mut content = String::new();
+                io::stdin().read_to_string(&mut content)?;
+                content
+            }
+        };
+        *lines = Some(content.lines().map(|line| line.to_string()).collect());
+    }
+
+    match lines.as_mut().and_then(|lines| lines.pop_front()) {
+        Some(line) => Ok(Some(line)),
+        None => Err(PromptError::InputExhausted {
+            prompt: prompt.to_string(),
+        }),
+    }
+}
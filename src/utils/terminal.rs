@@ -40,3 +40,8 @@ macro_rules! print_info {
     ($fmt:literal) => (println!("\x1B[38;5;57minfo\x1B[0m: {}", $fmt));
     ($fmt:literal, $($arg:expr),*) => (println!("\x1B[38;5;57minfo\x1B[0m: {}", format_args!($fmt, $($arg),*)));
 }
+#[macro_export]
+macro_rules! print_warn {
+    ($fmt:literal) => (println!("\x1B[1;33mwarning\x1B[0m: {}", $fmt));
+    ($fmt:literal, $($arg:expr),*) => (println!("\x1B[1;33mwarning\x1B[0m: {}", format_args!($fmt, $($arg),*)));
+}
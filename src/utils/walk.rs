@@ -0,0 +1,86 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use glob::Pattern;
+
+use super::GenericError;
+
+/// Include/exclude glob patterns, matched against paths relative to the walked root
+/// as well as against bare file names, so that `.git` or `*.log` work at any depth
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, GenericError> {
+        let compile = |patterns: &[String]| -> Result<Vec<Pattern>, GenericError> {
+            patterns
+                .iter()
+                .map(|p| {
+                    Pattern::new(p).map_err(|e| GenericError::Custom {
+                        message: format!("Invalid pattern \"{}\": {}", p, e),
+                    })
+                })
+                .collect()
+        };
+
+        Ok(PathFilter {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    fn matches(patterns: &[Pattern], relative_path: &Path) -> bool {
+        let path = relative_path.to_string_lossy().replace('\\', "/");
+        let name = relative_path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        patterns
+            .iter()
+            .any(|pattern| pattern.matches(&path) || pattern.matches(&name))
+    }
+
+    pub fn is_excluded(&self, relative_path: &Path) -> bool {
+        Self::matches(&self.exclude, relative_path)
+    }
+
+    pub fn is_included(&self, relative_path: &Path) -> bool {
+        if self.is_excluded(relative_path) {
+            return false;
+        }
+        self.include.is_empty() || Self::matches(&self.include, relative_path)
+    }
+}
+
+/// Lists every file under `root` accepted by `filter`, excluded directories are not entered
+/// and symlinks are not followed
+pub fn walk_files(root: &Path, filter: &PathFilter) -> Result<Vec<PathBuf>, GenericError> {
+    let mut files = vec![];
+    let mut path_queue = vec![root.to_path_buf()];
+
+    while let Some(dir) = path_queue.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let entry_path = entry.path();
+            let relative_path = entry_path.strip_prefix(root).unwrap_or(&entry_path);
+
+            if file_type.is_dir() {
+                if !filter.is_excluded(relative_path) {
+                    path_queue.push(entry_path);
+                }
+            } else if file_type.is_file() && filter.is_included(relative_path) {
+                files.push(entry_path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
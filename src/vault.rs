@@ -0,0 +1,499 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::{self, BufRead, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+
+use clap::ArgMatches;
+use custom_error::custom_error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tempfile::Builder;
+
+use crate::{
+    encryption::{
+        store::{read_sealed, update_sealed},
+        EncryptionError,
+    },
+    password::unlock_gate,
+    print_err, print_info, print_solution, print_success,
+    utils::{
+        clipboard::{self, ClipboardError},
+        editor::{edit_file, EditorError},
+        get_config_path,
+        prompt::{self, PromptError},
+        GenericError,
+    },
+};
+
+custom_error! {pub VaultError
+    Io{source: io::Error} = "{source}",
+    Generic{source: GenericError} = "{source}",
+    Encryption{source: EncryptionError} = "{source}",
+    Prompt{source: PromptError} = "{source}",
+    Clipboard{source: ClipboardError} = "{source}",
+    Editor{source: EditorError} = "{source}",
+    InvalidEntry{source: toml::de::Error} = "Invalid entry, nothing was saved: {source}",
+    EntryFormat{source: toml::ser::Error} = "Cannot write entry: {source}",
+    UnsupportedVault{version: u32} = "The vault was written by a newer mucli (version {version})",
+    InvalidName{name: String} = "Invalid entry name \"{name}\", spaces and control characters are not allowed",
+    InvalidField{field: String} = "Invalid field \"{field}\", expected KEY=VALUE",
+    EntryExists{name: String} = "\"{name}\" is already in the vault",
+    EntryNotFound{name: String} = "\"{name}\" is not in the vault",
+    FieldNotFound{name: String, field: String, fields: String} = "\"{name}\" has no field \"{field}\", it has: {fields}",
+    MissingSecret{name: String, field: String} = "\"{name}\" has no \"{field}\" field"
+}
+
+const VAULT_VERSION: u32 = 1;
+pub const DEFAULT_CLEAR_SECONDS: &str = "20";
+
+/// What an entry holds, its main field is named after it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    Password,
+    Token,
+    Note,
+}
+
+impl EntryKind {
+    pub const NAMES: [&'static str; 3] = ["password", "token", "note"];
+
+    pub fn from_name(name: &str) -> Option<EntryKind> {
+        match name {
+            "password" => Some(EntryKind::Password),
+            "token" => Some(EntryKind::Token),
+            "note" => Some(EntryKind::Note),
+            _ => None,
+        }
+    }
+
+    /// Field printed or copied when none is asked for
+    pub fn main_field(&self) -> &'static str {
+        match self {
+            EntryKind::Password => "password",
+            EntryKind::Token => "token",
+            EntryKind::Note => "note",
+        }
+    }
+}
+
+/// Named secret of the vault, structured entries have more fields than the main one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultEntry {
+    pub kind: EntryKind,
+    pub fields: BTreeMap<String, String>,
+    #[serde(default)]
+    pub updated_at: u64,
+}
+
+impl VaultEntry {
+    pub fn new(kind: EntryKind, fields: BTreeMap<String, String>) -> Self {
+        VaultEntry {
+            kind,
+            fields,
+            updated_at: unix_time(),
+        }
+    }
+}
+
+// the whole vault is a single file sealed with the latest encryption key,
+// entry names included
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Vault {
+    version: u32,
+    entries: BTreeMap<String, VaultEntry>,
+}
+
+// written to the edited file, only the fields can change
+#[derive(Debug, Serialize, Deserialize)]
+struct EditedEntry {
+    kind: EntryKind,
+    fields: BTreeMap<String, String>,
+}
+
+pub fn vault_command(sub_matches: &ArgMatches) {
+    match sub_matches.subcommand() {
+        Some(("add", sub_matches)) => add_command(sub_matches),
+        Some(("get", sub_matches)) => get_command(sub_matches),
+        Some(("list", _)) => list_command(),
+        Some(("rm", sub_matches)) => remove_command(sub_matches),
+        Some(("edit", sub_matches)) => edit_command(sub_matches),
+        Some(("clear-clipboard", sub_matches)) => clear_clipboard_command(sub_matches),
+        _ => (),
+    }
+}
+
+fn add_command(sub_matches: &ArgMatches) {
+    let name = match sub_matches.get_one::<String>("NAME") {
+        Some(name) => name,
+        None => return,
+    };
+    let kind = sub_matches
+        .get_one::<String>("kind")
+        .and_then(|kind| EntryKind::from_name(kind))
+        .unwrap_or(EntryKind::Password);
+    let fields: Vec<&String> = sub_matches
+        .get_many::<String>("field")
+        .unwrap_or_default()
+        .collect();
+    let force = sub_matches.get_flag("force");
+    // replacing an entry destroys its secret, which needs the same unlock as reading it
+    if force && !unlock_gate("vault") {
+        return;
+    }
+
+    match add_entry(name, kind, &fields, force) {
+        Ok(_) => print_success!("\"{}\" added to the vault", name),
+        Err(VaultError::EntryExists { name }) => {
+            print_err!("\"{}\" is already in the vault", name);
+            print_solution!("Use \"vault edit {}\" or --force to replace it", name);
+        }
+        Err(e) => print_err!("(vault add failed): {}", e),
+    }
+}
+
+fn get_command(sub_matches: &ArgMatches) {
+    if !unlock_gate("vault") {
+        return;
+    }
+    let name = match sub_matches.get_one::<String>("NAME") {
+        Some(name) => name,
+        None => return,
+    };
+    let field = sub_matches.get_one::<String>("field");
+
+    let value = match read_entry(name).and_then(|entry| entry_field(name, &entry, field)) {
+        Ok(value) => value,
+        Err(e) => {
+            print_err!("{}", e);
+            return;
+        }
+    };
+
+    match sub_matches.get_one::<u64>("clip") {
+        Some(seconds) => match copy_for(&value, *seconds) {
+            Ok(_) => print_success!(
+                "\"{}\" copied to the clipboard, cleared in {}s",
+                name,
+                seconds
+            ),
+            Err(e) => print_err!("{}", e),
+        },
+        None => println!("{}", value),
+    }
+}
+
+fn list_command() {
+    let vault = match read_vault() {
+        Ok(vault) => vault,
+        Err(e) => {
+            print_err!("{}", e);
+            return;
+        }
+    };
+    if vault.entries.is_empty() {
+        print_info!("The vault is empty");
+        print_solution!("Use \"vault add <NAME>\" to store a secret");
+        return;
+    }
+
+    let width = vault
+        .entries
+        .keys()
+        .map(|name| name.len())
+        .max()
+        .unwrap_or(0);
+    for (name, entry) in &vault.entries {
+        // only names are printed, values stay in the vault
+        let fields: Vec<&str> = entry.fields.keys().map(|field| field.as_str()).collect();
+        println!(
+            "{:width$}  {:8}  {}",
+            name,
+            entry.kind.main_field(),
+            fields.join(", "),
+            width = width
+        );
+    }
+}
+
+fn remove_command(sub_matches: &ArgMatches) {
+    if !unlock_gate("vault") {
+        return;
+    }
+    let name = match sub_matches.get_one::<String>("NAME") {
+        Some(name) => name,
+        None => return,
+    };
+
+    match prompt::confirm(&format!("Remove \"{}\" from the vault?", name)) {
+        Ok(true) => (),
+        Ok(false) => return,
+        Err(e) => {
+            print_err!("{}", e);
+            return;
+        }
+    }
+
+    match remove_entry(name) {
+        Ok(_) => print_success!("\"{}\" removed from the vault", name),
+        Err(e) => print_err!("{}", e),
+    }
+}
+
+fn edit_command(sub_matches: &ArgMatches) {
+    if !unlock_gate("vault") {
+        return;
+    }
+    let name = match sub_matches.get_one::<String>("NAME") {
+        Some(name) => name,
+        None => return,
+    };
+
+    match edit_entry(name) {
+        Ok(true) => print_success!("\"{}\" updated", name),
+        Ok(false) => print_info!("\"{}\" was not changed", name),
+        Err(e) => print_err!("(vault edit failed): {}", e),
+    }
+}
+
+// runs in the background after "vault get --clip", the clipboard is only
+// cleared when it still holds the copied secret
+fn clear_clipboard_command(sub_matches: &ArgMatches) {
+    let seconds = match sub_matches.get_one::<u64>("SECONDS") {
+        Some(seconds) => *seconds,
+        None => return,
+    };
+    let mut copied = String::new();
+    if io::stdin().lock().read_line(&mut copied).is_err() {
+        return;
+    }
+
+    thread::sleep(Duration::from_secs(seconds));
+    if let Ok(content) = clipboard::paste() {
+        if fingerprint(&content) != copied.trim() {
+            return;
+        }
+    }
+    let _ = clipboard::clear();
+}
+
+/// Stores a new entry, the main field is asked for unless given in `fields`
+pub fn add_entry(
+    name: &str,
+    kind: EntryKind,
+    fields: &[&String],
+    force: bool,
+) -> Result<(), VaultError> {
+    check_name(name)?;
+    let mut entry_fields = BTreeMap::new();
+    for field in fields {
+        let (key, value) = field
+            .split_once('=')
+            .filter(|(key, _)| !key.trim().is_empty())
+            .ok_or(VaultError::InvalidField {
+                field: field.to_string(),
+            })?;
+        entry_fields.insert(key.trim().to_string(), value.to_string());
+    }
+
+    // fail before asking for the secret
+    if !force && read_vault()?.entries.contains_key(name) {
+        return Err(VaultError::EntryExists {
+            name: name.to_string(),
+        });
+    }
+
+    let main_field = kind.main_field();
+    if !entry_fields.contains_key(main_field) {
+        let value = match kind {
+            EntryKind::Note => prompt::answer("Note")?,
+            _ => prompt::new_password(
+                &format!("Enter the {}", main_field),
+                &format!("Confirm the {}", main_field),
+            )?,
+        };
+        entry_fields.insert(main_field.to_string(), value);
+    }
+
+    store_entry(name, VaultEntry::new(kind, entry_fields), force)
+}
+
+/// Writes an entry, an existing one is only replaced with `force`
+pub fn store_entry(name: &str, entry: VaultEntry, force: bool) -> Result<(), VaultError> {
+    check_name(name)?;
+    update_vault(|vault| {
+        if !force && vault.entries.contains_key(name) {
+            return Err(VaultError::EntryExists {
+                name: name.to_string(),
+            });
+        }
+        vault.entries.insert(name.to_string(), entry);
+        Ok(())
+    })
+}
+
+pub fn read_entry(name: &str) -> Result<VaultEntry, VaultError> {
+    read_vault()?
+        .entries
+        .remove(name)
+        .ok_or(VaultError::EntryNotFound {
+            name: name.to_string(),
+        })
+}
+
+/// The main field of an entry, as "vault get" prints it
+pub fn read_secret(name: &str) -> Result<String, VaultError> {
+    entry_field(name, &read_entry(name)?, None)
+}
+
+fn remove_entry(name: &str) -> Result<(), VaultError> {
+    update_vault(|vault| match vault.entries.remove(name) {
+        Some(_) => Ok(()),
+        None => Err(VaultError::EntryNotFound {
+            name: name.to_string(),
+        }),
+    })
+}
+
+// the entry is decrypted to a private temporary file next to the vault
+// for the time of the edit only
+fn edit_entry(name: &str) -> Result<bool, VaultError> {
+    let vault_path = vault_path()?;
+    update_vault(|vault| {
+        let entry = vault
+            .entries
+            .get_mut(name)
+            .ok_or(VaultError::EntryNotFound {
+                name: name.to_string(),
+            })?;
+        let content = toml::to_string_pretty(&EditedEntry {
+            kind: entry.kind,
+            fields: entry.fields.clone(),
+        })?;
+
+        let dir = vault_path.parent().unwrap_or(&vault_path);
+        let temp_file = Builder::new().suffix(".toml").tempfile_in(dir)?;
+        fs::write(temp_file.path(), &content)?;
+        edit_file(temp_file.path())?;
+        let edited = fs::read_to_string(temp_file.path())?;
+        if edited == content {
+            return Ok(false);
+        }
+
+        let edited: EditedEntry = toml::from_str(&edited)?;
+        *entry = VaultEntry::new(edited.kind, edited.fields);
+        Ok(true)
+    })
+}
+
+fn entry_field(
+    name: &str,
+    entry: &VaultEntry,
+    field: Option<&String>,
+) -> Result<String, VaultError> {
+    let field = match field {
+        Some(field) => field.as_str(),
+        None => entry.kind.main_field(),
+    };
+    match entry.fields.get(field) {
+        Some(value) => Ok(value.to_string()),
+        None if field == entry.kind.main_field() => Err(VaultError::MissingSecret {
+            name: name.to_string(),
+            field: field.to_string(),
+        }),
+        None => Err(VaultError::FieldNotFound {
+            name: name.to_string(),
+            field: field.to_string(),
+            fields: entry
+                .fields
+                .keys()
+                .map(|field| field.as_str())
+                .collect::<Vec<&str>>()
+                .join(", "),
+        }),
+    }
+}
+
+fn copy_for(value: &str, seconds: u64) -> Result<(), VaultError> {
+    clipboard::copy(value)?;
+
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .args(["vault", "clear-clipboard", &seconds.to_string()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // out of the terminal process group, so that closing it does not keep the secret around
+    #[cfg(unix)]
+    command.process_group(0);
+
+    let mut clearer = command.spawn()?;
+    // only a fingerprint of the secret leaves this process
+    if let Some(mut stdin) = clearer.stdin.take() {
+        writeln!(stdin, "{}", fingerprint(value))?;
+    }
+    Ok(())
+}
+
+fn check_name(name: &str) -> Result<(), VaultError> {
+    if name.is_empty() || name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(VaultError::InvalidName {
+            name: name.to_string(),
+        });
+    }
+    Ok(())
+}
+
+// one vault per config, so that profiles keep their own secrets
+fn vault_path() -> Result<PathBuf, VaultError> {
+    Ok(get_config_path()?.with_extension("vault"))
+}
+
+fn read_vault() -> Result<Vault, VaultError> {
+    let vault: Vault = read_sealed(&vault_path()?)?.unwrap_or_default();
+    check_version(&vault)?;
+    Ok(vault)
+}
+
+fn update_vault<T, F>(modify: F) -> Result<T, VaultError>
+where
+    F: FnOnce(&mut Vault) -> Result<T, VaultError>,
+{
+    update_sealed(&vault_path()?, |vault: &mut Vault| {
+        check_version(vault)?;
+        let result = modify(vault)?;
+        vault.version = VAULT_VERSION;
+        Ok(result)
+    })
+}
+
+fn check_version(vault: &Vault) -> Result<(), VaultError> {
+    if vault.version > VAULT_VERSION {
+        return Err(VaultError::UnsupportedVault {
+            version: vault.version,
+        });
+    }
+    Ok(())
+}
+
+fn fingerprint(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}